@@ -1,8 +1,8 @@
 use std::{fs::File, path::{Path, PathBuf}, sync::{mpsc::{self, Receiver}, Arc, Mutex, RwLock}, time::{Duration, Instant}};
 
-use chordial::{engine::{Engine, Frame}, midi::{MidiMessage, MidiStatusByte}, node::{BusKind, Node}, param::{ParamKind, ParamValue, Parameter}};
+use chordial::{engine::Engine, feed::{audio_feed, AudioFeedWriter}, midi::{MidiMessage, SysExPool}, node::{BusKind, Node}, param::{ParamKind, ParamValue, Parameter}};
 
-use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, StreamConfig, SampleRate, SupportedBufferSize};
+use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, BuildStreamError, Device, FromSample, Host, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig, SupportedBufferSize};
 use midir::{MidiInput, MidiInputConnection};
 use wav::{Header, WAV_FORMAT_IEEE_FLOAT, BitDepth};
 
@@ -11,14 +11,16 @@ struct MidiIn {
 	connection: Option<MidiInputConnection<()>>,
 	port_name: String,
 	receiver: Option<Receiver<MidiMessage>>,
+	sysex: Arc<SysExPool>,
 }
 
 impl MidiIn {
-	fn new() -> Self {
+	fn new(sysex: Arc<SysExPool>) -> Self {
 		MidiIn {
 			connection: None,
 			port_name: String::new(),
 			receiver: None,
+			sysex,
 		}
 	}
 }
@@ -94,25 +96,24 @@ impl Node for MidiIn {
 			
 			if &name == port_name {
 				let (sender, receiver) = mpsc::channel();
+				let sysex = self.sysex.clone();
 
 				let result = midi.connect(
 					&port, 
 					&port_name,
 					move |_, msg, _| {
-						let mut bytes = [0, 0];
+						let midi_message = match msg.first() {
+							Some(0xF0) => sysex.insert(msg),
 
-						if msg.len() > 1 {
-							bytes[0] = msg[1];
-						}
+							_ => {
+								// Malformed messages are dropped
+								let Some(msg) = MidiMessage::from_bytes(msg) else {
+									return
+								};
 
-						if msg.len() > 2 {
-							bytes[1] = msg[2];
-						}
-
-						let midi_message = MidiMessage::new(
-							MidiStatusByte(msg[0]),
-							bytes
-						);
+								msg
+							}
+						};
 
 						let _ = sender.send(midi_message);
 					},
@@ -131,6 +132,66 @@ impl Node for MidiIn {
 }
 
 
+// Opens the default input device in its default format, and adds it as the "input" feed
+fn open_input_stream(host: &Host, engine: &mut Engine) -> Result<Stream, String> {
+	let device = host.default_input_device().ok_or("no default input device")?;
+	let supported = device.default_input_config().map_err(|err| err.to_string())?;
+	let config = supported.config();
+
+	let (writer, mut reader) = audio_feed(config.sample_rate.0 as usize / 2, 1024);
+
+	// The input device runs on its own clock, and possibly at its own rate
+	reader.set_sample_rates(config.sample_rate.0, engine.config.sample_rate);
+
+	let stream = match supported.sample_format() {
+		SampleFormat::F32 => build_input_stream::<f32>(&device, &config, writer),
+		SampleFormat::I16 => build_input_stream::<i16>(&device, &config, writer),
+		SampleFormat::U16 => build_input_stream::<u16>(&device, &config, writer),
+		SampleFormat::I32 => build_input_stream::<i32>(&device, &config, writer),
+
+		other => return Err(format!("unsupported input sample format {other}")),
+	}.map_err(|err| err.to_string())?;
+
+	stream.play().map_err(|err| err.to_string())?;
+
+	println!("using input device `{}` at {} Hz, {} channels",
+		device.name().unwrap_or("(could not get device name)".to_string()),
+		config.sample_rate.0,
+		config.channels,
+	);
+
+	engine.add_audio_feed("input", reader);
+
+	Ok(stream)
+}
+
+fn build_input_stream<T>(device: &Device, config: &StreamConfig, mut writer: AudioFeedWriter) -> Result<Stream, BuildStreamError>
+where
+	T: SizedSample,
+	f32: FromSample<T>,
+{
+	let channels = config.channels as usize;
+	let mut samples = vec![];
+
+	device.build_input_stream(
+		config,
+
+		move |data: &[T], _: &cpal::InputCallbackInfo| {
+			samples.clear();
+			samples.extend(data.iter().map(|sample| sample.to_sample::<f32>()));
+
+			writer.write_interleaved(&samples, channels);
+		},
+
+		move |err| {
+			eprintln!("input stream error: {err}");
+		},
+
+		None
+	)
+}
+
+
 fn main() {
 	println!("chordial audio engine - proof of concept");
 	
@@ -171,40 +232,45 @@ fn main() {
 		println!();
 	}
 
+	let channels = device
+		.default_output_config()
+		.map(|config| config.channels())
+		.unwrap_or(2);
+
 	let config = StreamConfig {
-		channels: 2,
+		channels,
 		sample_rate: SampleRate(44100),
 		buffer_size: cpal::BufferSize::Fixed(128),
 	};
 
 	let mut engine = Engine::new(config.sample_rate.0);
 
-	engine.register_node("chordial.cli.midi-in", |_| Box::new(MidiIn::new()));
+	engine.register_node("chordial.cli.midi-in", |engine| Box::new(MidiIn::new(engine.sysex.clone())));
 	engine.load(&PathBuf::from("samplertest.chrp"));
+	// The Sink keeps the project's channel layout, render_interleaved converts it to the device's
 	engine.playing = true;
 
+	// Feed the default input device into Source nodes with `input` set to "input"
+	let input_stream = match open_input_stream(&host, &mut engine) {
+		Ok(stream) => Some(stream),
+
+		Err(err) => {
+			println!("not using an input device: {err}");
+			None
+		}
+	};
+
 	let engine = Arc::new(Mutex::new(engine));
 	let thread_engine = engine.clone();
 
-	let mut buffer = vec![];
-
 	let stream = device.build_output_stream(
 		&config,
 
 		move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-			buffer.resize(data.len() / 2, Frame::ZERO);
-			thread_engine.lock().unwrap().render(&mut buffer);
-			
-			let mut out_buffer = out_buffer_thread.write().unwrap();
+			data.fill(0.0);
+			thread_engine.lock().unwrap().render_interleaved(data, channels as usize);
 
-			for (i, frame) in buffer.iter().enumerate() {
-				data[i*2] = frame.0;
-				data[i*2+1] = frame.1;
-			}
-
-			out_buffer.extend_from_slice(data);
-
-			buffer.fill(Frame::ZERO);
+			out_buffer_thread.write().unwrap().extend_from_slice(data);
 		},
 
 		move |_| {
@@ -253,10 +319,14 @@ stream opened with config:
 	
 	stream.pause().unwrap();
 
+	if let Some(input_stream) = &input_stream {
+		input_stream.pause().unwrap();
+	}
+
 	wav::write(
 		Header::new(
 			WAV_FORMAT_IEEE_FLOAT,
-			2,
+			config.channels,
 			config.sample_rate.0,
 			32,
 		), 
@@ -0,0 +1,111 @@
+// Renders a 10k note MidiClip block by block, and compares it to scanning every
+// note on every frame (how MidiClip used to schedule its events).
+// Run with `cargo bench --bench midi_clip`.
+
+use std::{hint::black_box, time::{Duration, Instant}};
+
+use chordial::{engine::{Config, Engine}, midi::{MidiBlock, MidiNoteDesc}, node::{Buffer, OutputRef, TlUnit, timeline::MidiClip}};
+
+
+const SAMPLE_RATE: u32 = 44100;
+const NOTES: usize = 10_000;
+const BLOCK: usize = 256;
+
+// About one second of audio, in whole blocks
+const FRAMES: usize = SAMPLE_RATE as usize / BLOCK * BLOCK;
+
+fn make_block() -> MidiBlock {
+	let mut channels: [Vec<MidiNoteDesc>; 16] = Default::default();
+
+	// A sixteenth note every step, spread across channels and pitches
+	for i in 0..NOTES {
+		channels[i % 16].push(MidiNoteDesc {
+			pos: TlUnit(i * 24),
+			len: TlUnit(24),
+			note: 36 + (i % 48) as u8,
+			vel: 100,
+		});
+	}
+
+	MidiBlock::from_channels(channels)
+}
+
+fn naive_scan(block: &MidiBlock, config: &Config) -> usize {
+	let mut events = 0;
+
+	for sample_pos in 0..FRAMES {
+		let tl_pos = config.frames_to_tl_units(sample_pos);
+		let prev_tl_pos = config.frames_to_tl_units(sample_pos.saturating_sub(1));
+
+		for channel in block.channels() {
+			for note in channel {
+				let note_end = note.pos + note.len;
+
+				if (tl_pos >= note.pos && (sample_pos == 0 || prev_tl_pos < note.pos))
+					|| (tl_pos >= note_end && prev_tl_pos < note_end)
+				{
+					events += 1;
+				}
+			}
+		}
+	}
+
+	events
+}
+
+fn indexed(engine: &mut Engine, clip: usize) -> usize {
+	let mut events = 0;
+
+	for pos in (0..FRAMES).step_by(BLOCK) {
+		engine.seek(pos);
+		engine.get_node_mut(clip).unwrap().clear_buffers();
+
+		let output = engine.poll_node_output(&OutputRef { node: clip, output: 0 }, BLOCK);
+
+		let Buffer::Midi(midi) = &*output else {
+			panic!()
+		};
+
+		events += midi[..BLOCK].iter().map(|chain| chain.len()).sum::<usize>();
+	}
+
+	events
+}
+
+fn measure(name: &str, runs: u32, mut f: impl FnMut() -> usize) -> Duration {
+	let start = Instant::now();
+	let mut events = 0;
+
+	for _ in 0..runs {
+		events = black_box(f());
+	}
+
+	let elapsed = start.elapsed() / runs;
+	let audio = FRAMES as f64 / SAMPLE_RATE as f64;
+
+	println!(
+		"{name:>8}: {elapsed:>12?} for {audio:.3}s of audio ({:.1}x realtime, {events} events)",
+		audio / elapsed.as_secs_f64()
+	);
+
+	elapsed
+}
+
+fn main() {
+	let mut engine = Engine::new(SAMPLE_RATE);
+	let block = engine.add_resource(make_block());
+	let clip = engine.add_node(MidiClip::new(block.clone()), "chordial.midi_clip");
+
+	println!("{NOTES} note clip, {BLOCK} frame blocks at {SAMPLE_RATE} Hz");
+
+	let naive = {
+		let data = block.inner();
+		let data = data.as_ref().unwrap().read().unwrap();
+
+		measure("naive", 1, || naive_scan(&data.data, &engine.config))
+	};
+
+	let indexed = measure("indexed", 20, || indexed(&mut engine, clip));
+
+	println!("speedup: {:.0}x", naive.as_secs_f64() / indexed.as_secs_f64());
+}
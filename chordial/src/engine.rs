@@ -1,6 +1,6 @@
-use std::{collections::{BTreeMap, HashMap}, fmt::{Debug, Write}, fs::File, io::{self, BufRead, BufReader, Read, Write as IoWrite}, ops::{Add, AddAssign, Mul, Sub}, path::{Path, PathBuf}, sync::{Arc, RwLock, RwLockReadGuard}, time::Instant};
+use std::{collections::{BTreeMap, HashMap}, fmt::{Debug, Write}, fs::{self, File}, io::{self, BufRead, BufReader, Read, Write as IoWrite}, ops::{Add, AddAssign, Mul, Sub}, path::{Path, PathBuf}, sync::{Arc, Mutex, RwLock, RwLockReadGuard}, time::Instant};
 
-use crate::{midi::MidiBlock, node::{effect::{Amplify, Gain}, io::{MidiSplit, Sink}, osc::{Osc, PolyOsc, Sine}, sampler::Sampler, timeline::MidiClip, Buffer, BufferAccess, BusKind, ControlValue, Envelope, Node, NodeInstance, OutputRef, TlUnit, Trigger}, param::ParamValue, resource::{Resource, ResourceHandle, ResourceHandleDyn, ResourceLoader, WavLoader}};
+use crate::{feed::{AudioFeedReader, SharedAudioFeed}, midi::{MidiBlock, MidiNoteDesc, SysExPool}, node::{convolution::Convolution, distortion::{ShaperCurve, Waveshaper}, dynamics::{Dynamics, DynamicsMode, Limiter}, effect::{Amplify, Gain}, eq::Equalizer, io::{MidiSplit, Sink, Source}, mixer::Mixer, modulation::{ModulatedDelay, ModulatedDelayKind, Phaser}, multichannel::{ChannelConvert, ChannelLayout, ChannelMatrix}, osc::{Osc, PolyOsc, Sine}, reverb::Reverb, sampler::{SampleNode, Sampler}, stereo::{ChannelUtility, Pan, StereoWidth}, timeline::{MidiClip, INFINITE_LENGTH}, track::TrackInput, Buffer, BufferAccess, BusKind, Connection, ControlValue, Envelope, Node, NodeInstance, OutputRef, TimelineTransform, TlUnit, Trigger}, param::ParamValue, resource::{Resource, ResourceHandle, ResourceHandleDyn, ResourceLoader, WavLoader}, smf::{read_smf, write_smf, SmfChannelMapping, SmfLoader, SmfTrack}, tempo::{MusicalPosition, Ratio, TempoMap, TempoRamp}, track::Track, transport::{Transport, TransportEvent}, tuning::{ScalaLoader, Tuning}};
 
 
 pub const STEP_DIVISIONS: u32 = 24;
@@ -53,13 +53,16 @@ impl Frame {
 
 pub struct Config {
 	pub sample_rate: u32,
-	pub bpm: f64,
-	pub tuning: f32,
+	pub tempo: TempoMap,
+	// Default tuning for pitched nodes without their own
+	pub tuning: Tuning,
 }
 
 impl Config {
-	pub fn midi_note_to_freq(&self, note: u8) -> f32 {
-		2.0f32.powf((note as f32 - 69.0) / 12.0) * self.tuning
+	// Frequency of a MIDI note in the node's own tuning if it has one, the default otherwise.
+	// None if the tuning leaves the note unmapped.
+	pub fn note_to_freq(&self, note: u8, node_tuning: Option<&Tuning>) -> Option<f64> {
+		node_tuning.unwrap_or(&self.tuning).note_to_freq(note)
 	}
 }
 
@@ -70,6 +73,8 @@ pub type ResourceLoadCtor = Arc<dyn Fn(&Path, &mut Engine, usize) -> Option<Box<
 pub struct Engine {
 	pub config: Config,
 	pub playing: bool,
+	pub transport: Transport,
+	was_playing: bool,
 	
 	nodes: BTreeMap<usize, NodeInstance>,
 	node_ctors: HashMap<&'static str, NodeCtor>,
@@ -82,12 +87,24 @@ pub struct Engine {
 
 	resource_loaders: HashMap<&'static str, ResourceLoadCtor>,
 
+	audio_feeds: HashMap<String, Mutex<SharedAudioFeed>>,
+
+	// Payloads of the SysEx messages sent to the engine, shared with the MIDI input threads
+	pub sysex: Arc<SysExPool>,
+
+	tracks: Vec<Track>,
+	track_counter: usize,
+
 	position: usize,
 	
 	pub rendering_offline: bool,
 	pub enable_buffer_readback: bool,
 	pub buffer_readback: Vec<Frame>,
 
+	// Intermediate buffers for rendering when the Sink's channel count differs from the requested one
+	render_scratch: Vec<f32>,
+	render_scratch_stereo: Vec<Frame>,
+
 	pub dbg_buffer_size: u32,
 	pub dbg_buffer_time: f32,
 	pub dbg_process_time: f32,
@@ -98,11 +115,13 @@ impl Engine {
 		let mut engine = Engine {
 			config: Config {
 				sample_rate,
-				bpm: 120.0,
-				tuning: 440.0,
+				tempo: TempoMap::default(),
+				tuning: Tuning::default(),
 			},
 
 			playing: false,
+			transport: Transport::default(),
+			was_playing: false,
 
 			nodes: BTreeMap::new(),
 			node_ctors: HashMap::new(),
@@ -115,22 +134,36 @@ impl Engine {
 
 			resource_loaders: HashMap::new(),
 
+			audio_feeds: HashMap::new(),
+			sysex: Arc::new(SysExPool::new()),
+
+			tracks: vec![],
+			track_counter: 0,
+
 			position: 0,
 
 			rendering_offline: false,
 			enable_buffer_readback: false,
 			buffer_readback: vec![],
+			render_scratch: vec![],
+			render_scratch_stereo: vec![],
 			dbg_buffer_size: 0u32,
 			dbg_buffer_time: 0f32,
 			dbg_process_time: 0f32,
 		};
 
 		engine.register_resource(|_| MidiBlock::default());
+		engine.register_resource(|_| ShaperCurve::default());
+		engine.register_resource(|_| Tuning::default());
 		
 		engine.register_resource_loader(WavLoader);
+		engine.register_resource_loader(SmfLoader::new());
+		engine.register_resource_loader(ScalaLoader);
 
 		engine.register_node("chordial.amplify", |_| Box::new(Amplify));
-		engine.register_node("chordial.sink", |_| Box::new(Sink));
+		engine.register_node("chordial.sink", |_| Box::new(Sink::new()));
+		engine.register_node("chordial.source", |_| Box::new(Source::new()));
+		engine.register_node("chordial.track_input", |_| Box::new(TrackInput::new()));
 		engine.register_node("chordial.sine", |_| Box::new(Sine::new(440.0)));
 		engine.register_node("chordial.gain", |_| Box::new(Gain { gain: 0.0 }));
 		engine.register_node("chordial.trigger", |_| Box::new(Trigger::new()));
@@ -141,44 +174,201 @@ impl Engine {
 		engine.register_node("chordial.midi_split", |_| Box::new(MidiSplit::new()));
 		engine.register_node("chordial.midi_clip", |_| Box::new(MidiClip::new(ResourceHandle::nil("MidiBlock"))));
 		engine.register_node("chordial.sampler", |_| Box::new(Sampler::new()));
+		engine.register_node("chordial.audio_clip", |_| Box::new(SampleNode::new()));
+		engine.register_node("chordial.reverb", |engine| Box::new(Reverb::new(engine.config.sample_rate)));
+		engine.register_node("chordial.convolution", |engine| Box::new(Convolution::new(engine.config.sample_rate)));
+		engine.register_node("chordial.compressor", |engine| Box::new(Dynamics::new(DynamicsMode::Compressor, engine.config.sample_rate)));
+		engine.register_node("chordial.expander", |engine| Box::new(Dynamics::new(DynamicsMode::Expander, engine.config.sample_rate)));
+		engine.register_node("chordial.gate", |engine| Box::new(Dynamics::new(DynamicsMode::Gate, engine.config.sample_rate)));
+		engine.register_node("chordial.limiter", |engine| Box::new(Limiter::new(engine.config.sample_rate)));
+		engine.register_node("chordial.waveshaper", |_| Box::new(Waveshaper::new()));
+		engine.register_node("chordial.chorus", |engine| Box::new(ModulatedDelay::new(ModulatedDelayKind::Chorus, engine.config.sample_rate)));
+		engine.register_node("chordial.flanger", |engine| Box::new(ModulatedDelay::new(ModulatedDelayKind::Flanger, engine.config.sample_rate)));
+		engine.register_node("chordial.phaser", |engine| Box::new(Phaser::new(engine.config.sample_rate)));
+		engine.register_node("chordial.eq", |engine| Box::new(Equalizer::new(engine.config.sample_rate)));
+		engine.register_node("chordial.mixer", |_| Box::new(Mixer::new()));
+		engine.register_node("chordial.pan", |_| Box::new(Pan::new()));
+		engine.register_node("chordial.stereo_width", |_| Box::new(StereoWidth::new()));
+		engine.register_node("chordial.channel_utility", |_| Box::new(ChannelUtility::new()));
+		engine.register_node("chordial.channel_convert", |_| Box::new(ChannelConvert::new()));
 
 		engine.create_node("chordial.sink");
 		engine
 	}
 
 	
+	// Makes an external audio feed available to Source nodes under `name`,
+	// replacing any feed previously registered with that name
+	pub fn add_audio_feed(&mut self, name: impl Into<String>, reader: AudioFeedReader) {
+		self.audio_feeds.insert(name.into(), Mutex::new(SharedAudioFeed::new(reader)));
+	}
+
+	pub fn remove_audio_feed(&mut self, name: &str) -> Option<AudioFeedReader> {
+		self.audio_feeds.remove(name).map(|feed| feed.into_inner().unwrap().into_reader())
+	}
+
+	pub fn get_audio_feed(&self, name: &str) -> Option<&Mutex<SharedAudioFeed>> {
+		self.audio_feeds.get(name)
+	}
+
+	pub fn audio_feeds(&self) -> impl Iterator<Item = &str> {
+		self.audio_feeds.keys().map(|name| name.as_str())
+	}
+
+	// Channel count of the Sink's input bus
+	pub fn output_channels(&self) -> usize {
+		self.nodes[&0].node.get_inputs()[0].channels().unwrap()
+	}
+
+	pub fn set_output_channels(&mut self, channels: usize) {
+		self.set_node_param(0, 0, ParamValue::Int(channels as i64));
+	}
+
+	// Renders stereo output. If the Sink is set up with a different
+	// channel count, its output is downmixed to stereo.
 	pub fn render(&mut self, buffer: &mut [Frame]) {
-		let start = Instant::now();
+		let channels = self.output_channels();
+
+		if channels == 2 {
+			self.render_sink(BufferAccess::Audio(buffer));
+		} else {
+			let mut scratch = std::mem::take(&mut self.render_scratch);
+
+			scratch.resize(buffer.len() * channels, 0.0);
+			scratch.fill(0.0);
+
+			self.render_sink(BufferAccess::Multichannel(channels, &mut scratch));
+
+			ChannelMatrix::new(ChannelLayout::from_channels(channels), ChannelLayout::Stereo)
+				.process_to_stereo(&scratch, buffer);
+
+			self.render_scratch = scratch;
+		}
+
+		self.update_readback(buffer);
+	}
+
+	// Renders interleaved output with the given channel count, converting
+	// from the Sink's channel layout if it differs
+	pub fn render_interleaved(&mut self, buffer: &mut [f32], channels: usize) {
+		let sink_channels = self.output_channels();
+		let frames = buffer.len() / channels;
+
+		if sink_channels == channels && channels != 2 {
+			self.render_sink(BufferAccess::Multichannel(channels, buffer));
 
-		if !self.playing {
-			buffer.fill(Frame::ZERO);
-			
 			if self.enable_buffer_readback {
-				self.buffer_readback.resize(buffer.len(), Frame::ZERO);
-				self.buffer_readback.fill(Frame::ZERO);
+				let mut readback = std::mem::take(&mut self.buffer_readback);
+
+				readback.resize(frames, Frame::ZERO);
+				ChannelMatrix::new(ChannelLayout::from_channels(channels), ChannelLayout::Stereo)
+					.process_to_stereo(buffer, &mut readback);
+
+				self.buffer_readback = readback;
 			}
 
 			return
 		}
 
-		let sink = &self.nodes[&0];
+		let from = ChannelLayout::from_channels(sink_channels);
+		let to = ChannelLayout::from_channels(channels);
+
+		if sink_channels == 2 {
+			let mut stereo = std::mem::take(&mut self.render_scratch_stereo);
+
+			stereo.resize(frames, Frame::ZERO);
+			stereo.fill(Frame::ZERO);
+
+			self.render_sink(BufferAccess::Audio(&mut stereo));
+			ChannelMatrix::new(from, to).process_from_stereo(&stereo, buffer);
+			self.update_readback(&stereo);
+
+			self.render_scratch_stereo = stereo;
+		} else {
+			let mut scratch = std::mem::take(&mut self.render_scratch);
+
+			scratch.resize(frames * sink_channels, 0.0);
+			scratch.fill(0.0);
+
+			self.render_sink(BufferAccess::Multichannel(sink_channels, &mut scratch));
+			ChannelMatrix::new(from, to).process(&scratch, buffer);
+
+			if self.enable_buffer_readback {
+				let mut readback = std::mem::take(&mut self.buffer_readback);
+
+				readback.resize(frames, Frame::ZERO);
+				ChannelMatrix::new(from, ChannelLayout::Stereo).process_to_stereo(&scratch, &mut readback);
+
+				self.buffer_readback = readback;
+			}
+
+			self.render_scratch = scratch;
+		}
+	}
+
+	// Renders one block, split at the loop end if playback wraps inside it
+	fn render_sink(&mut self, mut buffer: BufferAccess) {
+		let start = Instant::now();
+		let len = buffer.len();
+
+		if self.playing != self.was_playing {
+			let event = if self.playing { TransportEvent::Play } else { TransportEvent::Stop };
+
+			for node in self.nodes.values_mut() {
+				node.node.transport_changed(event, &self.config);
+			}
+
+			self.was_playing = self.playing;
+		}
 
-		sink.node.render(0, BufferAccess::Audio(buffer), sink, self);
+		if !self.playing {
+			// Feeds keep being written while stopped, playback picks them up from their latency
+			for feed in self.audio_feeds.values_mut() {
+				feed.get_mut().unwrap().reader_mut().drain();
+			}
 
-		for node in self.nodes.values_mut() {
-			node.node.advance(buffer.len(), &self.config);
-			node.clear_buffers();
+			buffer.clear();
+			return
 		}
 
-		self.position += buffer.len();
+		let mut done = 0;
+
+		while done < len {
+			let wrap = self.transport.frames_until_wrap(self.position, len - done);
+			let segment = wrap.unwrap_or(len - done);
+
+			if segment > 0 {
+				let sink = &self.nodes[&0];
+
+				sink.node.render(0, buffer.slice(done..done + segment), sink, self);
+
+				for node in self.nodes.values_mut() {
+					node.node.advance(segment, &self.config);
+					node.clear_buffers();
+				}
+
+				for feed in self.audio_feeds.values_mut() {
+					feed.get_mut().unwrap().next_block();
+				}
+
+				self.position += segment;
+				done += segment;
+			}
+
+			if wrap.is_some() {
+				self.seek(self.transport.loop_range.start);
+			}
+		}
 		
 		self.dbg_process_time = (Instant::now() - start).as_secs_f32();
-		self.dbg_buffer_time = buffer.len() as f32 / self.config.sample_rate as f32;
-		self.dbg_buffer_size = buffer.len() as u32;
+		self.dbg_buffer_time = len as f32 / self.config.sample_rate as f32;
+		self.dbg_buffer_size = len as u32;
+	}
 
+	fn update_readback(&mut self, buffer: &[Frame]) {
 		if self.enable_buffer_readback {
 			self.buffer_readback.resize(buffer.len(), Frame::ZERO);
-			self.buffer_readback.copy_from_slice(&buffer);
+			self.buffer_readback.copy_from_slice(buffer);
 		}
 	}
 
@@ -194,6 +384,20 @@ impl Engine {
 		self.position
 	}
 
+	// Nodes are notified of `playing` changes at the start of the next render
+	pub fn play(&mut self) {
+		self.playing = true;
+	}
+
+	pub fn stop(&mut self) {
+		self.playing = false;
+	}
+
+	// Whether recording should happen at the current position (see `Transport::is_punched_in()`)
+	pub fn is_punched_in(&self) -> bool {
+		self.transport.is_punched_in(self.position)
+	}
+
 	pub fn register_node(
 		&mut self, 
 		name: &'static str, 
@@ -262,6 +466,10 @@ impl Engine {
 			return
 		};
 
+		for track in &mut self.tracks {
+			track.clips.retain(|clip| *clip != node);
+		}
+
 		for other in self.nodes.values_mut() {
 			for input in &mut other.inputs {
 				input.0.retain(|input_node| input_node.node != node);
@@ -269,6 +477,148 @@ impl Engine {
 		}
 	}
 
+	// Appends a track whose clips produce `kind` (Audio or Midi), and returns its ID.
+	// Connect the track's `input_node` to the node chain it should feed.
+	pub fn add_track(&mut self, name: impl Into<String>, kind: BusKind) -> usize {
+		let input_node = self.create_node("chordial.track_input").unwrap();
+
+		while self.tracks.iter().any(|track| track.id == self.track_counter) {
+			self.track_counter += 1;
+		}
+
+		let id = self.track_counter;
+		let kind = match kind {
+			BusKind::Midi => 0,
+			BusKind::Audio => 1,
+
+			other => panic!("unsupported track kind: {other:?}")
+		};
+
+		self.set_node_param(input_node, 0, ParamValue::Int(id as i64));
+		self.set_node_param(input_node, 1, ParamValue::Int(kind));
+		self.tracks.push(Track::new(id, name.into(), input_node));
+
+		id
+	}
+
+	// Removes a track along with its input node and clips
+	pub fn remove_track(&mut self, track: usize) {
+		let Some(idx) = self.tracks.iter().position(|t| t.id == track) else {
+			return
+		};
+
+		let track = self.tracks.remove(idx);
+
+		for clip in track.clips {
+			self.delete_node(clip);
+		}
+
+		self.delete_node(track.input_node);
+	}
+
+	// Tracks in arrangement order
+	pub fn tracks(&self) -> &[Track] {
+		&self.tracks
+	}
+
+	pub fn get_track(&self, track: usize) -> Option<&Track> {
+		self.tracks.iter().find(|t| t.id == track)
+	}
+
+	pub fn get_track_mut(&mut self, track: usize) -> Option<&mut Track> {
+		self.tracks.iter_mut().find(|t| t.id == track)
+	}
+
+	pub fn move_track(&mut self, track: usize, index: usize) {
+		let Some(idx) = self.tracks.iter().position(|t| t.id == track) else {
+			return
+		};
+
+		let track = self.tracks.remove(idx);
+		self.tracks.insert(index.min(self.tracks.len()), track);
+	}
+
+	// Moves a timeline node onto a track, removing it from any other track
+	pub fn add_clip_to_track(&mut self, track: usize, clip: usize) {
+		assert!(
+			self.get_node(clip).is_some_and(|node| node.is_timeline_node()),
+			"only timeline nodes can be placed on tracks!"
+		);
+
+		for t in &mut self.tracks {
+			t.clips.retain(|c| *c != clip);
+		}
+
+		self.get_track_mut(track).unwrap().clips.push(clip);
+	}
+
+	pub fn remove_clip_from_track(&mut self, track: usize, clip: usize) {
+		if let Some(track) = self.get_track_mut(track) {
+			track.clips.retain(|c| *c != clip);
+		}
+	}
+
+	pub fn get_clip_track(&self, clip: usize) -> Option<usize> {
+		self.tracks.iter().find(|t| t.clips.contains(&clip)).map(|t| t.id)
+	}
+
+	pub fn set_track_mute(&mut self, track: usize, mute: bool) {
+		if let Some(track) = self.get_track_mut(track) {
+			track.mute = mute;
+		}
+	}
+
+	pub fn set_track_solo(&mut self, track: usize, solo: bool) {
+		if let Some(track) = self.get_track_mut(track) {
+			track.solo = solo;
+		}
+	}
+
+	// False if the track is muted, or if any other track is soloed and this one isn't
+	pub fn is_track_audible(&self, track: usize) -> bool {
+		let Some(track) = self.get_track(track) else {
+			return false
+		};
+
+		let any_solo = self.tracks.iter().any(|t| t.solo);
+
+		!track.mute && (track.solo || !any_solo)
+	}
+
+	// Sets a node parameter, and if that changed the node's port layout, disconnects
+	// everything attached to removed inputs or outputs. Returns the dropped connections.
+	pub fn set_node_param(&mut self, node: usize, param: usize, value: ParamValue) -> Vec<Connection> {
+		let Some(change) = self.nodes.get_mut(&node).unwrap().set_param(param, value) else {
+			return vec![]
+		};
+
+		let mut removed = vec![];
+
+		for (input, refs) in change.removed_inputs {
+			for from in refs {
+				removed.push(Connection { from, to_node: node, to_input: input });
+			}
+		}
+
+		if !change.removed_outputs.is_empty() {
+			for (id, other) in self.nodes.iter_mut() {
+				for (input, (refs, _)) in other.inputs.iter_mut().enumerate() {
+					refs.retain(|from| {
+						let dropped = from.node == node && change.removed_outputs.contains(&from.output);
+
+						if dropped {
+							removed.push(Connection { from: *from, to_node: *id, to_input: input });
+						}
+
+						!dropped
+					});
+				}
+			}
+		}
+
+		removed
+	}
+
 	pub fn nodes(&self) -> impl Iterator<Item = (&usize, &NodeInstance)> {
 		self.nodes.iter()
 	}
@@ -295,15 +645,15 @@ impl Engine {
 		// Optimization: don't render Timeline Nodes outside their timeline span
 		// unless explicitly requested by the node
 		if input_node.is_timeline_node() && !input_node.node.process_outside_timeline_span() {
-			let tl_pos = self.config.frames_to_tl_units(self.position);
-			let buffer_len_tl = self.config.frames_to_tl_units(buffer_len);
-
 			let node_end = input_node.get_timeline_position().0
 				+ input_node.node.get_timeline_length(&self.config).0
 				- input_node.get_timeline_start_offset().0
 				- input_node.get_timeline_end_offset().0;
+
+			let node_start = self.config.tl_units_to_frames(input_node.get_timeline_position());
+			let node_end = self.config.tl_units_to_frames(TlUnit(node_end));
 			
-			if tl_pos + buffer_len_tl < input_node.get_timeline_position() || tl_pos > TlUnit(node_end) {
+			if self.position + buffer_len <= node_start || self.position > node_end {
 				return input_node.outputs[output_ref.output].read().unwrap()
 			}
 			
@@ -459,6 +809,82 @@ impl Engine {
 		loader(path, self, id)
 	}
 
+	// Like loading a .mid file through `load_resource`, but can also replace
+	// the project's tempo map with the tempo and time signatures of the file
+	pub fn import_smf(
+		&mut self,
+		path: &Path,
+		mapping: SmfChannelMapping,
+		use_file_tempo: bool
+	) -> Option<ResourceHandle<MidiBlock>> {
+		let import = read_smf(&fs::read(path).ok()?, mapping)?;
+
+		if use_file_tempo {
+			if let Some(tempo) = import.tempo {
+				self.config.tempo = tempo;
+			}
+		}
+
+		Some(self.add_resource(import.block))
+	}
+
+	// Writes every timeline node with MIDI notes to a multi-track file, with a file track
+	// per chordial track and one for each clip outside a track. Endlessly looping clips
+	// stop where the last other clip ends.
+	pub fn export_smf(&self, path: &Path) -> io::Result<()> {
+		let arrangement_end = self.nodes
+			.values()
+			.filter(|node| node.is_timeline_node())
+			.map(|node| (node.get_timeline_transform().unwrap(), node.node.get_timeline_length(&self.config)))
+			.filter(|(_, len)| *len < INFINITE_LENGTH)
+			.map(|(tf, len)| (tf.position.0 + len.0).saturating_sub(tf.start_offset.0 + tf.end_offset.0))
+			.max()
+			.unwrap_or(0);
+
+		let clip_notes = |clip: usize| {
+			let node = self.get_node(clip)?;
+			let TimelineTransform { position, start_offset, end_offset } = *node.get_timeline_transform()?;
+			let visible_end = node.node.get_timeline_length(&self.config).0.saturating_sub(end_offset.0);
+			let notes = node.node.get_midi_notes(TlUnit(arrangement_end.saturating_sub(position.0) + start_offset.0))?;
+
+			// Notes are cut to the part of the clip its offsets leave visible, which starts at its position
+			Some(notes.into_iter().filter_map(move |(channel, note)| {
+				let (start, end) = (note.pos.0, note.pos.0 + note.len.0);
+
+				if start >= visible_end || end < start_offset.0 || (end == start_offset.0 && start < end) {
+					return None
+				}
+
+				let (start, end) = (start.max(start_offset.0), end.min(visible_end));
+
+				Some((channel, MidiNoteDesc { pos: TlUnit(position.0 + start - start_offset.0), len: TlUnit(end - start), ..note }))
+			}))
+		};
+
+		let mut tracks: Vec<_> = self.tracks
+			.iter()
+			.map(|track| SmfTrack {
+				name: Some(track.name.clone()),
+				notes: track.clips.iter().filter_map(|clip| clip_notes(*clip)).flatten().collect(),
+			})
+			.collect();
+
+		for (id, node) in &self.nodes {
+			if !node.is_timeline_node() || self.get_clip_track(*id).is_some() {
+				continue
+			}
+
+			if let Some(notes) = clip_notes(*id) {
+				tracks.push(SmfTrack {
+					name: None,
+					notes: notes.collect(),
+				});
+			}
+		}
+
+		fs::write(path, write_smf(&tracks, Some(&self.config.tempo)))
+	}
+
 	pub fn get_resources_by_kind(&self, kind: &str)
 		-> impl Iterator<Item = &Box<dyn ResourceHandleDyn>>
 	{
@@ -560,9 +986,46 @@ impl Engine {
 				writeln!(f, "meta {meta} {val}")?;
 			}
 
+			if let Some(tf) = node.get_timeline_transform() {
+				writeln!(f, "tl {} {} {}", tf.position.0, tf.start_offset.0, tf.end_offset.0)?;
+			}
+
 			writeln!(f)?;
 		}
 
+		for point in self.config.tempo.tempo_points() {
+			let ramp = match point.ramp {
+				TempoRamp::Step => "step",
+				TempoRamp::Linear => "linear",
+			};
+
+			writeln!(f, "tempo {} {} {ramp}", point.pos.0, point.bpm)?;
+		}
+
+		for sig in self.config.tempo.time_signatures() {
+			writeln!(f, "timesig {} {} {}", sig.pos.0, sig.numerator, sig.denominator)?;
+		}
+
+		if self.config.tuning != Tuning::default() {
+			let data = self.config.tuning.save();
+
+			writeln!(f, "tuning {}", data.len())?;
+
+			f.write_all(&data)?;
+
+			writeln!(f)?;
+		}
+
+		for track in &self.tracks {
+			let clips = if track.clips.is_empty() {
+				"-".to_string()
+			} else {
+				track.clips.iter().map(|clip| clip.to_string()).collect::<Vec<_>>().join(",")
+			};
+
+			writeln!(f, "track {} {} {} {} {clips} {}", track.id, track.input_node, track.mute, track.solo, track.name)?;
+		}
+
 		Ok(())
 	}
 
@@ -572,6 +1035,10 @@ impl Engine {
 		self.resources.clear();
 		self.resources_by_kind.clear();
 		self.resource_counter = 0;
+		self.tracks.clear();
+		self.track_counter = 0;
+		self.config.tempo = TempoMap::default();
+		self.config.tuning = Tuning::default();
 
 		let file = File::open(path).unwrap();
 		let mut reader = BufReader::new(file);
@@ -652,6 +1119,7 @@ impl Engine {
 					last_read = reader.read_until(b'\n', &mut buf).unwrap();
 		
 					let mut param_counter = 0;
+					let mut inputs = vec![];
 		
 					// parse inputs and parameters
 					while last_read != 0 {
@@ -666,31 +1134,22 @@ impl Engine {
 						}
 		
 						if line.starts_with("in ") {
-							let inputs = line[3..].split(" ").collect::<Vec<_>>();
-							let mut input_data = (vec![], RwLock::new(Buffer::from_bus_kind(BusKind::Control)));
-		
-							for input_node in &inputs {
+							let refs = line[3..].split(" ").map(|input_node| {
 								let input_node = input_node.split(".").collect::<Vec<_>>();
 								let [noderef, output] = input_node.as_slice() else {
 									panic!()
 								};
 							
-								input_data.0.push(OutputRef {
+								OutputRef {
 									node: noderef.parse().unwrap(),
 									output: output.parse().unwrap(),
-								});
-							}
+								}
+							}).collect();
 		
-							if input_data.0.len() > 2 {
-								input_data.1 = RwLock::new(
-									Buffer::from_bus_kind(node.node.get_inputs()[node.inputs.len()])
-								);
-							}
-		
-							node.inputs.push(input_data);
+							inputs.push(refs);
 							
 						} else if line == "in" {
-							node.inputs.push((vec![], RwLock::new(Buffer::from_bus_kind(BusKind::Control))));
+							inputs.push(vec![]);
 						
 						} else if line.starts_with("param ") {
 							node.set_param(param_counter, ParamValue::parse(&line[6..]));
@@ -711,6 +1170,17 @@ impl Engine {
 							let (key, val) = line.split_at(line.find(" ").unwrap());
 
 							node.set_metadata(key.trim().to_string(), ParamValue::parse(val.trim()));
+
+						} else if let Some(transform) = line.strip_prefix("tl ") {
+							let [position, start_offset, end_offset] = transform
+								.split_whitespace()
+								.map(|unit| TlUnit(unit.parse().unwrap()))
+								.collect::<Vec<_>>()[..]
+							else {
+								panic!("invalid timeline transform: {line}")
+							};
+
+							node.set_timeline_transform(TimelineTransform { position, start_offset, end_offset });
 						} else {
 							buf = line_raw.into_bytes();
 							break
@@ -718,10 +1188,80 @@ impl Engine {
 						
 						last_read = reader.read_until(b'\n', &mut buf).unwrap();
 					}
+
+					// Parameters can change the node's layout, so inputs are only attached once
+					// all of them are applied
+					let kinds = node.node.get_inputs();
+
+					node.inputs = inputs
+						.into_iter()
+						.enumerate()
+						.map(|(i, refs)| {
+							let kind = kinds.get(i).copied().unwrap_or(BusKind::Control);
+							(refs, RwLock::new(Buffer::from_bus_kind(kind)))
+						})
+						.collect();
 		
 					self.nodes.insert(idx, node);
 				}
 
+				"tempo" => {
+					let [pos, bpm, ramp] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+						panic!("invalid tempo point: {line}")
+					};
+
+					let ramp = match ramp {
+						"step" => TempoRamp::Step,
+						"linear" => TempoRamp::Linear,
+
+						other => panic!("invalid tempo ramp: {other}")
+					};
+
+					self.config.tempo.set_tempo(TlUnit(pos.parse().unwrap()), bpm.parse().unwrap(), ramp);
+				}
+
+				"timesig" => {
+					let [pos, numerator, denominator] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+						panic!("invalid time signature: {line}")
+					};
+
+					self.config.tempo.set_time_signature(
+						TlUnit(pos.parse().unwrap()),
+						numerator.parse().unwrap(),
+						denominator.parse().unwrap()
+					);
+				}
+
+				"tuning" => {
+					let mut data = vec![0; line.trim().parse::<usize>().unwrap()];
+
+					reader.read_exact(&mut data).unwrap();
+					self.config.tuning.load(&data);
+				}
+
+				"track" => {
+					let mut fields = line.trim().splitn(6, ' ');
+					let mut next = || fields.next().unwrap_or("");
+
+					let id = next().parse().unwrap();
+					let input_node = next().parse().unwrap();
+					let mute = next().parse().unwrap();
+					let solo = next().parse().unwrap();
+
+					let clips = match next() {
+						"-" => vec![],
+						clips => clips.split(',').map(|clip| clip.parse().unwrap()).collect(),
+					};
+
+					let mut track = Track::new(id, next().to_string(), input_node);
+
+					track.clips = clips;
+					track.mute = mute;
+					track.solo = solo;
+
+					self.tracks.push(track);
+				}
+
 				other => panic!("unrecognnized file element: {other}"),
 			}
 			
@@ -735,21 +1275,49 @@ impl Engine {
 }
 
 impl Config {
+	// Tempo at the start of the timeline
+	pub fn bpm(&self) -> f64 {
+		self.tempo.bpm_at(TlUnit(0))
+	}
+
 	pub fn secs_per_beat(&self) -> f64 {
 		1.0 / self.beats_per_sec()
 	}
 	
 	pub fn beats_per_sec(&self) -> f64 {
-		self.bpm / 60.0
+		self.bpm() / 60.0
 	}
 
+	// Exact position of a (possibly fractional) timeline position, in fractional frames
+	pub fn tl_to_frames_f64(&self, ticks: f64) -> f64 {
+		self.tempo.ticks_to_secs(ticks) * self.sample_rate as f64
+	}
+
+	pub fn frames_to_tl_f64(&self, frames: f64) -> f64 {
+		self.tempo.secs_to_ticks(frames / self.sample_rate as f64)
+	}
+
+	// First frame at or after the exact time of `timeline_unit`, so events are never
+	// emitted before their time and every timeline position maps to exactly one frame.
+	// Computed with exact fractions where the tempo is constant, tempo ramps fall back to floats.
 	pub fn tl_units_to_frames(&self, timeline_unit: TlUnit) -> usize {
-		let beat = timeline_unit.0 as f64 / (STEP_DIVISIONS * BEAT_DIVISIONS) as f64;
-		(beat * self.secs_per_beat() * self.sample_rate as f64) as usize
+		let sample_rate = Ratio::int(self.sample_rate as u128);
+
+		match self.tempo.ticks_to_secs_exact(timeline_unit).and_then(|secs| secs.checked_mul(&sample_rate)) {
+			Some(frames) => frames.ceil() as usize,
+			None => self.tl_to_frames_f64(timeline_unit.0 as f64).ceil() as usize,
+		}
 	}
 
+	// Last timeline unit at or before `frames`, the inverse of `tl_units_to_frames`
 	pub fn frames_to_tl_units(&self, frames: usize) -> TlUnit {
-		let beat = frames as f64 / self.sample_rate as f64 / self.secs_per_beat();
-		TlUnit((beat * (STEP_DIVISIONS * BEAT_DIVISIONS) as f64) as usize)
+		match self.tempo.secs_to_ticks_exact(Ratio::new(frames as u128, self.sample_rate as u128)) {
+			Some(ticks) => TlUnit(ticks.floor() as usize),
+			None => TlUnit(self.frames_to_tl_f64(frames as f64).floor() as usize),
+		}
+	}
+
+	pub fn musical_position(&self, frames: usize) -> MusicalPosition {
+		self.tempo.musical_position(self.frames_to_tl_units(frames))
 	}
 }
\ No newline at end of file
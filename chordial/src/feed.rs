@@ -0,0 +1,275 @@
+use std::sync::{atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering}, Arc};
+
+use crate::engine::Frame;
+
+
+// Largest playback speed deviation used to correct clock drift (0.2%)
+const MAX_DRIFT_CORRECTION: f64 = 0.002;
+
+// How quickly the playback speed follows the fill level error, per read
+const DRIFT_SMOOTHING: f64 = 0.05;
+
+struct FeedShared {
+	// Interleaved stereo samples stored as f32 bits
+	samples: Box<[AtomicU32]>,
+	capacity: usize,
+
+	// Total frames written and read, the difference is the fill level
+	head: AtomicUsize,
+	tail: AtomicUsize,
+
+	overruns: AtomicUsize,
+	underruns: AtomicUsize,
+	connected: AtomicBool,
+}
+
+impl FeedShared {
+	fn load(&self, frame: usize) -> Frame {
+		let idx = (frame % self.capacity) * 2;
+
+		Frame(
+			f32::from_bits(self.samples[idx].load(Ordering::Relaxed)),
+			f32::from_bits(self.samples[idx + 1].load(Ordering::Relaxed)),
+		)
+	}
+
+	fn store(&self, frame: usize, value: Frame) {
+		let idx = (frame % self.capacity) * 2;
+
+		self.samples[idx].store(value.0.to_bits(), Ordering::Relaxed);
+		self.samples[idx + 1].store(value.1.to_bits(), Ordering::Relaxed);
+	}
+}
+
+// Creates a lock-free single producer, single consumer channel for feeding audio from
+// an external clock domain (e.g. an input device) into the engine. The reader aims to
+// keep `latency` frames buffered, and slightly resamples to follow the writer's clock.
+pub fn audio_feed(capacity: usize, latency: usize) -> (AudioFeedWriter, AudioFeedReader) {
+	let capacity = capacity.max(4);
+
+	let shared = Arc::new(FeedShared {
+		samples: (0..capacity * 2).map(|_| AtomicU32::new(0)).collect(),
+		capacity,
+		head: AtomicUsize::new(0),
+		tail: AtomicUsize::new(0),
+		overruns: AtomicUsize::new(0),
+		underruns: AtomicUsize::new(0),
+		connected: AtomicBool::new(true),
+	});
+
+	(
+		AudioFeedWriter {
+			shared: shared.clone(),
+		},
+		AudioFeedReader {
+			shared,
+			latency: latency.clamp(2, capacity - 2),
+			frac: 0.0,
+			ratio: 1.0,
+			rate: 1.0,
+			primed: false,
+		},
+	)
+}
+
+pub struct AudioFeedWriter {
+	shared: Arc<FeedShared>,
+}
+
+impl AudioFeedWriter {
+	// Returns false if the feed is full and the frame was dropped
+	pub fn push(&mut self, frame: Frame) -> bool {
+		let head = self.shared.head.load(Ordering::Relaxed);
+		let tail = self.shared.tail.load(Ordering::Acquire);
+
+		if head.wrapping_sub(tail) >= self.shared.capacity {
+			self.shared.overruns.fetch_add(1, Ordering::Relaxed);
+			return false
+		}
+
+		self.shared.store(head, frame);
+		self.shared.head.store(head.wrapping_add(1), Ordering::Release);
+
+		true
+	}
+
+	// Returns the number of frames written
+	pub fn write(&mut self, frames: &[Frame]) -> usize {
+		frames.iter().take_while(|f| self.push(**f)).count()
+	}
+
+	// Writes interleaved samples. Mono is copied to both channels,
+	// channels past the second one are ignored.
+	pub fn write_interleaved(&mut self, data: &[f32], channels: usize) -> usize {
+		data
+			.chunks_exact(channels.max(1))
+			.map(|chunk| match chunk {
+				[mono] => Frame(*mono, *mono),
+				[l, r, ..] => Frame(*l, *r),
+				[] => Frame::ZERO,
+			})
+			.take_while(|f| self.push(*f))
+			.count()
+	}
+
+	pub fn is_connected(&self) -> bool {
+		self.shared.connected.load(Ordering::Relaxed)
+	}
+}
+
+impl Drop for AudioFeedWriter {
+	fn drop(&mut self) {
+		self.shared.connected.store(false, Ordering::Relaxed);
+	}
+}
+
+pub struct AudioFeedReader {
+	shared: Arc<FeedShared>,
+	latency: usize,
+
+	// Fractional read position between the frame at `tail` and the next one
+	frac: f64,
+	ratio: f64,
+
+	// Writer frames per engine frame, for writers running at another sample rate
+	rate: f64,
+
+	// Reading starts once `latency` frames are buffered, and stops again on underrun
+	primed: bool,
+}
+
+impl AudioFeedReader {
+	pub fn available(&self) -> usize {
+		let head = self.shared.head.load(Ordering::Acquire);
+		let tail = self.shared.tail.load(Ordering::Relaxed);
+
+		head.wrapping_sub(tail)
+	}
+
+	pub fn latency(&self) -> usize {
+		self.latency
+	}
+
+	// Current playback speed relative to the writer, 1.0 means no drift correction
+	pub fn ratio(&self) -> f64 {
+		self.ratio
+	}
+
+	// Resamples from a writer at `feed_rate` to the engine's rate. `latency` counts writer frames.
+	pub fn set_sample_rates(&mut self, feed_rate: u32, engine_rate: u32) {
+		self.rate = feed_rate as f64 / engine_rate as f64;
+	}
+
+	pub fn overruns(&self) -> usize {
+		self.shared.overruns.load(Ordering::Relaxed)
+	}
+
+	pub fn underruns(&self) -> usize {
+		self.shared.underruns.load(Ordering::Relaxed)
+	}
+
+	// Fills `output` with feed audio, or silence while the feed is (re)buffering
+	pub fn read(&mut self, output: &mut [Frame]) {
+		let mut available = self.available();
+
+		if !self.primed {
+			if available < self.latency {
+				output.fill(Frame::ZERO);
+				return
+			}
+
+			self.primed = true;
+			self.frac = 0.0;
+		}
+
+		let mut tail = self.shared.tail.load(Ordering::Relaxed);
+
+		for (i, out) in output.iter_mut().enumerate() {
+			// Interpolation needs the current frame and the next one
+			if available < 2 {
+				self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+				self.primed = false;
+				output[i..].fill(Frame::ZERO);
+				break
+			}
+
+			let a = self.shared.load(tail);
+			let b = self.shared.load(tail.wrapping_add(1));
+			let t = self.frac as f32;
+
+			*out = a * (1.0 - t) + b * t;
+
+			self.frac += self.ratio * self.rate;
+
+			while self.frac >= 1.0 && available >= 2 {
+				self.frac -= 1.0;
+				tail = tail.wrapping_add(1);
+				available -= 1;
+			}
+		}
+
+		self.shared.tail.store(tail, Ordering::Release);
+
+		// Speed up slightly when more than `latency` frames are buffered, slow down when fewer are
+		let error = (self.available() as f64 - self.latency as f64) / self.latency as f64;
+		let target = 1.0 + (error * MAX_DRIFT_CORRECTION).clamp(-MAX_DRIFT_CORRECTION, MAX_DRIFT_CORRECTION);
+
+		self.ratio += (target - self.ratio) * DRIFT_SMOOTHING;
+	}
+
+	// Discards everything buffered, reading starts over once `latency` frames have arrived
+	pub fn drain(&mut self) {
+		let head = self.shared.head.load(Ordering::Acquire);
+
+		self.shared.tail.store(head, Ordering::Release);
+		self.primed = false;
+		self.ratio = 1.0;
+	}
+
+	pub fn is_connected(&self) -> bool {
+		self.shared.connected.load(Ordering::Relaxed)
+	}
+}
+
+// A feed as the engine holds it. The first read in a block pulls from the feed, later
+// ones in the same block get the same frames, so every Source playing it hears all of it.
+pub struct SharedAudioFeed {
+	reader: AudioFeedReader,
+	block: Vec<Frame>,
+}
+
+impl SharedAudioFeed {
+	pub fn new(reader: AudioFeedReader) -> Self {
+		SharedAudioFeed {
+			reader,
+			block: vec![],
+		}
+	}
+
+	pub fn read(&mut self, output: &mut [Frame]) {
+		let read = self.block.len();
+
+		if read < output.len() {
+			self.block.resize(output.len(), Frame::ZERO);
+			self.reader.read(&mut self.block[read..]);
+		}
+
+		output.copy_from_slice(&self.block[..output.len()]);
+	}
+
+	pub fn next_block(&mut self) {
+		self.block.clear();
+	}
+
+	pub fn reader(&self) -> &AudioFeedReader {
+		&self.reader
+	}
+
+	pub fn reader_mut(&mut self) -> &mut AudioFeedReader {
+		&mut self.reader
+	}
+
+	pub fn into_reader(self) -> AudioFeedReader {
+		self.reader
+	}
+}
@@ -0,0 +1,155 @@
+use std::{f32::consts::TAU, ops::{Add, AddAssign, Mul, Sub}};
+
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Complex {
+	pub re: f32,
+	pub im: f32,
+}
+
+impl Complex {
+	pub const ZERO: Complex = Complex { re: 0.0, im: 0.0 };
+
+	pub fn new(re: f32, im: f32) -> Self {
+		Complex { re, im }
+	}
+
+	pub fn from_polar(mag: f32, phase: f32) -> Self {
+		Complex {
+			re: mag * phase.cos(),
+			im: mag * phase.sin(),
+		}
+	}
+
+	pub fn conj(self) -> Self {
+		Complex::new(self.re, -self.im)
+	}
+
+	pub fn norm(self) -> f32 {
+		(self.re * self.re + self.im * self.im).sqrt()
+	}
+}
+
+impl Add for Complex {
+	type Output = Complex;
+
+	fn add(self, rhs: Self) -> Self::Output {
+		Complex::new(self.re + rhs.re, self.im + rhs.im)
+	}
+}
+
+impl AddAssign for Complex {
+	fn add_assign(&mut self, rhs: Self) {
+		self.re += rhs.re;
+		self.im += rhs.im;
+	}
+}
+
+impl Sub for Complex {
+	type Output = Complex;
+
+	fn sub(self, rhs: Self) -> Self::Output {
+		Complex::new(self.re - rhs.re, self.im - rhs.im)
+	}
+}
+
+impl Mul for Complex {
+	type Output = Complex;
+
+	fn mul(self, rhs: Self) -> Self::Output {
+		Complex::new(
+			self.re * rhs.re - self.im * rhs.im,
+			self.re * rhs.im + self.im * rhs.re,
+		)
+	}
+}
+
+impl Mul<f32> for Complex {
+	type Output = Complex;
+
+	fn mul(self, rhs: f32) -> Self::Output {
+		Complex::new(self.re * rhs, self.im * rhs)
+	}
+}
+
+
+// Iterative radix-2 FFT with precomputed twiddles and bit-reversal table.
+// `size` must be a power of two.
+#[derive(Clone)]
+pub struct Fft {
+	size: usize,
+	twiddles: Vec<Complex>,
+	bit_reverse: Vec<usize>,
+}
+
+impl Fft {
+	pub fn new(size: usize) -> Self {
+		assert!(size.is_power_of_two(), "FFT size must be a power of two!");
+
+		let bits = size.trailing_zeros();
+
+		Fft {
+			size,
+			twiddles: (0..size / 2)
+				.map(|i| Complex::from_polar(1.0, -TAU * i as f32 / size as f32))
+				.collect(),
+			bit_reverse: (0..size)
+				.map(|i| if bits == 0 { 0 } else { i.reverse_bits() >> (usize::BITS - bits) })
+				.collect(),
+		}
+	}
+
+	pub fn size(&self) -> usize {
+		self.size
+	}
+
+	pub fn forward(&self, data: &mut [Complex]) {
+		self.transform(data, false);
+	}
+
+	// Inverse transform, scaled by 1/N so that `inverse(forward(x)) == x`
+	pub fn inverse(&self, data: &mut [Complex]) {
+		self.transform(data, true);
+
+		let scale = 1.0 / self.size as f32;
+
+		data.iter_mut().for_each(|c| *c = *c * scale);
+	}
+
+	fn transform(&self, data: &mut [Complex], inverse: bool) {
+		assert_eq!(data.len(), self.size);
+
+		for i in 0..self.size {
+			let j = self.bit_reverse[i];
+
+			if i < j {
+				data.swap(i, j);
+			}
+		}
+
+		let mut len = 2;
+
+		while len <= self.size {
+			let half = len / 2;
+			let stride = self.size / len;
+
+			for start in (0..self.size).step_by(len) {
+				for k in 0..half {
+					let twiddle = if inverse {
+						self.twiddles[k * stride].conj()
+					} else {
+						self.twiddles[k * stride]
+					};
+
+					let a = data[start + k];
+					let b = data[start + k + half] * twiddle;
+
+					data[start + k] = a + b;
+					data[start + k + half] = a - b;
+				}
+			}
+
+			len *= 2;
+		}
+	}
+}
@@ -1,6 +1,13 @@
 pub mod engine;
+pub mod feed;
+pub mod fft;
 pub mod midi;
 pub mod node;
 pub mod param;
 pub mod resource;
+pub mod smf;
+pub mod tempo;
+pub mod track;
+pub mod transport;
+pub mod tuning;
 pub mod util;
\ No newline at end of file
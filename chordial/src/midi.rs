@@ -1,4 +1,4 @@
-use std::{collections::HashMap, mem::size_of};
+use std::{cmp::Reverse, mem::size_of, ops::{Range, RangeInclusive}, sync::{atomic::{AtomicUsize, Ordering}, Mutex}};
 use smallvec::SmallVec;
 
 use crate::{node::TlUnit, param::ParamValue, resource::Resource};
@@ -8,7 +8,7 @@ pub type MidiMessageChain = SmallVec<[MidiMessage; 4]>;
 const MIDI_CODE_MASK   : u8 = 0xF0;
 const MIDI_CHANNEL_MASK: u8 = 0x0F;
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub struct MidiMessage {
 	data: [u8; 3],
 }
@@ -20,6 +20,62 @@ impl MidiMessage {
 		}
 	}
 
+	// Parses a complete message as received from a device. Returns None for incomplete
+	// messages and for SysEx, which has to go through a SysExPool.
+	pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+		let (&status, data) = bytes.split_first()?;
+		let code = MidiStatusByte(status).code()?;
+		let data_len = code.data_len();
+
+		if code == MidiStatusCode::SysEx || data.len() < data_len || data[..data_len].iter().any(|b| *b > 0x7F) {
+			return None
+		}
+
+		let mut result = [status, 0, 0];
+		result[1..=data_len].copy_from_slice(&data[..data_len]);
+
+		Some(MidiMessage {
+			data: result
+		})
+	}
+
+	pub fn from_kind(kind: MidiMessageKind) -> Self {
+		use MidiMessageKind::*;
+		use MidiStatusCode as Code;
+
+		let channel_msg = |code, channel: u8, a: u8, b: u8| {
+			MidiMessage::new(MidiStatusByte::new(code, channel & MIDI_CHANNEL_MASK), [a & 0x7F, b & 0x7F])
+		};
+
+		let system_msg = |code, a: u8, b: u8| MidiMessage::new(MidiStatusByte::new(code, 0), [a & 0x7F, b & 0x7F]);
+
+		match kind {
+			NoteOff { channel, note, velocity } => channel_msg(Code::NoteOff, channel, note, velocity),
+			NoteOn { channel, note, velocity } => channel_msg(Code::NoteOn, channel, note, velocity),
+			PolyPressure { channel, note, pressure } => channel_msg(Code::PolyKeyPressure, channel, note, pressure),
+			ControlChange { channel, controller, value } => channel_msg(Code::CtrlChange, channel, controller, value),
+			ProgramChange { channel, program } => channel_msg(Code::ProgramChange, channel, program, 0),
+			ChannelPressure { channel, pressure } => channel_msg(Code::ChannelPressure, channel, pressure, 0),
+
+			PitchBend { channel, value } => {
+				let value = (value.clamp(-8192, 8191) + 8192) as u16;
+				channel_msg(Code::PitchBendChange, channel, value as u8 & 0x7F, (value >> 7) as u8)
+			}
+
+			SysEx { slot } => system_msg(Code::SysEx, slot as u8 & 0x7F, (slot >> 7) as u8),
+			TimeCode { piece, value } => system_msg(Code::TimeCode, (piece << 4) | (value & 0x0F), 0),
+			SongPosition { beats } => system_msg(Code::SongPosition, beats as u8 & 0x7F, (beats >> 7) as u8),
+			SongSelect { song } => system_msg(Code::SongSelect, song, 0),
+			TuneRequest => system_msg(Code::TuneRequest, 0, 0),
+			TimingClock => system_msg(Code::TimingClock, 0, 0),
+			Start => system_msg(Code::Start, 0, 0),
+			Continue => system_msg(Code::Continue, 0, 0),
+			Stop => system_msg(Code::Stop, 0, 0),
+			ActiveSensing => system_msg(Code::ActiveSensing, 0, 0),
+			Reset => system_msg(Code::Reset, 0, 0),
+		}
+	}
+
 	pub fn status_byte(&self) -> MidiStatusByte {
 		MidiStatusByte(self.data[0])
 	}
@@ -27,56 +83,565 @@ impl MidiMessage {
 	pub fn data(&self) -> &[u8; 3] {
 		&self.data
 	}
+
+	// The status byte followed by the data bytes actually used by the message
+	pub fn bytes(&self) -> &[u8] {
+		let len = self.status_byte().code().map_or(0, |code| code.data_len());
+
+		&self.data[..=len]
+	}
+
+	pub fn kind(&self) -> Option<MidiMessageKind> {
+		use MidiMessageKind::*;
+		use MidiStatusCode as Code;
+
+		let status = self.status_byte();
+		let channel = status.channel();
+		let [_, a, b] = self.data;
+
+		let kind = match status.code()? {
+			Code::NoteOff => NoteOff { channel, note: a, velocity: b },
+			Code::NoteOn => NoteOn { channel, note: a, velocity: b },
+			Code::PolyKeyPressure => PolyPressure { channel, note: a, pressure: b },
+			Code::CtrlChange => ControlChange { channel, controller: a, value: b },
+			Code::ProgramChange => ProgramChange { channel, program: a },
+			Code::ChannelPressure => ChannelPressure { channel, pressure: a },
+			Code::PitchBendChange => PitchBend { channel, value: ((a as i16) | ((b as i16) << 7)) - 8192 },
+			Code::SysEx => SysEx { slot: a as u16 | ((b as u16) << 7) },
+			Code::TimeCode => TimeCode { piece: a >> 4, value: a & 0x0F },
+			Code::SongPosition => SongPosition { beats: a as u16 | ((b as u16) << 7) },
+			Code::SongSelect => SongSelect { song: a },
+			Code::TuneRequest => TuneRequest,
+			Code::TimingClock => TimingClock,
+			Code::Start => Start,
+			Code::Continue => Continue,
+			Code::Stop => Stop,
+			Code::ActiveSensing => ActiveSensing,
+			Code::Reset => Reset,
+		};
+
+		Some(kind)
+	}
+
+	// Note on with velocity 0 counts as a note off
+	pub fn is_note_off(&self) -> bool {
+		matches!(self.kind(), Some(MidiMessageKind::NoteOff { .. } | MidiMessageKind::NoteOn { velocity: 0, .. }))
+	}
+
+	pub fn is_note_on(&self) -> bool {
+		matches!(self.kind(), Some(MidiMessageKind::NoteOn { velocity: 1.., .. }))
+	}
+}
+
+// Typed view of a MidiMessage
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MidiMessageKind {
+	NoteOff { channel: u8, note: u8, velocity: u8 },
+	NoteOn { channel: u8, note: u8, velocity: u8 },
+	PolyPressure { channel: u8, note: u8, pressure: u8 },
+	ControlChange { channel: u8, controller: u8, value: u8 },
+	ProgramChange { channel: u8, program: u8 },
+	ChannelPressure { channel: u8, pressure: u8 },
+	// Centered at 0, from -8192 to 8191
+	PitchBend { channel: u8, value: i16 },
+
+	// The payload is kept in a SysExPool
+	SysEx { slot: u16 },
+	// MTC quarter frame
+	TimeCode { piece: u8, value: u8 },
+	// In sixteenth notes
+	SongPosition { beats: u16 },
+	SongSelect { song: u8 },
+	TuneRequest,
+
+	TimingClock,
+	Start,
+	Continue,
+	Stop,
+	ActiveSensing,
+	Reset,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct MidiStatusByte(pub u8);
 
+// Channel messages use the upper nibble of the status byte, system messages the whole byte
 #[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MidiStatusCode {
 	NoteOff         = 0b1000_0000,
 	NoteOn          = 0b1001_0000,
 	PolyKeyPressure = 0b1010_0000,
 	CtrlChange      = 0b1011_0000,
+	ProgramChange   = 0b1100_0000,
 	ChannelPressure = 0b1101_0000,
 	PitchBendChange = 0b1110_0000,
+
+	SysEx           = 0xF0,
+	TimeCode        = 0xF1,
+	SongPosition    = 0xF2,
+	SongSelect      = 0xF3,
+	TuneRequest     = 0xF6,
+
+	TimingClock     = 0xF8,
+	Start           = 0xFA,
+	Continue        = 0xFB,
+	Stop            = 0xFC,
+	ActiveSensing   = 0xFE,
+	Reset           = 0xFF,
 }
 
 impl MidiStatusCode {
-	pub fn from_u8(byte: u8) -> Self {
-		match byte {
+	// Takes a channel message code (with the channel masked out) or a system status byte.
+	// Data bytes and the undefined system bytes return None.
+	pub fn from_u8(byte: u8) -> Option<Self> {
+		let code = match byte {
 			0b1000_0000 => MidiStatusCode::NoteOff,
 			0b1001_0000 => MidiStatusCode::NoteOn,
 			0b1010_0000 => MidiStatusCode::PolyKeyPressure,
 			0b1011_0000 => MidiStatusCode::CtrlChange,
+			0b1100_0000 => MidiStatusCode::ProgramChange,
 			0b1101_0000 => MidiStatusCode::ChannelPressure,
 			0b1110_0000 => MidiStatusCode::PitchBendChange,
-			_ => panic!("unrecognized midi status code: {:#b}", byte)
+
+			0xF0 => MidiStatusCode::SysEx,
+			0xF1 => MidiStatusCode::TimeCode,
+			0xF2 => MidiStatusCode::SongPosition,
+			0xF3 => MidiStatusCode::SongSelect,
+			0xF6 => MidiStatusCode::TuneRequest,
+
+			0xF8 => MidiStatusCode::TimingClock,
+			0xFA => MidiStatusCode::Start,
+			0xFB => MidiStatusCode::Continue,
+			0xFC => MidiStatusCode::Stop,
+			0xFE => MidiStatusCode::ActiveSensing,
+			0xFF => MidiStatusCode::Reset,
+
+			_ => return None
+		};
+
+		Some(code)
+	}
+
+	pub fn is_channel_message(&self) -> bool {
+		(*self as u8) < 0xF0
+	}
+
+	pub fn is_realtime(&self) -> bool {
+		(*self as u8) >= 0xF8
+	}
+
+	// Number of data bytes following the status byte. For SysEx these are the pool slot.
+	pub fn data_len(&self) -> usize {
+		match self {
+			MidiStatusCode::ProgramChange
+			| MidiStatusCode::ChannelPressure
+			| MidiStatusCode::TimeCode
+			| MidiStatusCode::SongSelect => 1,
+
+			MidiStatusCode::NoteOff
+			| MidiStatusCode::NoteOn
+			| MidiStatusCode::PolyKeyPressure
+			| MidiStatusCode::CtrlChange
+			| MidiStatusCode::PitchBendChange
+			| MidiStatusCode::SongPosition
+			| MidiStatusCode::SysEx => 2,
+
+			_ => 0,
 		}
 	}
 }
 
 impl MidiStatusByte {
+	// The channel is ignored for system messages
 	pub fn new(code: MidiStatusCode, channel: u8) -> Self {
 		assert!(channel == (channel & MIDI_CHANNEL_MASK));
 
-		Self(code as u8 | channel)
+		if code.is_channel_message() {
+			Self(code as u8 | channel)
+		} else {
+			Self(code as u8)
+		}
 	}
 
 	pub fn from_u8(byte: u8) -> Self {
 		Self(byte)
 	}
 
-	pub fn code(&self) -> MidiStatusCode {
-		MidiStatusCode::from_u8(self.0 & MIDI_CODE_MASK)
+	pub fn code(&self) -> Option<MidiStatusCode> {
+		match self.0 {
+			0xF0.. => MidiStatusCode::from_u8(self.0),
+			_ => MidiStatusCode::from_u8(self.0 & MIDI_CODE_MASK),
+		}
 	}
 
+	pub fn is_channel_message(&self) -> bool {
+		self.code().is_some_and(|code| code.is_channel_message())
+	}
+
+	// Only meaningful for channel messages
 	pub fn channel(&self) -> u8 {
 		self.0 & MIDI_CHANNEL_MASK
 	}
 }
 
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub const SYSEX_SLOTS: usize = 256;
+
+// SysEx payloads don't fit in a MidiMessage, so they're stored here and the message
+// refers to their slot. Slots are reused round-robin, so a SysEx message has to be
+// read back before SYSEX_SLOTS newer ones arrive.
+pub struct SysExPool {
+	slots: Box<[Mutex<Vec<u8>>]>,
+	next: AtomicUsize,
+}
+
+impl SysExPool {
+	pub fn new() -> Self {
+		SysExPool {
+			slots: (0..SYSEX_SLOTS).map(|_| Mutex::new(vec![])).collect(),
+			next: AtomicUsize::new(0),
+		}
+	}
+
+	// Stores a complete SysEx message (including the F0 and F7 bytes)
+	pub fn insert(&self, data: &[u8]) -> MidiMessage {
+		let slot = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+
+		let mut stored = self.slots[slot].lock().unwrap();
+		stored.clear();
+		stored.extend_from_slice(data);
+
+		MidiMessage::from_kind(MidiMessageKind::SysEx { slot: slot as u16 })
+	}
+
+	pub fn get(&self, msg: &MidiMessage) -> Option<Vec<u8>> {
+		let Some(MidiMessageKind::SysEx { slot }) = msg.kind() else {
+			return None
+		};
+
+		Some(self.slots.get(slot as usize)?.lock().unwrap().clone())
+	}
+}
+
+impl Default for SysExPool {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+
+pub const CC_MOD_WHEEL: u8 = 1;
+pub const CC_DATA_ENTRY: u8 = 6;
+pub const CC_DATA_ENTRY_LSB: u8 = 38;
+pub const CC_SLIDE: u8 = 74;
+pub const CC_NRPN_LSB: u8 = 98;
+pub const CC_NRPN_MSB: u8 = 99;
+pub const CC_RPN_LSB: u8 = 100;
+pub const CC_RPN_MSB: u8 = 101;
+pub const CC_RESET_ALL_CONTROLLERS: u8 = 121;
+
+pub const RPN_PITCH_BEND_SENSITIVITY: u16 = 0;
+pub const RPN_MPE_CONFIGURATION: u16 = 6;
+pub const RPN_NULL: u16 = 0x3FFF;
+
+pub const DEFAULT_BEND_RANGE: f32 = 2.0;
+pub const DEFAULT_MPE_BEND_RANGE: f32 = 48.0;
+
+// Controller state of a MIDI channel. Voices follow the state of their channel,
+// and new voices start from it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MidiChannelState {
+	// Centered at 0, from -8192 to 8191
+	pub pitch_bend: i16,
+	pub mod_wheel: u8,
+	pub pressure: u8,
+	pub slide: u8,
+	// Selected Registered Parameter Number
+	pub rpn: u16,
+}
+
+impl MidiChannelState {
+	pub const fn new() -> Self {
+		MidiChannelState {
+			pitch_bend: 0,
+			mod_wheel: 0,
+			pressure: 0,
+			slide: 64,
+			rpn: RPN_NULL,
+		}
+	}
+
+	// Returns true if voices on the channel need to follow the new state
+	pub fn apply(&mut self, kind: &MidiMessageKind) -> bool {
+		match *kind {
+			MidiMessageKind::PitchBend { value, .. } => self.pitch_bend = value,
+			MidiMessageKind::ChannelPressure { pressure, .. } => self.pressure = pressure,
+
+			MidiMessageKind::ControlChange { controller, value, .. } => match controller {
+				CC_MOD_WHEEL => self.mod_wheel = value,
+				CC_SLIDE => self.slide = value,
+				CC_RESET_ALL_CONTROLLERS => *self = Self::new(),
+
+				CC_RPN_MSB => {
+					self.rpn = (self.rpn & 0x7F) | (value as u16) << 7;
+					return false
+				}
+
+				CC_RPN_LSB => {
+					self.rpn = (self.rpn & !0x7F) | value as u16;
+					return false
+				}
+
+				// We don't implement any NRPNs, deselect the RPN so data entry is ignored
+				CC_NRPN_MSB | CC_NRPN_LSB => {
+					self.rpn = RPN_NULL;
+					return false
+				}
+
+				_ => return false
+			}
+
+			_ => return false
+		}
+
+		true
+	}
+}
+
+impl Default for MidiChannelState {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn message_channel(kind: &MidiMessageKind) -> Option<u8> {
+	match *kind {
+		MidiMessageKind::PitchBend { channel, .. }
+		| MidiMessageKind::ChannelPressure { channel, .. }
+		| MidiMessageKind::ControlChange { channel, .. } => Some(channel),
+
+		_ => None
+	}
+}
+
+fn bend_to_semitones(bend: i16, range: f32) -> f32 {
+	bend as f32 / 8192.0 * range
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MpeZoneKind {
+	// Manager channel 1, members from channel 2 upwards
+	Lower,
+	// Manager channel 16, members from channel 15 downwards
+	Upper,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MpeZone {
+	// The zone is disabled with no member channels
+	pub members: u8,
+	// In semitones, for the member channels and the manager channel
+	pub bend_range: f32,
+	pub manager_bend_range: f32,
+}
+
+impl MpeZone {
+	pub const fn new(members: u8) -> Self {
+		MpeZone {
+			members,
+			bend_range: DEFAULT_MPE_BEND_RANGE,
+			manager_bend_range: DEFAULT_BEND_RANGE,
+		}
+	}
+}
+
+// MPE (MIDI Polyphonic Expression) zone layout. Notes on member channels get
+// per-note pitch bend, slide and pressure from their channel, on top of the
+// zone-wide controllers on the manager channel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MpeConfig {
+	pub lower: MpeZone,
+	pub upper: MpeZone,
+}
+
+impl MpeConfig {
+	// A lower zone over every channel, what most MPE controllers send by default
+	pub const fn new() -> Self {
+		MpeConfig {
+			lower: MpeZone::new(15),
+			upper: MpeZone::new(0),
+		}
+	}
+
+	pub fn zone(&self, kind: MpeZoneKind) -> &MpeZone {
+		match kind {
+			MpeZoneKind::Lower => &self.lower,
+			MpeZoneKind::Upper => &self.upper,
+		}
+	}
+
+	pub fn zone_mut(&mut self, kind: MpeZoneKind) -> &mut MpeZone {
+		match kind {
+			MpeZoneKind::Lower => &mut self.lower,
+			MpeZoneKind::Upper => &mut self.upper,
+		}
+	}
+
+	pub fn manager_channel(kind: MpeZoneKind) -> u8 {
+		match kind {
+			MpeZoneKind::Lower => 0,
+			MpeZoneKind::Upper => 15,
+		}
+	}
+
+	pub fn member_channels(&self, kind: MpeZoneKind) -> RangeInclusive<u8> {
+		match kind {
+			MpeZoneKind::Lower => 1..=self.lower.members,
+			MpeZoneKind::Upper => 15 - self.upper.members..=14,
+		}
+	}
+
+	// The zone `channel` is the manager or a member channel of
+	pub fn zone_of(&self, channel: u8) -> Option<MpeZoneKind> {
+		[MpeZoneKind::Lower, MpeZoneKind::Upper]
+			.into_iter()
+			.filter(|kind| self.zone(*kind).members > 0)
+			.find(|kind| {
+				channel == Self::manager_channel(*kind) || self.member_channels(*kind).contains(&channel)
+			})
+	}
+
+	// Applies an MPE Configuration Message. The new zone resets to the default
+	// bend ranges and the other zone shrinks to make room.
+	pub fn configure(&mut self, kind: MpeZoneKind, members: u8) {
+		let members = members.min(15);
+		let other = match kind {
+			MpeZoneKind::Lower => MpeZoneKind::Upper,
+			MpeZoneKind::Upper => MpeZoneKind::Lower,
+		};
+
+		*self.zone_mut(kind) = MpeZone::new(members);
+
+		if members > 0 {
+			let other = self.zone_mut(other);
+			other.members = other.members.min(14u8.saturating_sub(members));
+		}
+	}
+}
+
+impl Default for MpeConfig {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+// Controller state of every channel, shared by the voice trackers
+#[derive(Debug, Clone)]
+pub struct MidiChannels {
+	pub states: [MidiChannelState; 16],
+	// In semitones, for channels outside MPE zones
+	pub bend_range: f32,
+	// Set in MPE mode
+	pub mpe: Option<MpeConfig>,
+}
+
+impl MidiChannels {
+	pub fn new() -> Self {
+		MidiChannels {
+			states: [MidiChannelState::new(); 16],
+			bend_range: DEFAULT_BEND_RANGE,
+			mpe: None,
+		}
+	}
+
+	// Returns true if voices need to follow the new channel state
+	pub fn apply(&mut self, kind: &MidiMessageKind) -> bool {
+		let Some(channel) = message_channel(kind) else {
+			return false
+		};
+
+		let state = &mut self.states[channel as usize];
+
+		let MidiMessageKind::ControlChange { controller: controller @ (CC_DATA_ENTRY | CC_DATA_ENTRY_LSB), value, .. } = *kind else {
+			return state.apply(kind)
+		};
+
+		let rpn = state.rpn;
+
+		// Outside MPE zones the bend range comes from the instrument, so only
+		// MPE configuration and per-zone bend sensitivity are handled here
+		let Some(mpe) = &mut self.mpe else {
+			return false
+		};
+
+		match (rpn, controller) {
+			(RPN_MPE_CONFIGURATION, CC_DATA_ENTRY) => match channel {
+				0 => mpe.configure(MpeZoneKind::Lower, value),
+				15 => mpe.configure(MpeZoneKind::Upper, value),
+
+				_ => return false
+			}
+
+			(RPN_PITCH_BEND_SENSITIVITY, _) => {
+				let Some(zone_kind) = mpe.zone_of(channel) else {
+					return false
+				};
+
+				let is_manager = channel == MpeConfig::manager_channel(zone_kind);
+				let zone = mpe.zone_mut(zone_kind);
+
+				// Sensitivity sent to any member channel applies to the whole zone
+				let range = if is_manager { &mut zone.manager_bend_range } else { &mut zone.bend_range };
+
+				if controller == CC_DATA_ENTRY {
+					*range = value as f32;
+				} else {
+					*range = range.trunc() + value as f32 / 100.0;
+				}
+			}
+
+			_ => return false
+		}
+
+		true
+	}
+
+	pub fn update_voice(&self, voice: &mut MidiVoiceDesc) {
+		let state = &self.states[voice.channel as usize];
+		let zone = self.mpe.as_ref().and_then(|mpe| Some((mpe, mpe.zone_of(voice.channel)?)));
+
+		let Some((mpe, kind)) = zone else {
+			voice.pitch_bend = bend_to_semitones(state.pitch_bend, self.bend_range);
+			voice.modulation = state.mod_wheel as f32 / 127.0;
+			voice.pressure = state.pressure as f32 / 127.0;
+			voice.slide = state.slide as f32 / 127.0;
+			return
+		};
+
+		let zone = mpe.zone(kind);
+		let manager_channel = MpeConfig::manager_channel(kind);
+		let manager = &self.states[manager_channel as usize];
+
+		voice.pitch_bend = bend_to_semitones(manager.pitch_bend, zone.manager_bend_range);
+
+		if voice.channel != manager_channel {
+			voice.pitch_bend += bend_to_semitones(state.pitch_bend, zone.bend_range);
+		}
+
+		voice.modulation = state.mod_wheel.max(manager.mod_wheel) as f32 / 127.0;
+		voice.pressure = state.pressure.max(manager.pressure) as f32 / 127.0;
+		voice.slide = state.slide as f32 / 127.0;
+	}
+}
+
+impl Default for MidiChannels {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct MidiVoiceDesc {
 	pub note: u8,
 	pub channel: u8,
@@ -84,19 +649,109 @@ pub struct MidiVoiceDesc {
 	pub progress: u32,
 	pub release_point: u32,
 	pub released: bool,
+
+	// Pitch bend in semitones, the rest in [0, 1]
+	pub pitch_bend: f32,
+	pub modulation: f32,
+	pub pressure: f32,
+	pub slide: f32,
+
+	// Oscillator phase or sample position, advanced by the instrument
+	pub phase: f64,
+
+	// Portamento, an offset in semitones fading out over `glide_length` frames
+	pub glide_from: f32,
+	pub glide_start: u32,
+	pub glide_length: u32,
+}
+
+impl MidiVoiceDesc {
+	pub fn new(channel: u8, note: u8, velocity: u8, channels: &MidiChannels) -> Self {
+		let mut voice = MidiVoiceDesc {
+			note,
+			channel,
+			velocity,
+			progress: 0,
+			release_point: 0,
+			released: false,
+			pitch_bend: 0.0,
+			modulation: 0.0,
+			pressure: 0.0,
+			slide: 0.0,
+			phase: 0.0,
+			glide_from: 0.0,
+			glide_start: 0,
+			glide_length: 0,
+		};
+
+		channels.update_voice(&mut voice);
+		voice
+	}
+
+	pub fn glide_offset(&self) -> f32 {
+		if self.glide_length == 0 {
+			return 0.0
+		}
+
+		let t = self.progress.saturating_sub(self.glide_start) as f32 / self.glide_length as f32;
+
+		self.glide_from * (1.0 - t).max(0.0)
+	}
+
+	// Note number including pitch bend and glide
+	pub fn pitch(&self) -> f32 {
+		self.note as f32 + self.pitch_bend + self.glide_offset()
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VoiceStealing {
+	Oldest,
+	Quietest,
+	Lowest,
+	Highest,
+	// The oldest released voice, or the oldest voice if none are released
+	ReleasedFirst,
+}
+
+impl VoiceStealing {
+	pub fn from_index(idx: i64) -> Self {
+		match idx {
+			0 => VoiceStealing::Oldest,
+			1 => VoiceStealing::Quietest,
+			2 => VoiceStealing::Lowest,
+			3 => VoiceStealing::Highest,
+			4 => VoiceStealing::ReleasedFirst,
+
+			// Comes straight from a parameter, so unknown policies aren't fatal
+			_ => VoiceStealing::Oldest
+		}
+	}
 }
 
 pub struct MonoVoiceTracker {
 	pub voice: Option<MidiVoiceDesc>,
 	pub release_length: u32,
 	pub zero_crossing: bool,
+	pub channels: MidiChannels,
+	// Glide into new notes without restarting the voice while one is held
+	pub legato: bool,
+	// In frames, 0 disables portamento
+	pub glide_time: u32,
+	// Held notes as (channel, note, velocity), the last one sounds
+	held: SmallVec<[(u8, u8, u8); 8]>,
 }
 
 pub struct PolyVoiceTracker {
-	pub voices: HashMap<(u8, u8), MidiVoiceDesc>,
+	// Oldest voice first
+	pub voices: Vec<MidiVoiceDesc>,
     pub polyphony: u8,
 	pub release_length: u32,
 	pub zero_crossing: bool,
+	pub channels: MidiChannels,
+	pub stealing: VoiceStealing,
+	// Restart the voice already playing a note instead of starting another one
+	pub retrigger: bool,
 }
 
 impl MonoVoiceTracker {
@@ -105,6 +760,10 @@ impl MonoVoiceTracker {
 			voice: None,
 			release_length: 0,
 			zero_crossing: true,
+			channels: MidiChannels::new(),
+			legato: false,
+			glide_time: 0,
+			held: SmallVec::new(),
 		}
 	}
 
@@ -115,45 +774,70 @@ impl MonoVoiceTracker {
     }
 
     pub fn apply_midi_message(&mut self, msg: MidiMessage, buffer_progress: u32) {
-        let status = msg.status_byte();
-		let channel = status.channel();
+		let Some(kind) = msg.kind() else {
+			return
+		};
 
-		match status.code() {
-			MidiStatusCode::NoteOn => {
-				let desc = MidiVoiceDesc {
-					channel,
-					note: msg.data[1],
-					velocity: msg.data[2],
-					progress: 0,
-					released: false,
-					release_point: 0,
-				};
+		match kind {
+			MidiMessageKind::NoteOn { channel, note, velocity: velocity @ 1.. } => {
+				self.held.retain(|(c, n, _)| (*c, *n) != (channel, note));
+				self.held.push((channel, note, velocity));
+				self.play_note(channel, note, velocity);
+			}
 
-				if desc.velocity != 0 {
-					self.voice = Some(desc);
-				} else {
-					self.release_voice(channel, msg.data[1], buffer_progress);
-				}
+			MidiMessageKind::NoteOn { channel, note, .. } | MidiMessageKind::NoteOff { channel, note, .. } => {
+				self.release_voice(channel, note, buffer_progress);
 			}
 
-			MidiStatusCode::NoteOff => {
-				self.release_voice(channel, msg.data[1], buffer_progress);
+			MidiMessageKind::PolyPressure { channel, note, pressure } => {
+				if let Some(voice) = self.voice.as_mut().filter(|v| v.channel == channel && v.note == note) {
+					voice.pressure = pressure as f32 / 127.0;
+				}
 			}
 
-			_ => { }
+			kind => {
+				if let (true, Some(voice)) = (self.channels.apply(&kind), &mut self.voice) {
+					self.channels.update_voice(voice);
+				}
+			}
 		}
     }
 
+	fn play_note(&mut self, channel: u8, note: u8, velocity: u8) {
+		let mut voice = MidiVoiceDesc::new(channel, note, velocity, &self.channels);
+
+		if let Some(prev) = &self.voice {
+			if self.legato && !prev.released {
+				voice.velocity = prev.velocity;
+				voice.progress = prev.progress;
+				voice.phase = prev.phase;
+			}
+
+			if self.glide_time > 0 {
+				voice.glide_from = prev.note as f32 + prev.glide_offset() - note as f32;
+				voice.glide_start = voice.progress;
+				voice.glide_length = self.glide_time;
+			}
+		}
+
+		self.voice = Some(voice);
+	}
+
 	pub fn release_voice(&mut self, channel: u8, note: u8, buffer_progress: u32) {
+		self.held.retain(|(c, n, _)| (*c, *n) != (channel, note));
+
 		let Some(active) = &mut self.voice else {
 			return
 		};
 
-		if active.note != note || active.channel != channel {
+		if active.note != note || active.channel != channel || active.released {
 			return
 		}
 
-		if self.release_length == 0 {
+		// Fall back to the last note still held
+		if let Some(&(channel, note, velocity)) = self.held.last() {
+			self.play_note(channel, note, velocity);
+		} else if self.release_length == 0 {
 			self.voice = None;
 		} else {
 			active.released = true;
@@ -161,6 +845,14 @@ impl MonoVoiceTracker {
 		}
 	}
 
+	pub fn release_all_voices(&mut self, buffer_progress: u32) {
+		self.held.clear();
+
+		if let Some((channel, note)) = self.voice.as_ref().map(|voice| (voice.channel, voice.note)) {
+			self.release_voice(channel, note, buffer_progress);
+		}
+	}
+
 	pub fn advance(&mut self, samples: u32) {
 		let Some(note) = &mut self.voice else {
 			return
@@ -184,10 +876,13 @@ impl MonoVoiceTracker {
 impl PolyVoiceTracker {
 	pub fn new() -> Self {
 		PolyVoiceTracker {
-			voices: HashMap::new(),
+			voices: Vec::new(),
 			polyphony: 0,
 			release_length: 0,
 			zero_crossing: true,
+			channels: MidiChannels::new(),
+			stealing: VoiceStealing::Oldest,
+			retrigger: true,
 		}
 	}
 
@@ -198,46 +893,87 @@ impl PolyVoiceTracker {
     }
 
     pub fn apply_midi_message(&mut self, msg: MidiMessage, buffer_progress: u32) {
-        let status = msg.status_byte();
-		let channel = status.channel();
 		let polyphony = self.polyphony as usize;
 
 		if self.voices.capacity() < polyphony {
 			self.voices.reserve(polyphony - self.voices.len());
 		}
 
-		match status.code() {
-			MidiStatusCode::NoteOn => {
-				let desc = MidiVoiceDesc {
-					note: msg.data[1],
-					channel,
-					velocity: msg.data[2],
-					progress: 0,
-					released: false,
-					release_point: 0,
-				};
+		let Some(kind) = msg.kind() else {
+			return
+		};
 
-				if desc.velocity != 0 {
-					if self.voices.len() < polyphony || polyphony == 0 {
-						self.voices.insert((desc.channel, desc.note), desc);
-					}
-				} else {
-					self.release_voice(channel, msg.data[1], buffer_progress);
-				}
+		match kind {
+			MidiMessageKind::NoteOn { channel, note, velocity: velocity @ 1.. } => {
+				self.note_on(channel, note, velocity);
 			}
 
-			MidiStatusCode::NoteOff => {
-				self.release_voice(channel, msg.data[1], buffer_progress)
+			MidiMessageKind::NoteOn { channel, note, .. } | MidiMessageKind::NoteOff { channel, note, .. } => {
+				self.release_voice(channel, note, buffer_progress)
 			}
 
-			_ => {
-				
+			MidiMessageKind::PolyPressure { channel, note, pressure } => {
+				for voice in self.voices.iter_mut().filter(|v| v.channel == channel && v.note == note) {
+					voice.pressure = pressure as f32 / 127.0;
+				}
+			}
+
+			// Manager channel and configuration messages can affect any voice
+			kind => {
+				if self.channels.apply(&kind) {
+					for voice in &mut self.voices {
+						self.channels.update_voice(voice);
+					}
+				}
 			}
 		}
     }
 
+	fn note_on(&mut self, channel: u8, note: u8, velocity: u8) {
+		if self.retrigger {
+			if let Some(voice) = self.voices.iter_mut().find(|v| v.channel == channel && v.note == note) {
+				// Restarts the note from the top, as a new voice would
+				voice.velocity = velocity;
+				voice.progress = 0;
+				voice.released = false;
+				voice.release_point = 0;
+				voice.phase = 0.0;
+				return
+			}
+		}
+
+		let polyphony = self.polyphony as usize;
+
+		while polyphony != 0 && self.voices.len() >= polyphony {
+			let victim = self.voice_to_steal();
+			self.voices.remove(victim);
+		}
+
+		self.voices.push(MidiVoiceDesc::new(channel, note, velocity, &self.channels));
+	}
+
+	// Index of the voice to make room for a new one, ties go to the oldest voice
+	fn voice_to_steal(&self) -> usize {
+		let voices = self.voices.iter().enumerate();
+
+		let victim = match self.stealing {
+			VoiceStealing::Oldest => Some((0, &self.voices[0])),
+			VoiceStealing::Quietest => voices.min_by_key(|(_, v)| v.velocity),
+			VoiceStealing::Lowest => voices.min_by_key(|(_, v)| v.note),
+			VoiceStealing::Highest => voices.min_by_key(|(_, v)| Reverse(v.note)),
+			VoiceStealing::ReleasedFirst => voices.min_by_key(|(_, v)| !v.released),
+		};
+
+		victim.map(|(idx, _)| idx).unwrap_or(0)
+	}
+
+	// The oldest voice playing `note` on `channel`
+	pub fn voice(&self, channel: u8, note: u8) -> Option<&MidiVoiceDesc> {
+		self.voices.iter().find(|v| v.channel == channel && v.note == note)
+	}
+
 	pub fn advance(&mut self, samples: u32) {
-		for note in self.voices.values_mut() {
+		for note in &mut self.voices {
 			note.progress += samples;
 		}
 
@@ -245,16 +981,22 @@ impl PolyVoiceTracker {
 	}
 
 	pub fn purge_dead_voices(&mut self) {
-		self.voices.retain(|_, v| !v.released || v.progress - v.release_point < self.release_length);
+		self.voices.retain(|v| !v.released || v.progress - v.release_point < self.release_length);
 	}
 
+	// Releases the oldest held voice playing `note` on `channel`
 	pub fn release_voice(&mut self, channel: u8, note: u8, buffer_progress: u32) {
+		let Some(idx) = self.voices
+			.iter()
+			.position(|v| v.channel == channel && v.note == note && !v.released)
+		else {
+			return
+		};
+
 		if self.release_length == 0 {
-			self.voices.remove(&(channel, note));
+			self.voices.remove(idx);
 		} else {
-			let Some(voice) = self.voices.get_mut(&(channel, note)) else {
-				return
-			};
+			let voice = &mut self.voices[idx];
 
 			voice.release_point = voice.progress + buffer_progress;
 			voice.released = true;
@@ -264,6 +1006,18 @@ impl PolyVoiceTracker {
 	pub fn kill_all_voices(&mut self) {
 		self.voices.clear();
 	}
+
+	pub fn release_all_voices(&mut self, buffer_progress: u32) {
+		if self.release_length == 0 {
+			self.voices.clear();
+			return
+		}
+
+		for voice in self.voices.iter_mut().filter(|voice| !voice.released) {
+			voice.release_point = voice.progress + buffer_progress;
+			voice.released = true;
+		}
+	}
 }
 
 #[derive(Copy, Clone)]
@@ -274,9 +1028,137 @@ pub struct MidiNoteDesc {
 	pub vel: u8
 }
 
+// A note start or end in a MidiBlock, `note` is the index into its channel
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MidiBlockEvent {
+	pub pos: TlUnit,
+	pub channel: u8,
+	pub note: u32,
+	pub is_end: bool,
+}
+
+// Notes per MIDI channel, along with an index of their starts and ends for playback.
+// Notes are only edited through the methods below, which keep the index in sync.
 #[derive(Clone, Default)]
 pub struct MidiBlock {
-	pub channels: [Vec<MidiNoteDesc>; 16],
+	channels: [Vec<MidiNoteDesc>; 16],
+
+	// Every note start and end sorted by position, note ends first at the same position
+	index: Vec<MidiBlockEvent>,
+}
+
+impl MidiBlock {
+	pub fn from_channels(channels: [Vec<MidiNoteDesc>; 16]) -> Self {
+		let mut block = MidiBlock {
+			channels,
+			index: vec![],
+		};
+
+		block.rebuild_index();
+		block
+	}
+
+	fn event_key(event: &MidiBlockEvent) -> (TlUnit, bool, u8, u32) {
+		(event.pos, !event.is_end, event.channel, event.note)
+	}
+
+	// The start of a note, and its end unless it's zero length (those never end)
+	fn note_events(channel: usize, idx: usize, note: &MidiNoteDesc) -> impl Iterator<Item = MidiBlockEvent> {
+		let start = MidiBlockEvent {
+			pos: note.pos,
+			channel: channel as u8,
+			note: idx as u32,
+			is_end: false,
+		};
+
+		let end = MidiBlockEvent {
+			pos: note.pos + note.len,
+			is_end: true,
+			..start
+		};
+
+		std::iter::once(start).chain((note.len.0 > 0).then_some(end))
+	}
+
+	fn rebuild_index(&mut self) {
+		self.index.clear();
+
+		for (channel, notes) in self.channels.iter().enumerate() {
+			for (idx, note) in notes.iter().enumerate() {
+				self.index.extend(Self::note_events(channel, idx, note));
+			}
+		}
+
+		self.index.sort_by_key(Self::event_key);
+	}
+
+	fn insert_events(&mut self, channel: usize, idx: usize) {
+		for event in Self::note_events(channel, idx, &self.channels[channel][idx]) {
+			let pos = self.index.partition_point(|e| Self::event_key(e) < Self::event_key(&event));
+			self.index.insert(pos, event);
+		}
+	}
+
+	fn remove_events(&mut self, channel: usize, idx: usize) {
+		self.index.retain(|event| (event.channel as usize, event.note as usize) != (channel, idx));
+	}
+
+	pub fn channels(&self) -> &[Vec<MidiNoteDesc>; 16] {
+		&self.channels
+	}
+
+	pub fn channel(&self, channel: usize) -> &[MidiNoteDesc] {
+		&self.channels[channel]
+	}
+
+	// Returns the note's index in its channel
+	pub fn add_note(&mut self, channel: usize, note: MidiNoteDesc) -> usize {
+		self.channels[channel].push(note);
+
+		let idx = self.channels[channel].len() - 1;
+		self.insert_events(channel, idx);
+
+		idx
+	}
+
+	pub fn update_note(&mut self, channel: usize, idx: usize, note: MidiNoteDesc) {
+		self.remove_events(channel, idx);
+		self.channels[channel][idx] = note;
+		self.insert_events(channel, idx);
+	}
+
+	// Later notes of the channel move down an index
+	pub fn remove_note(&mut self, channel: usize, idx: usize) -> MidiNoteDesc {
+		self.remove_events(channel, idx);
+
+		for event in &mut self.index {
+			if event.channel as usize == channel && event.note as usize > idx {
+				event.note -= 1;
+			}
+		}
+
+		self.channels[channel].remove(idx)
+	}
+
+	pub fn events(&self) -> &[MidiBlockEvent] {
+		&self.index
+	}
+
+	pub fn events_in(&self, range: Range<TlUnit>) -> &[MidiBlockEvent] {
+		let start = self.index.partition_point(|event| event.pos < range.start);
+		let end = self.index.partition_point(|event| event.pos < range.end);
+
+		&self.index[start..end.max(start)]
+	}
+
+	pub fn get_note(&self, event: &MidiBlockEvent) -> &MidiNoteDesc {
+		&self.channels[event.channel as usize][event.note as usize]
+	}
+
+	// Position of the last note start or end
+	pub fn end(&self) -> TlUnit {
+		self.index.last().map_or(TlUnit(0), |event| event.pos)
+	}
 }
 
 impl Resource for MidiBlock {
@@ -303,7 +1185,7 @@ impl Resource for MidiBlock {
 					panic!()
 				};
 
-				self.channels[channel].push(MidiNoteDesc {
+				self.add_note(channel, MidiNoteDesc {
 					pos: TlUnit(*pos as usize),
 					len: TlUnit(*len as usize),
 					note: *note as u8,
@@ -322,12 +1204,12 @@ impl Resource for MidiBlock {
 					panic!()
 				};
 				
-				let note = &mut self.channels[channel][*idx as usize];
-
-				note.pos = TlUnit(*pos as usize);
-				note.len = TlUnit(*len as usize);
-				note.note = *value as u8;
-				note.vel = *vel as u8;
+				self.update_note(channel, *idx as usize, MidiNoteDesc {
+					pos: TlUnit(*pos as usize),
+					len: TlUnit(*len as usize),
+					note: *value as u8,
+					vel: *vel as u8,
+				});
 			}
 			
 			"remove_note" => {
@@ -335,7 +1217,7 @@ impl Resource for MidiBlock {
 					panic!()
 				};
 
-				self.channels[channel].remove(*idx as usize);
+				self.remove_note(channel, *idx as usize);
 			}
 
 			_ => panic!()
@@ -442,5 +1324,7 @@ impl Resource for MidiBlock {
 				});
 			}
 		}
+
+		self.rebuild_index();
 	}
 }
\ No newline at end of file
@@ -1,12 +1,22 @@
-use std::{collections::HashMap, fmt::{Debug, Display}, ops::Add, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, RwLock, RwLockReadGuard}};
+use std::{collections::HashMap, fmt::{Debug, Display}, ops::{Add, Range}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, RwLock, RwLockReadGuard}};
 
-use crate::{engine::{Config, Engine, Frame}, midi::MidiMessageChain, param::{ParamKind, ParamValue, Parameter}, resource::ResourceHandleDyn, util::{inverse_lerp, lerp}};
+use crate::{engine::{Config, Engine, Frame}, midi::{MidiMessageChain, MidiNoteDesc}, node::multichannel::{ChannelLayout, ChannelMatrix}, param::{ParamKind, ParamValue, Parameter}, resource::ResourceHandleDyn, transport::TransportEvent, util::{inverse_lerp, lerp}};
 
+pub mod convolution;
+pub mod distortion;
+pub mod dynamics;
 pub mod effect;
+pub mod eq;
 pub mod io;
+pub mod mixer;
+pub mod modulation;
+pub mod multichannel;
 pub mod osc;
+pub mod reverb;
 pub mod sampler;
+pub mod stereo;
 pub mod timeline;
+pub mod track;
 
 pub trait Node: Send {
 	fn get_inputs(&self) -> &[BusKind] { &[] }
@@ -23,6 +33,16 @@ pub trait Node: Send {
 
 	fn get_params(&self) -> &[Parameter] { &[] }
 
+	// Nodes whose inputs, outputs or parameters depend on their state return true
+	// here once after such a change (usually from `param_updated()`), which makes
+	// the owning NodeInstance rebuild its buffers.
+	fn take_layout_changed(&mut self) -> bool { false }
+
+	// Read-only queries for computed node state (such as a filter's frequency response),
+	// keyed the same way as `Resource::get()`
+	#[allow(unused_variables)]
+	fn query(&self, keys: &[ParamValue]) -> Option<ParamValue> { None }
+
 	fn get_name(&self) -> &'static str;
 
 	fn render(
@@ -39,6 +59,10 @@ pub trait Node: Send {
 	#[allow(unused_variables)]
 	fn seek(&mut self, position: usize, config: &Config) { }
 
+	// Called when playback starts or stops, before the next render
+	#[allow(unused_variables)]
+	fn transport_changed(&mut self, event: TransportEvent, config: &Config) { }
+
 
 	// Resources
 	//
@@ -72,6 +96,13 @@ pub trait Node: Send {
 	fn process_outside_timeline_span(&self) -> bool {
 		true
 	}
+
+	// Notes played by a timeline node as (channel, note) relative to its timeline position,
+	// used for MIDI file export. Endlessly repeating content stops at `until`.
+	#[allow(unused_variables)]
+	fn get_midi_notes(&self, until: TlUnit) -> Option<Vec<(u8, MidiNoteDesc)>> {
+		None
+	}
 }
 
 
@@ -105,109 +136,159 @@ impl<T: Node> NodeUtil for T {
 			return None
 		};
 
-		if refs.0.len() < 2 {
-			let [output_ref] = instance.inputs[input].0.as_slice() else {
-				return None
-			};
+		let kind = instance.input_kinds.get(input).copied();
 
-			Some(engine.poll_node_output(output_ref, buffer_len))
-		} else {
-			let mut access = refs.1.write().unwrap();
+		if let [output_ref] = refs.0.as_slice() {
+			let buf = engine.poll_node_output(output_ref, buffer_len);
 
-			for output_ref in &refs.0 {
-				let buf = &*engine.poll_node_output(output_ref, buffer_len);
-				
-				if access.len() != buffer_len {
-					if access.len() == 0 {
-						*access = Buffer::from_bus_kind(buf.get_bus_kind());
-					}
+			// Audio of another channel count is converted below
+			if kind.is_none_or(|kind| kind == buf.get_bus_kind()) {
+				return Some(buf)
+			}
+		} else if refs.0.is_empty() {
+			return None
+		}
 
-					access.resize(buffer_len);
-				}
+		let mut access = refs.1.write().unwrap();
 
-				match (&mut *access, buf) {
-					(Buffer::Audio(access), Buffer::Audio(buf)) => {
-						access
-							.iter_mut()
-							.zip(buf)
-							.for_each(|(a, b)| *a += *b);
-							
-					}
-	
-					(Buffer::Midi(access), Buffer::Midi(buf)) => {
-						access
-							.iter_mut()
-							.zip(buf)
-							.for_each(|(a, b)| a.append(&mut b.clone()))
-					}
-	
-					(Buffer::Control(access), Buffer::Control(buf)) => {
-						access
-							.iter_mut()
-							.zip(buf)
-							.for_each(|(a, b)| *a += *b);
-					}
-	
-					_ => panic!()
+		for output_ref in &refs.0 {
+			let buf = &*engine.poll_node_output(output_ref, buffer_len);
+			
+			if access.len() != buffer_len {
+				if access.len() == 0 {
+					*access = Buffer::from_bus_kind(kind.unwrap_or(buf.get_bus_kind()));
 				}
+
+				access.resize(buffer_len);
 			}
-			
-			drop(access);
 
-			Some(refs.1.read().unwrap())
+			mix_into_buffer(&mut access.get_buffer_access(), buf);
 		}
+		
+		drop(access);
+
+		Some(refs.1.read().unwrap())
 	}
 
 	fn poll_input_into_buffer(
 		&self,
 		input: usize,
-		mut buffer: &mut BufferAccess,
+		buffer: &mut BufferAccess,
 		instance: &NodeInstance,
 		engine: &Engine
 	) {
 		let refs = &instance.inputs[input];
 
-		let mut access = refs.1.write().unwrap();
-
 		for output_ref in &refs.0 {
 			let buf = &*engine.poll_node_output(output_ref, buffer.len());
-			
-			if access.len() != buffer.len() {
-				if access.len() == 0 {
-					*access = Buffer::from_bus_kind(buf.get_bus_kind());
-				}
 
-				access.resize(buffer.len());
+			mix_into_buffer(buffer, buf);
+		}
+	}
+}
+
+// Adds `buf` onto `access`. Audio buses of different channel counts
+// are up or downmixed to the destination's layout.
+fn mix_into_buffer(access: &mut BufferAccess, buf: &Buffer) {
+	match (access, buf) {
+		(BufferAccess::Audio(access), Buffer::Audio(buf)) => {
+			access
+				.iter_mut()
+				.zip(buf)
+				.for_each(|(a, b)| *a += *b);
+		}
+
+		(BufferAccess::Multichannel(channels, access), Buffer::Multichannel(buf_channels, buf)) if channels == buf_channels => {
+			access
+				.iter_mut()
+				.zip(buf)
+				.for_each(|(a, b)| *a += *b);
+		}
+
+		(BufferAccess::Audio(access), Buffer::Multichannel(buf_channels, buf)) => {
+			let matrix = ChannelMatrix::new(ChannelLayout::from_channels(*buf_channels), ChannelLayout::Stereo);
+
+			for (a, b) in access.iter_mut().zip(buf.chunks_exact(*buf_channels)) {
+				let mut frame = [a.0, a.1];
+
+				matrix.mix_frame(b, &mut frame);
+				*a = Frame(frame[0], frame[1]);
 			}
+		}
 
-			match (&mut buffer, buf) {
-				(BufferAccess::Audio(access), Buffer::Audio(buf)) => {
-					access
-						.iter_mut()
-						.zip(buf)
-						.for_each(|(a, b)| *a += *b);
-						
-				}
+		(BufferAccess::Multichannel(channels, access), Buffer::Audio(buf)) => {
+			let matrix = ChannelMatrix::new(ChannelLayout::Stereo, ChannelLayout::from_channels(*channels));
 
-				(BufferAccess::Midi(access), Buffer::Midi(buf)) => {
-					access
-						.iter_mut()
-						.zip(buf)
-						.for_each(|(a, b)| a.append(&mut b.clone()))
-				}
+			for (a, b) in access.chunks_exact_mut(*channels).zip(buf) {
+				matrix.mix_frame(&[b.0, b.1], a);
+			}
+		}
 
-				(BufferAccess::Control(access), Buffer::Control(buf)) => {
-					access
-						.iter_mut()
-						.zip(buf)
-						.for_each(|(a, b)| *a += *b);
-				}
+		(BufferAccess::Multichannel(channels, access), Buffer::Multichannel(buf_channels, buf)) => {
+			let matrix = ChannelMatrix::new(ChannelLayout::from_channels(*buf_channels), ChannelLayout::from_channels(*channels));
 
-				_ => panic!(),
+			for (a, b) in access.chunks_exact_mut(*channels).zip(buf.chunks_exact(*buf_channels)) {
+				matrix.mix_frame(b, a);
 			}
 		}
-		
-		drop(access);
+
+		(BufferAccess::Midi(access), Buffer::Midi(buf)) => {
+			access
+				.iter_mut()
+				.zip(buf)
+				.for_each(|(a, b)| a.append(&mut b.clone()))
+		}
+
+		(BufferAccess::Control(access), Buffer::Control(buf)) => {
+			access
+				.iter_mut()
+				.zip(buf)
+				.for_each(|(a, b)| *a += *b);
+		}
+
+		(access, buf) => panic!("cannot mix a {:?} bus into a {:?} input", buf.get_bus_kind(), access.get_bus_kind()),
+	}
+}
+
+// For nodes with an audio and a control output produced by the same processing pass
+// (like a compressor's gain reduction). Whichever output is rendered first runs it
+// for the current block, and the results are cached until the node is advanced.
+pub(crate) struct BlockCache {
+	rendered_block: Option<usize>,
+	pub(crate) audio: Vec<Frame>,
+	pub(crate) control: Vec<f32>,
+}
+
+impl BlockCache {
+	pub(crate) fn new() -> Self {
+		BlockCache {
+			rendered_block: None,
+			audio: vec![],
+			control: vec![],
+		}
+	}
+
+	pub(crate) fn prepare(&mut self, block: usize, len: usize) -> bool {
+		if self.rendered_block == Some(block) && self.audio.len() == len {
+			return false
+		}
+
+		self.rendered_block = Some(block);
+		self.audio.clear();
+		self.audio.resize(len, Frame::ZERO);
+		self.control.clear();
+		self.control.resize(len, 0.0);
+
+		true
+	}
+
+	pub(crate) fn write_output(&self, output: usize, mut buffer: BufferAccess) {
+		match output {
+			0 => buffer.audio_mut().unwrap().copy_from_slice(&self.audio),
+			1 => buffer.control_mut().unwrap().copy_from_slice(&self.control),
+
+			_ => panic!()
+		}
 	}
 }
 
@@ -244,6 +325,8 @@ pub struct NodeInstance {
 	metadata: HashMap<String, ParamValue>,
 	tl_transform: Option<TimelineTransform>,
 	params: Vec<(Parameter, ParamValue)>,
+	// The bus kind of each input as of the last layout rebuild
+	input_kinds: Vec<BusKind>,
 }
 
 impl NodeInstance {
@@ -269,7 +352,11 @@ impl NodeInstance {
 						.get_params()
 						.iter()
 						.copied()
-						.map(|desc| (desc, ParamValue::from_desc(desc)))
+						.enumerate()
+						.map(|(i, desc)| (
+							desc,
+							node.get_param_default_value(i).unwrap_or_else(|| ParamValue::from_desc(desc))
+						))
 						.collect(),
 			
 			tl_transform:
@@ -279,6 +366,7 @@ impl NodeInstance {
 					None
 				},
 			
+			input_kinds: node.get_inputs().to_vec(),
 			metadata: HashMap::new(),
 			node,
 			ctor,
@@ -303,9 +391,83 @@ impl NodeInstance {
 		&self.params
 	}
 
-	pub fn set_param(&mut self, param: usize, value: ParamValue) {
+	// Applies a parameter value. If the node reports a changed port layout afterwards,
+	// the instance is rebuilt to match and the dropped inputs and outputs are returned.
+	//
+	// Connections from other nodes into removed outputs are not touched here,
+	// use `Engine::set_node_param()` to have those pruned as well.
+	pub fn set_param(&mut self, param: usize, value: ParamValue) -> Option<LayoutChange> {
 		self.node.param_updated(param, &value);
 		self.params[param].1.set(value);
+
+		if self.node.take_layout_changed() {
+			Some(self.rebuild_layout())
+		} else {
+			None
+		}
+	}
+
+	// Resizes inputs, outputs and parameter values to match the node's current layout.
+	// Connections on inputs that still exist with the same bus kind are kept, outputs
+	// that changed kind are reported as removed so connections from them get dropped.
+	// Parameters added by the rebuild start at their defaults, which the node is told about.
+	pub fn rebuild_layout(&mut self) -> LayoutChange {
+		let inputs = self.node.get_inputs();
+		let outputs = self.node.get_outputs();
+		let params = self.node.get_params();
+
+		let mut change = LayoutChange::default();
+
+		for (i, (kind, (refs, _))) in inputs.iter().zip(&self.input_kinds).zip(&mut self.inputs).enumerate() {
+			if kind.0 != kind.1 && !refs.is_empty() {
+				change.removed_inputs.push((i, std::mem::take(refs)));
+			}
+		}
+
+		if self.inputs.len() > inputs.len() {
+			change.removed_inputs.extend(self.inputs
+				.drain(inputs.len()..)
+				.enumerate()
+				.filter(|(_, (refs, _))| !refs.is_empty())
+				.map(|(i, (refs, _))| (inputs.len() + i, refs)));
+		} else {
+			self.inputs.resize_with(inputs.len(), || (vec![], RwLock::new(Buffer::from_bus_kind(BusKind::Control))));
+		}
+
+		self.input_kinds = inputs.to_vec();
+
+		if self.outputs.len() > outputs.len() {
+			change.removed_outputs = (outputs.len()..self.outputs.len()).collect();
+			self.outputs.truncate(outputs.len());
+		}
+
+		for (i, kind) in outputs.iter().enumerate() {
+			if let Some(buffer) = self.outputs.get_mut(i) {
+				if buffer.get_mut().unwrap().get_bus_kind() != *kind {
+					*buffer = RwLock::new(Buffer::from_bus_kind(*kind));
+					change.removed_outputs.push(i);
+				}
+			} else {
+				self.outputs.push(RwLock::new(Buffer::from_bus_kind(*kind)));
+			}
+		}
+
+		if self.params.len() != params.len() {
+			self.params.truncate(params.len());
+
+			let added = self.params.len();
+
+			for (i, desc) in params.iter().enumerate().skip(added) {
+				let value = self.node.get_param_default_value(i).unwrap_or_else(|| ParamValue::from_desc(*desc));
+				self.params.push((*desc, value));
+			}
+
+			for (i, (_, value)) in self.params.iter().enumerate().skip(added) {
+				self.node.param_updated(i, value);
+			}
+		}
+
+		change
 	}
 
 	pub fn render(&self, output: usize, samples: usize, engine: &Engine) {
@@ -328,11 +490,7 @@ impl NodeInstance {
 		}
 
 		for buffer in &mut self.outputs {
-			match &mut *buffer.write().unwrap() {
-				Buffer::Audio(buf) => buf.clear(),
-				Buffer::Control(buf) => buf.clear(),
-				Buffer::Midi(buf) => buf.clear()
-			}
+			buffer.write().unwrap().clear();
 		}
 	}
 
@@ -380,16 +538,54 @@ pub struct OutputRef {
 }
 
 #[derive(Debug, Copy, Clone)]
+pub struct Connection {
+	pub from: OutputRef,
+	pub to_node: usize,
+	pub to_input: usize,
+}
+
+// Inputs and outputs dropped when a node's port layout shrinks or changes kind
+#[derive(Debug, Default, Clone)]
+pub struct LayoutChange {
+	// Removed inputs that had connections, along with the outputs they were connected to
+	pub removed_inputs: Vec<(usize, Vec<OutputRef>)>,
+	pub removed_outputs: Vec<usize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BusKind {
+	// Stereo audio
 	Audio,
 	Midi,
 	Control,
+	// Audio with any channel count other than 2, interleaved.
+	// Use `BusKind::audio()` to get the canonical kind for a channel count.
+	Multichannel(usize),
+}
+
+impl BusKind {
+	pub fn audio(channels: usize) -> Self {
+		match channels {
+			2 => BusKind::Audio,
+			n => BusKind::Multichannel(n),
+		}
+	}
+
+	// Channel count for audio buses, None for Midi and Control
+	pub fn channels(&self) -> Option<usize> {
+		match self {
+			BusKind::Audio => Some(2),
+			BusKind::Multichannel(n) => Some(*n),
+			BusKind::Midi | BusKind::Control => None,
+		}
+	}
 }
 
 pub enum Buffer {
 	Audio(Vec<Frame>),
 	Midi(Vec<MidiMessageChain>),
 	Control(Vec<f32>),
+	Multichannel(usize, Vec<f32>),
 }
 
 impl Buffer {
@@ -398,6 +594,7 @@ impl Buffer {
 			BusKind::Audio => Buffer::Audio(vec![]),
 			BusKind::Midi => Buffer::Midi(vec![]),
 			BusKind::Control => Buffer::Control(vec![]),
+			BusKind::Multichannel(channels) => Buffer::Multichannel(channels, vec![]),
 		}
 	}
 
@@ -406,6 +603,7 @@ impl Buffer {
 			Buffer::Audio(_) => BusKind::Audio,
 			Buffer::Control(_) => BusKind::Control,
 			Buffer::Midi(_) => BusKind::Midi,
+			Buffer::Multichannel(channels, _) => BusKind::Multichannel(*channels),
 		}
 	}
 
@@ -414,6 +612,7 @@ impl Buffer {
 			Buffer::Audio(buf) => BufferAccess::Audio(buf),
 			Buffer::Control(buf) => BufferAccess::Control(buf),
 			Buffer::Midi(buf) => BufferAccess::Midi(buf),
+			Buffer::Multichannel(channels, buf) => BufferAccess::Multichannel(*channels, buf),
 		}
 	}
 
@@ -422,14 +621,17 @@ impl Buffer {
 			Buffer::Audio(buf) => buf.clear(),
 			Buffer::Control(buf) => buf.clear(),
 			Buffer::Midi(buf) => buf.clear(),
+			Buffer::Multichannel(_, buf) => buf.clear(),
 		}
 	}
 
+	// Length in frames
 	pub fn len(&self) -> usize {
 		match self {
 			Buffer::Audio(buf) => buf.len(),
 			Buffer::Midi(buf) => buf.len(),
 			Buffer::Control(buf) => buf.len(),
+			Buffer::Multichannel(channels, buf) => buf.len() / (*channels).max(1),
 		}
 	}
 
@@ -438,6 +640,7 @@ impl Buffer {
 			Buffer::Audio(buf) => buf.capacity(),
 			Buffer::Midi(buf) => buf.capacity(),
 			Buffer::Control(buf) => buf.capacity(),
+			Buffer::Multichannel(channels, buf) => buf.capacity() / (*channels).max(1),
 		}
 	}
 
@@ -446,6 +649,7 @@ impl Buffer {
 			Buffer::Audio(buf) => buf.resize(len, Frame::ZERO),
 			Buffer::Midi(buf) => buf.resize(len, MidiMessageChain::default()),
 			Buffer::Control(buf) => buf.resize(len, 0.0),
+			Buffer::Multichannel(channels, buf) => buf.resize(len * *channels, 0.0),
 		}
 	}
 	
@@ -496,20 +700,44 @@ impl Buffer {
 		
 		Some(control)
 	}
+
+	// Channel count and interleaved samples of a Multichannel buffer
+	pub fn multichannel(&self) -> Option<(usize, &[f32])> {
+		let Buffer::Multichannel(channels, samples) = self else {
+			return None
+		};
+
+		Some((*channels, samples))
+	}
 }
 
 pub enum BufferAccess<'buf> {
 	Audio(&'buf mut [Frame]),
 	Midi(&'buf mut [MidiMessageChain]),
 	Control(&'buf mut [f32]),
+	Multichannel(usize, &'buf mut [f32]),
 }
 
 impl<'buf> BufferAccess<'buf> {
-	fn len(&self) -> usize {
+	pub(crate) fn len(&self) -> usize {
 		match self {
 			BufferAccess::Audio(buf) => buf.len(),
 			BufferAccess::Midi(buf) => buf.len(),
 			BufferAccess::Control(buf) => buf.len(),
+			BufferAccess::Multichannel(channels, buf) => buf.len() / (*channels).max(1),
+		}
+	}
+
+	// Reborrows the frames in `range`
+	pub fn slice(&mut self, range: Range<usize>) -> BufferAccess<'_> {
+		match self {
+			BufferAccess::Audio(buf) => BufferAccess::Audio(&mut buf[range]),
+			BufferAccess::Midi(buf) => BufferAccess::Midi(&mut buf[range]),
+			BufferAccess::Control(buf) => BufferAccess::Control(&mut buf[range]),
+
+			BufferAccess::Multichannel(channels, buf) => {
+				BufferAccess::Multichannel(*channels, &mut buf[range.start * *channels..range.end * *channels])
+			}
 		}
 	}
 
@@ -518,6 +746,7 @@ impl<'buf> BufferAccess<'buf> {
 			BufferAccess::Audio(_) => BusKind::Audio,
 			BufferAccess::Control(_) => BusKind::Control,
 			BufferAccess::Midi(_) => BusKind::Midi,
+			BufferAccess::Multichannel(channels, _) => BusKind::Multichannel(*channels),
 		}
 	}
 
@@ -526,6 +755,7 @@ impl<'buf> BufferAccess<'buf> {
 			BufferAccess::Audio(buf) => buf.fill(Frame::ZERO),
 			BufferAccess::Control(buf) => buf.fill(0f32),
 			BufferAccess::Midi(buf) => buf.fill(MidiMessageChain::default()),
+			BufferAccess::Multichannel(_, buf) => buf.fill(0f32),
 		}
 	}
 
@@ -576,6 +806,14 @@ impl<'buf> BufferAccess<'buf> {
 		
 		Some(control)
 	}
+
+	pub fn multichannel_mut(&mut self) -> Option<(usize, &mut [f32])> {
+		let BufferAccess::Multichannel(channels, samples) = self else {
+			return None
+		};
+
+		Some((*channels, samples))
+	}
 }
 
 
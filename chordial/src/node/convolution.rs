@@ -0,0 +1,308 @@
+use std::sync::Mutex;
+
+use crate::{engine::{Config, Engine, Frame}, fft::{Complex, Fft}, param::{ParamKind, ParamValue, Parameter}, resource::{AudioData, ResourceHandle, ResourceHandleDyn}, util::{self, db_to_amp}};
+
+use super::{BufferAccess, BusKind, Node, NodeInstance, NodeUtil};
+
+
+// Partition size of the uniformly partitioned convolution, which is also the
+// latency introduced by the node (in samples)
+const PARTITION_SIZE: usize = 128;
+
+// Uniformly partitioned overlap-save convolution of a stereo signal with a stereo IR
+pub(crate) struct Convolver {
+	fft: Fft,
+
+	// Per channel: IR partition spectra, and the frequency-domain delay line
+	// holding the spectra of the most recent input blocks
+	partitions: [Vec<Vec<Complex>>; 2],
+	fdl: [Vec<Vec<Complex>>; 2],
+	fdl_pos: usize,
+
+	input: [Vec<f32>; 2],
+	output: [Vec<f32>; 2],
+	block_pos: usize,
+
+	scratch: Vec<Complex>,
+	accum: Vec<Complex>,
+}
+
+impl Convolver {
+	pub(crate) fn new() -> Self {
+		Convolver {
+			fft: Fft::new(PARTITION_SIZE * 2),
+			partitions: [vec![], vec![]],
+			fdl: [vec![], vec![]],
+			fdl_pos: 0,
+			input: [vec![0.0; PARTITION_SIZE * 2], vec![0.0; PARTITION_SIZE * 2]],
+			output: [vec![0.0; PARTITION_SIZE], vec![0.0; PARTITION_SIZE]],
+			block_pos: 0,
+			scratch: vec![Complex::ZERO; PARTITION_SIZE * 2],
+			accum: vec![Complex::ZERO; PARTITION_SIZE * 2],
+		}
+	}
+
+	pub(crate) fn load_ir(&mut self, ir: [Vec<f32>; 2]) {
+		let partition_count = ir[0].len().div_ceil(PARTITION_SIZE);
+
+		for (channel, ir) in ir.iter().enumerate() {
+			self.partitions[channel] = ir
+				.chunks(PARTITION_SIZE)
+				.map(|chunk| {
+					let mut spectrum = vec![Complex::ZERO; PARTITION_SIZE * 2];
+
+					for (c, s) in spectrum.iter_mut().zip(chunk) {
+						c.re = *s;
+					}
+
+					self.fft.forward(&mut spectrum);
+					spectrum
+				})
+				.collect();
+
+			self.fdl[channel] = vec![vec![Complex::ZERO; PARTITION_SIZE * 2]; partition_count];
+		}
+
+		self.fdl_pos = 0;
+	}
+
+	fn process_block(&mut self) {
+		let partition_count = self.partitions[0].len();
+
+		for channel in 0..2 {
+			if partition_count == 0 {
+				self.output[channel].fill(0.0);
+				self.input[channel].copy_within(PARTITION_SIZE.., 0);
+				continue
+			}
+
+			for (c, s) in self.scratch.iter_mut().zip(&self.input[channel]) {
+				*c = Complex::new(*s, 0.0);
+			}
+
+			self.fft.forward(&mut self.scratch);
+			self.fdl[channel][self.fdl_pos].copy_from_slice(&self.scratch);
+			self.accum.fill(Complex::ZERO);
+
+			for (p, partition) in self.partitions[channel].iter().enumerate() {
+				let spectrum = &self.fdl[channel][(self.fdl_pos + partition_count - p) % partition_count];
+
+				for ((acc, x), h) in self.accum.iter_mut().zip(spectrum).zip(partition) {
+					*acc += *x * *h;
+				}
+			}
+
+			self.fft.inverse(&mut self.accum);
+
+			// Overlap-save: only the second half of the circular convolution is valid
+			for (out, c) in self.output[channel].iter_mut().zip(&self.accum[PARTITION_SIZE..]) {
+				*out = c.re;
+			}
+
+			self.input[channel].copy_within(PARTITION_SIZE.., 0);
+		}
+
+		if partition_count > 0 {
+			self.fdl_pos = (self.fdl_pos + 1) % partition_count;
+		}
+	}
+
+	// Returns the wet signal, and the input delayed by the same latency
+	pub(crate) fn process(&mut self, input: Frame) -> (Frame, Frame) {
+		let pos = self.block_pos;
+		let wet = Frame(self.output[0][pos], self.output[1][pos]);
+		let dry = Frame(self.input[0][pos], self.input[1][pos]);
+
+		self.input[0][PARTITION_SIZE + pos] = input.0;
+		self.input[1][PARTITION_SIZE + pos] = input.1;
+		self.block_pos += 1;
+
+		if self.block_pos == PARTITION_SIZE {
+			self.process_block();
+			self.block_pos = 0;
+		}
+
+		(wet, dry)
+	}
+}
+
+// The IR is resampled and partitioned when a parameter shaping it changes, or between
+// blocks once the linked resource changed, never while rendering.
+pub struct Convolution {
+	sample_rate: u32,
+	ir: ResourceHandle<AudioData>,
+	ir_generation: usize,
+	mix: f32,
+	trim_start: f32,
+	trim_end: f32,
+	gain: f32,
+	convolver: Mutex<Convolver>,
+}
+
+impl Convolution {
+	pub fn new(sample_rate: u32) -> Self {
+		Convolution {
+			sample_rate,
+			ir: ResourceHandle::nil("AudioData"),
+			ir_generation: 0,
+			mix: 1.0,
+			trim_start: 0.0,
+			trim_end: 0.0,
+			gain: 0.0,
+			convolver: Mutex::new(Convolver::new()),
+		}
+	}
+
+	// Resamples the IR to the engine rate, then applies trim and gain
+	fn prepare_ir(&self, data: &AudioData) -> [Vec<f32>; 2] {
+		let frames: Vec<Frame> = if data.sample_rate == self.sample_rate || data.data.len() < 2 {
+			data.data.clone()
+		} else {
+			let len = (data.data.len() as f64 * self.sample_rate as f64 / data.sample_rate as f64) as usize;
+
+			(0..len)
+				.map(|i| util::resample(
+					&data.data,
+					data.sample_rate as f32,
+					self.sample_rate as f32,
+					i,
+					util::ResampleMethod::Linear
+				))
+				.collect()
+		};
+
+		let ms_to_frames = |ms: f32| (ms / 1000.0 * self.sample_rate as f32) as usize;
+		let start = ms_to_frames(self.trim_start).min(frames.len());
+		let end = frames.len().saturating_sub(ms_to_frames(self.trim_end)).max(start);
+		let gain = db_to_amp(self.gain);
+
+		[
+			frames[start..end].iter().map(|f| f.0 * gain).collect(),
+			frames[start..end].iter().map(|f| f.1 * gain).collect(),
+		]
+	}
+
+	fn load_ir(&mut self) {
+		self.ir_generation = self.ir.generation();
+
+		let ir = match &*self.ir.inner() {
+			Some(ir) => self.prepare_ir(&ir.read().unwrap().data),
+			None => [vec![], vec![]],
+		};
+
+		self.convolver.get_mut().unwrap().load_ir(ir);
+	}
+}
+
+impl Node for Convolution {
+	fn get_name(&self) -> &'static str {
+		"Convolution"
+	}
+
+	fn get_inputs(&self) -> &[BusKind] {
+		&[BusKind::Audio]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&[BusKind::Audio]
+	}
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in"]
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&["out"]
+	}
+
+	fn get_resource_names(&self) -> &'static [&'static str] {
+		&["ir"]
+	}
+
+	fn get_resource(&self, name: &str) -> &dyn ResourceHandleDyn {
+		match name {
+			"ir" => &self.ir,
+
+			_ => panic!()
+		}
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "mix",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "trim_start",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "trim_end",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "ir_gain",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Float(1.0)),
+			1..=3 => Some(ParamValue::Float(0.0)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let ParamValue::Float(value) = value else {
+			panic!()
+		};
+
+		let value = *value as f32;
+
+		match param {
+			0 => self.mix = value.clamp(0.0, 1.0),
+			1 => self.trim_start = value.max(0.0),
+			2 => self.trim_end = value.max(0.0),
+			3 => self.gain = value,
+
+			_ => panic!()
+		}
+
+		// Trim and gain are baked into the partitions
+		if param != 0 {
+			self.load_ir();
+		}
+	}
+
+	fn advance(&mut self, _frames: usize, _config: &Config) {
+		if self.ir.generation() != self.ir_generation {
+			self.load_ir();
+		}
+	}
+
+	fn render(
+		&self,
+		_output: usize,
+		mut buffer: BufferAccess,
+		instance: &NodeInstance,
+		engine: &Engine
+	) {
+		self.poll_input_into_buffer(0, &mut buffer, instance, engine);
+
+		let mut convolver = self.convolver.lock().unwrap();
+
+		let audio = buffer.audio_mut().unwrap();
+
+		for f in audio.iter_mut() {
+			// The dry signal is delayed by the same amount as the wet one to keep them aligned
+			let (wet, dry) = convolver.process(*f);
+
+			*f = dry * (1.0 - self.mix) + wet * self.mix;
+		}
+	}
+}
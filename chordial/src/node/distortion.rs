@@ -0,0 +1,365 @@
+use std::{mem::size_of, sync::Mutex};
+
+use crate::{engine::{Engine, Frame}, param::{ParamKind, ParamValue, Parameter}, resource::{Resource, ResourceHandle, ResourceHandleDyn}, util::{db_to_amp, lerp, DelayLine, Oversampler}};
+
+use super::{BufferAccess, BusKind, Node, NodeInstance, NodeUtil};
+
+
+// A user-defined transfer curve, sampled at evenly spaced input values
+// from -1.0 to 1.0. Inputs outside that range are clamped.
+#[derive(Clone, Default)]
+pub struct ShaperCurve {
+	pub points: Vec<f32>,
+}
+
+impl ShaperCurve {
+	pub fn apply(&self, x: f32) -> f32 {
+		match self.points.len() {
+			0 => x,
+			1 => self.points[0],
+
+			len => {
+				let pos = (x.clamp(-1.0, 1.0) + 1.0) * 0.5 * (len - 1) as f32;
+				let i = (pos.floor() as usize).min(len - 2);
+
+				lerp(self.points[i], self.points[i + 1], pos - i as f32)
+			}
+		}
+	}
+}
+
+impl Resource for ShaperCurve {
+	fn resource_kind(&self) -> &'static str {
+		"ShaperCurve"
+	}
+
+	fn apply_action(&mut self, action: &str, args: &[ParamValue]) {
+		match action {
+			"set_points" => {
+				self.points = args
+					.iter()
+					.map(|arg| {
+						let ParamValue::Float(value) = arg else {
+							panic!()
+						};
+
+						*value as f32
+					})
+					.collect();
+			}
+
+			"set_point" => {
+				let [ParamValue::Int(idx), ParamValue::Float(value)] = args else {
+					panic!()
+				};
+
+				self.points[*idx as usize] = *value as f32;
+			}
+
+			_ => panic!()
+		}
+	}
+
+	fn get(&self, keys: &[ParamValue]) -> Option<ParamValue> {
+		let [ParamValue::String(request), args @ ..] = keys else {
+			return None
+		};
+
+		match request.as_str() {
+			"get_point_count" => Some(ParamValue::Int(self.points.len() as i64)),
+
+			"get_point" => {
+				let [ParamValue::Int(idx)] = args else {
+					return None
+				};
+
+				Some(ParamValue::Float(*self.points.get(*idx as usize)? as f64))
+			}
+
+			"apply" => {
+				let [ParamValue::Float(x)] = args else {
+					return None
+				};
+
+				Some(ParamValue::Float(self.apply(*x as f32) as f64))
+			}
+
+			_ => None
+		}
+	}
+
+	fn save(&self) -> Vec<u8> {
+		let mut result = Vec::with_capacity(self.points.len() * size_of::<f32>());
+
+		for point in &self.points {
+			result.extend_from_slice(&point.to_ne_bytes());
+		}
+
+		result
+	}
+
+	fn load(&mut self, data: &[u8]) {
+		self.points = data
+			.chunks_exact(size_of::<f32>())
+			.map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+			.collect();
+	}
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShaperKind {
+	Tanh,
+	HardClip,
+	Foldback,
+	Bitcrush,
+	Custom,
+}
+
+impl ShaperKind {
+	pub fn from_index(idx: i64) -> Self {
+		match idx {
+			0 => ShaperKind::Tanh,
+			1 => ShaperKind::HardClip,
+			2 => ShaperKind::Foldback,
+			3 => ShaperKind::Bitcrush,
+			4 => ShaperKind::Custom,
+
+			_ => panic!("invalid waveshaper curve: {idx}")
+		}
+	}
+}
+
+struct WaveshaperState {
+	oversampler: Oversampler,
+	oversampled: Vec<Frame>,
+	dry_delay: (DelayLine, DelayLine),
+	hold: Frame,
+	hold_phase: f32,
+}
+
+pub struct Waveshaper {
+	kind: ShaperKind,
+	drive: f32,
+	oversampling: usize,
+	bits: f32,
+	downsample: f32,
+	output: f32,
+	mix: f32,
+	curve: ResourceHandle<ShaperCurve>,
+	state: Mutex<WaveshaperState>,
+}
+
+impl Waveshaper {
+	pub fn new() -> Self {
+		Waveshaper {
+			kind: ShaperKind::Tanh,
+			drive: 0.0,
+			oversampling: 1,
+			bits: 8.0,
+			downsample: 1.0,
+			output: 0.0,
+			mix: 1.0,
+			curve: ResourceHandle::nil("ShaperCurve"),
+			state: Mutex::new(WaveshaperState {
+				oversampler: Oversampler::new(1),
+				oversampled: vec![Frame::ZERO; 1],
+				dry_delay: (DelayLine::new(16), DelayLine::new(16)),
+				hold: Frame::ZERO,
+				hold_phase: 0.0,
+			}),
+		}
+	}
+
+	fn shape(&self, x: f32, curve: Option<&ShaperCurve>) -> f32 {
+		match self.kind {
+			ShaperKind::Tanh => x.tanh(),
+			ShaperKind::HardClip => x.clamp(-1.0, 1.0),
+			ShaperKind::Foldback => 1.0 - ((x + 1.0).rem_euclid(4.0) - 2.0).abs(),
+
+			ShaperKind::Bitcrush => {
+				let step = 2.0 / 2.0f32.powf(self.bits);
+				((x / step).round() * step).clamp(-1.0, 1.0)
+			}
+
+			ShaperKind::Custom => match curve {
+				Some(curve) => curve.apply(x),
+				None => x,
+			}
+		}
+	}
+}
+
+impl Default for Waveshaper {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Node for Waveshaper {
+	fn get_name(&self) -> &'static str {
+		"Waveshaper"
+	}
+
+	fn get_inputs(&self) -> &[BusKind] {
+		&[BusKind::Audio]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&[BusKind::Audio]
+	}
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in"]
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&["out"]
+	}
+
+	fn get_resource_names(&self) -> &'static [&'static str] {
+		&["curve"]
+	}
+
+	fn get_resource(&self, name: &str) -> &dyn ResourceHandleDyn {
+		match name {
+			"curve" => &self.curve,
+
+			_ => panic!()
+		}
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Int,
+				text: "curve",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "drive",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "oversampling",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "bits",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "downsample",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "output",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "mix",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Int(0)),
+			1 => Some(ParamValue::Float(0.0)),
+			2 => Some(ParamValue::Int(1)),
+			3 => Some(ParamValue::Float(8.0)),
+			4 => Some(ParamValue::Float(1.0)),
+			5 => Some(ParamValue::Float(0.0)),
+			6 => Some(ParamValue::Float(1.0)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		match (param, value) {
+			(0, ParamValue::Int(kind)) => self.kind = ShaperKind::from_index(*kind),
+			(1, ParamValue::Float(drive)) => self.drive = *drive as f32,
+
+			(2, ParamValue::Int(factor)) => {
+				let factor = match factor {
+					..=1 => 1,
+					2 => 2,
+					3..=4 => 4,
+					_ => 8,
+				};
+
+				let state = self.state.get_mut().unwrap();
+
+				self.oversampling = factor;
+				state.oversampler = Oversampler::new(factor);
+				state.oversampled.resize(factor, Frame::ZERO);
+			}
+
+			(3, ParamValue::Float(bits)) => self.bits = (*bits as f32).clamp(1.0, 24.0),
+			(4, ParamValue::Float(factor)) => self.downsample = (*factor as f32).max(1.0),
+			(5, ParamValue::Float(output)) => self.output = *output as f32,
+			(6, ParamValue::Float(mix)) => self.mix = (*mix as f32).clamp(0.0, 1.0),
+
+			_ => panic!()
+		}
+	}
+
+	fn render(
+		&self,
+		_output: usize,
+		mut buffer: BufferAccess,
+		instance: &NodeInstance,
+		engine: &Engine
+	) {
+		self.poll_input_into_buffer(0, &mut buffer, instance, engine);
+
+		let curve = self.curve.inner();
+		let curve = curve.as_ref().map(|curve| curve.read().unwrap());
+		let curve = curve.as_ref().map(|curve| &curve.data);
+
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+
+		let drive = db_to_amp(self.drive);
+		let output = db_to_amp(self.output);
+		let crush = self.kind == ShaperKind::Bitcrush;
+		let latency = state.oversampler.latency();
+
+		for f in buffer.audio_mut().unwrap() {
+			let mut input = *f * drive;
+
+			// Keep the dry signal aligned with the oversampling filters' delay
+			let dry = if latency > 0.0 {
+				let delayed = Frame(state.dry_delay.0.read_frac(latency), state.dry_delay.1.read_frac(latency));
+
+				state.dry_delay.0.write(f.0);
+				state.dry_delay.1.write(f.1);
+				delayed
+			} else {
+				*f
+			};
+
+			// Sample rate reduction is meant to alias, so it happens before oversampling
+			if crush && self.downsample > 1.0 {
+				if state.hold_phase <= 0.0 {
+					state.hold = input;
+					state.hold_phase += self.downsample;
+				}
+
+				state.hold_phase -= 1.0;
+				input = state.hold;
+			}
+
+			state.oversampler.upsample(input, &mut state.oversampled);
+
+			for frame in state.oversampled.iter_mut() {
+				*frame = Frame(self.shape(frame.0, curve), self.shape(frame.1, curve));
+			}
+
+			let wet = state.oversampler.downsample(&state.oversampled) * output;
+
+			*f = dry * (1.0 - self.mix) + wet * self.mix;
+		}
+	}
+}
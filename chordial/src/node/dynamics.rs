@@ -0,0 +1,480 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::{engine::{Config, Engine, Frame}, param::{ParamKind, ParamValue, Parameter}, util::{amp_to_db, db_to_amp, DelayLine}};
+
+use super::{BlockCache, BufferAccess, BusKind, Node, NodeInstance, NodeUtil};
+
+
+const MIN_GAIN_DB: f32 = -96.0;
+const MAX_LOOKAHEAD_MS: f32 = 20.0;
+
+fn time_coefficient(ms: f32, sample_rate: u32) -> f32 {
+	if ms <= 0.0 {
+		return 0.0
+	}
+
+	(-1.0 / (ms / 1000.0 * sample_rate as f32)).exp()
+}
+
+fn frame_level(frame: Frame) -> f32 {
+	frame.0.abs().max(frame.1.abs())
+}
+
+fn poll_sidechain<T: Node>(
+	node: &T,
+	cache: &BlockCache,
+	instance: &NodeInstance,
+	engine: &Engine
+) -> Vec<f32> {
+	match node.poll_input(1, cache.audio.len(), instance, engine) {
+		Some(sidechain) => sidechain.audio().unwrap().iter().copied().map(frame_level).collect(),
+		None => cache.audio.iter().copied().map(frame_level).collect(),
+	}
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DynamicsMode {
+	Compressor,
+	Expander,
+	Gate,
+}
+
+struct DynamicsState {
+	cache: BlockCache,
+	envelope: f32,
+}
+
+pub struct Dynamics {
+	mode: DynamicsMode,
+	sample_rate: u32,
+	threshold: f32,
+	ratio: f32,
+	knee: f32,
+	attack: f32,
+	release: f32,
+	makeup: f32,
+	block: usize,
+	state: Mutex<DynamicsState>,
+}
+
+impl Dynamics {
+	pub fn new(mode: DynamicsMode, sample_rate: u32) -> Self {
+		let [threshold, ratio, knee, attack, release, makeup] = Self::defaults(mode);
+
+		Dynamics {
+			mode,
+			sample_rate,
+			threshold,
+			ratio,
+			knee,
+			attack,
+			release,
+			makeup,
+			block: 0,
+			state: Mutex::new(DynamicsState {
+				cache: BlockCache::new(),
+				envelope: 0.0,
+			}),
+		}
+	}
+
+	fn defaults(mode: DynamicsMode) -> [f32; 6] {
+		match mode {
+			DynamicsMode::Compressor => [-18.0, 4.0, 6.0, 10.0, 100.0, 0.0],
+			DynamicsMode::Expander => [-40.0, 2.0, 6.0, 1.0, 100.0, 0.0],
+			DynamicsMode::Gate => [-50.0, 20.0, 0.0, 0.5, 50.0, 0.0],
+		}
+	}
+
+	// Static gain curve: returns the gain change in dB for an input level in dB
+	fn gain_computer(&self, level: f32) -> f32 {
+		let over = level - self.threshold;
+		let knee = self.knee.max(0.0);
+
+		let output = match self.mode {
+			DynamicsMode::Compressor => {
+				if 2.0 * over < -knee {
+					level
+				} else if 2.0 * over.abs() <= knee && knee > 0.0 {
+					let x = over + knee / 2.0;
+					level + (1.0 / self.ratio - 1.0) * x * x / (2.0 * knee)
+				} else {
+					self.threshold + over / self.ratio
+				}
+			}
+
+			DynamicsMode::Expander | DynamicsMode::Gate => {
+				if 2.0 * over > knee {
+					level
+				} else if 2.0 * over.abs() <= knee && knee > 0.0 {
+					let x = over - knee / 2.0;
+					level + (1.0 - self.ratio) * x * x / (2.0 * knee)
+				} else {
+					self.threshold + over * self.ratio
+				}
+			}
+		};
+
+		(output - level).clamp(MIN_GAIN_DB, 0.0)
+	}
+
+	fn process(&self, state: &mut DynamicsState, sidechain: &[f32]) {
+		let attack = time_coefficient(self.attack, self.sample_rate);
+		let release = time_coefficient(self.release, self.sample_rate);
+		let makeup = db_to_amp(self.makeup);
+
+		for (i, level) in sidechain.iter().enumerate() {
+			let target = self.gain_computer(amp_to_db(*level));
+
+			// Attack is the reduction increasing for a compressor,
+			// and the gate/expander opening back up otherwise
+			let attacking = match self.mode {
+				DynamicsMode::Compressor => target < state.envelope,
+				DynamicsMode::Expander | DynamicsMode::Gate => target > state.envelope,
+			};
+
+			let coef = if attacking { attack } else { release };
+
+			state.envelope = target + coef * (state.envelope - target);
+			state.cache.control[i] = state.envelope;
+			state.cache.audio[i] = state.cache.audio[i] * (db_to_amp(state.envelope) * makeup);
+		}
+	}
+}
+
+impl Node for Dynamics {
+	fn get_name(&self) -> &'static str {
+		match self.mode {
+			DynamicsMode::Compressor => "Compressor",
+			DynamicsMode::Expander => "Expander",
+			DynamicsMode::Gate => "Gate",
+		}
+	}
+
+	fn get_inputs(&self) -> &[BusKind] {
+		&[BusKind::Audio, BusKind::Audio]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&[BusKind::Audio, BusKind::Control]
+	}
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in", "sidechain"]
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&["out", "gr"]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "threshold",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "ratio",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "knee",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "attack",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "release",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "makeup",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		Self::defaults(self.mode)
+			.get(param)
+			.map(|value| ParamValue::Float(*value as f64))
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let ParamValue::Float(value) = value else {
+			panic!()
+		};
+
+		let value = *value as f32;
+
+		match param {
+			0 => self.threshold = value,
+			1 => self.ratio = value.max(1.0),
+			2 => self.knee = value.max(0.0),
+			3 => self.attack = value.max(0.0),
+			4 => self.release = value.max(0.0),
+			5 => self.makeup = value,
+
+			_ => panic!()
+		}
+	}
+
+	fn render(
+		&self,
+		output: usize,
+		buffer: BufferAccess,
+		instance: &NodeInstance,
+		engine: &Engine
+	) {
+		let mut state = self.state.lock().unwrap();
+
+		if state.cache.prepare(self.block, buffer.len()) {
+			self.poll_input_into_buffer(0, &mut BufferAccess::Audio(&mut state.cache.audio), instance, engine);
+
+			let sidechain = poll_sidechain(self, &state.cache, instance, engine);
+
+			self.process(&mut state, &sidechain);
+		}
+
+		state.cache.write_output(output, buffer);
+	}
+
+	fn advance(&mut self, _frames: usize, _config: &Config) {
+		self.block += 1;
+	}
+}
+
+
+struct LimiterState {
+	cache: BlockCache,
+	delay: (DelayLine, DelayLine),
+
+	// Sliding minimum of the required gain over the lookahead window,
+	// kept as a monotonic queue of (sample index, gain)
+	min_queue: VecDeque<(usize, f32)>,
+	sample_index: usize,
+
+	released_gain: f32,
+	smoothing: Vec<f32>,
+	smoothing_pos: usize,
+	smoothing_sum: f64,
+}
+
+// Brickwall lookahead limiter.
+//
+// The required gain is held at its minimum over the lookahead window and then
+// averaged over the same window, so the gain has fully ramped down by the time
+// the (delayed) peak reaches the output.
+pub struct Limiter {
+	sample_rate: u32,
+	threshold: f32,
+	release: f32,
+	lookahead: f32,
+	makeup: f32,
+	block: usize,
+	state: Mutex<LimiterState>,
+}
+
+impl Limiter {
+	pub fn new(sample_rate: u32) -> Self {
+		let max_lookahead = (MAX_LOOKAHEAD_MS / 1000.0 * sample_rate as f32) as usize + 1;
+
+		let mut limiter = Limiter {
+			sample_rate,
+			threshold: -0.3,
+			release: 50.0,
+			lookahead: 5.0,
+			makeup: 0.0,
+			block: 0,
+			state: Mutex::new(LimiterState {
+				cache: BlockCache::new(),
+				delay: (DelayLine::new(max_lookahead), DelayLine::new(max_lookahead)),
+				min_queue: VecDeque::with_capacity(max_lookahead + 1),
+				sample_index: 0,
+				released_gain: 1.0,
+				smoothing: Vec::with_capacity(max_lookahead),
+				smoothing_pos: 0,
+				smoothing_sum: 0.0,
+			}),
+		};
+
+		limiter.reset_window();
+		limiter
+	}
+
+	fn window_len(&self) -> usize {
+		((self.lookahead / 1000.0 * self.sample_rate as f32) as usize).max(1)
+	}
+
+	fn reset_window(&mut self) {
+		let window = self.window_len();
+		let state = self.state.get_mut().unwrap();
+
+		state.smoothing.clear();
+		state.smoothing.resize(window, 1.0);
+		state.smoothing_sum = window as f64;
+		state.smoothing_pos = 0;
+		state.min_queue.clear();
+	}
+
+	// Without an external sidechain, the output never exceeds the threshold
+	fn process(&self, state: &mut LimiterState, sidechain: &[f32], self_keyed: bool) {
+		let window = state.smoothing.len();
+		let ceiling = db_to_amp(self.threshold);
+		let makeup = db_to_amp(self.makeup);
+		let release = time_coefficient(self.release, self.sample_rate);
+
+		// Summed from scratch every block so the running sum can't drift
+		state.smoothing_sum = state.smoothing.iter().map(|gain| *gain as f64).sum();
+
+		for (i, level) in sidechain.iter().enumerate() {
+			let level = level * makeup;
+			let required = if level > ceiling { ceiling / level } else { 1.0 };
+			let index = state.sample_index;
+
+			while state.min_queue.back().is_some_and(|(_, gain)| *gain >= required) {
+				state.min_queue.pop_back();
+			}
+
+			state.min_queue.push_back((index, required));
+
+			while state.min_queue.front().is_some_and(|(idx, _)| *idx + window <= index) {
+				state.min_queue.pop_front();
+			}
+
+			let held = state.min_queue.front().unwrap().1;
+
+			state.released_gain = if held < state.released_gain {
+				held
+			} else {
+				held + release * (state.released_gain - held)
+			};
+
+			state.smoothing_sum += (state.released_gain - state.smoothing[state.smoothing_pos]) as f64;
+			state.smoothing[state.smoothing_pos] = state.released_gain;
+			state.smoothing_pos = (state.smoothing_pos + 1) % window;
+			state.sample_index += 1;
+
+			let gain = (state.smoothing_sum / window as f64).min(1.0) as f32;
+			let input = state.cache.audio[i] * makeup;
+
+			let delayed = if window > 1 {
+				let delayed = Frame(state.delay.0.read(window - 1), state.delay.1.read(window - 1));
+
+				state.delay.0.write(input.0);
+				state.delay.1.write(input.1);
+				delayed
+			} else {
+				input
+			};
+
+			let out = delayed * gain;
+
+			debug_assert!(!self_keyed || frame_level(out) <= ceiling * 1.0001, "limiter overshoot: {out:?} > {ceiling}");
+
+			state.cache.audio[i] = out;
+			state.cache.control[i] = amp_to_db(gain);
+		}
+	}
+}
+
+impl Node for Limiter {
+	fn get_name(&self) -> &'static str {
+		"Limiter"
+	}
+
+	fn get_inputs(&self) -> &[BusKind] {
+		&[BusKind::Audio, BusKind::Audio]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&[BusKind::Audio, BusKind::Control]
+	}
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in", "sidechain"]
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&["out", "gr"]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "threshold",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "release",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "lookahead",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "makeup",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Float(-0.3)),
+			1 => Some(ParamValue::Float(50.0)),
+			2 => Some(ParamValue::Float(5.0)),
+			3 => Some(ParamValue::Float(0.0)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let ParamValue::Float(value) = value else {
+			panic!()
+		};
+
+		let value = *value as f32;
+
+		match param {
+			0 => self.threshold = value.min(0.0),
+			1 => self.release = value.max(0.0),
+			2 => {
+				self.lookahead = value.clamp(0.0, MAX_LOOKAHEAD_MS);
+				self.reset_window();
+			}
+			3 => self.makeup = value,
+
+			_ => panic!()
+		}
+	}
+
+	fn render(
+		&self,
+		output: usize,
+		buffer: BufferAccess,
+		instance: &NodeInstance,
+		engine: &Engine
+	) {
+		let mut state = self.state.lock().unwrap();
+
+		if state.cache.prepare(self.block, buffer.len()) {
+			self.poll_input_into_buffer(0, &mut BufferAccess::Audio(&mut state.cache.audio), instance, engine);
+
+			let sidechain = poll_sidechain(self, &state.cache, instance, engine);
+
+			self.process(&mut state, &sidechain, instance.inputs[1].0.is_empty());
+		}
+
+		state.cache.write_output(output, buffer);
+	}
+
+	fn advance(&mut self, _frames: usize, _config: &Config) {
+		self.block += 1;
+	}
+}
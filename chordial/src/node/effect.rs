@@ -1,12 +1,40 @@
+use smallvec::SmallVec;
+
 use crate::{engine::{Config, Engine, Frame}, node::NodeUtil, param::{ParamKind, ParamValue, Parameter}, util::db_to_factor};
 
 use super::{Buffer, BufferAccess, BusKind, Node, NodeInstance};
 
 
+// Input layout for effects: the audio input, followed by up to
+// `MAX_CONTROL_INPUTS` optional Control inputs
+const MAX_CONTROL_INPUTS: usize = 8;
+const EFFECT_INPUTS: [BusKind; MAX_CONTROL_INPUTS + 1] = [
+	BusKind::Audio,
+	BusKind::Control, BusKind::Control, BusKind::Control, BusKind::Control,
+	BusKind::Control, BusKind::Control, BusKind::Control, BusKind::Control,
+];
+
+// Reads a per-sample value from a connected Control input, or falls back to the parameter value
+pub(crate) fn control_or(controls: &[Option<&[f32]>], control: usize, i: usize, fallback: f32) -> f32 {
+	match controls.get(control) {
+		Some(Some(buf)) => buf.get(i).copied().unwrap_or(fallback),
+		_ => fallback,
+	}
+}
+
 pub trait Effect: Send {
 	fn render_effect(&self, buffer: BufferAccess);
 	fn advance_effect(&mut self, frames: usize, config: &Config);
 
+	// Effects that accept Control inputs list them after "in", and receive
+	// their buffers (or None when unconnected) in `render_effect_modulated()`
+	fn get_input_names(&self) -> &'static [&'static str] { &["in"] }
+
+	#[allow(unused_variables)]
+	fn render_effect_modulated(&self, buffer: BufferAccess, controls: &[Option<&[f32]>]) {
+		self.render_effect(buffer)
+	}
+
 	#[allow(unused_variables)]
 	fn param_updated(&mut self, param: usize, value: &ParamValue) { }
 
@@ -15,13 +43,20 @@ pub trait Effect: Send {
 
 	fn get_params(&self) -> &[Parameter] { &[] }
 
+	#[allow(unused_variables)]
+	fn query(&self, keys: &[ParamValue]) -> Option<ParamValue> { None }
+
 	fn get_name(&self) -> &'static str;
 }
 
 
 impl<T: Effect + 'static> Node for T {
 	fn get_inputs(&self) -> &[BusKind] {
-		&[BusKind::Audio]
+		let names = Effect::get_input_names(self);
+
+		assert!(names.len() <= EFFECT_INPUTS.len(), "too many effect control inputs!");
+
+		&EFFECT_INPUTS[..names.len()]
 	}
 
 	fn get_outputs(&self) -> &[BusKind] {
@@ -29,7 +64,7 @@ impl<T: Effect + 'static> Node for T {
 	}
 
 	fn get_input_names(&self) -> &'static [&'static str] {
-		&["in"]
+		Effect::get_input_names(self)
 	}
 
 	fn get_output_names(&self) -> &'static [&'static str] {
@@ -46,7 +81,25 @@ impl<T: Effect + 'static> Node for T {
 	
 	fn render(&self, _: usize, mut buffer: BufferAccess, instance: &NodeInstance, engine: &Engine) {
 		self.poll_input_into_buffer(0, &mut buffer, instance, engine);
-		self.render_effect(buffer);
+
+		let control_count = Effect::get_input_names(self).len().saturating_sub(1);
+
+		if control_count == 0 {
+			self.render_effect(buffer);
+			return
+		}
+
+		let len = buffer.len();
+		let guards = (1..=control_count)
+			.map(|input| self.poll_input(input, len, instance, engine))
+			.collect::<SmallVec<[_; MAX_CONTROL_INPUTS]>>();
+
+		let controls = guards
+			.iter()
+			.map(|guard| guard.as_ref().and_then(|buf| buf.control()))
+			.collect::<SmallVec<[_; MAX_CONTROL_INPUTS]>>();
+
+		self.render_effect_modulated(buffer, &controls);
 	}
 
 	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
@@ -60,6 +113,10 @@ impl<T: Effect + 'static> Node for T {
 	fn param_updated(&mut self, param: usize, value: &ParamValue) {
 		Effect::param_updated(self, param, value)
 	}
+
+	fn query(&self, keys: &[ParamValue]) -> Option<ParamValue> {
+		Effect::query(self, keys)
+	}
 }
 
 
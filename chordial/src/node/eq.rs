@@ -0,0 +1,448 @@
+use std::{f32::consts::TAU, sync::Mutex};
+
+use crate::{engine::{Config, Frame}, fft::{Complex, Fft}, param::{ParamKind, ParamValue, Parameter}, util::{amp_to_db, blackman, db_to_amp}};
+
+use super::{convolution::Convolver, effect::Effect, BufferAccess};
+
+
+pub const EQ_BANDS: usize = 8;
+const PARAMS_PER_BAND: usize = 4;
+
+// Length of the FIR used in linear phase mode. Its latency is half of this,
+// plus the partition size of the convolver.
+const FIR_LENGTH: usize = 4096;
+
+// When the bands change in linear phase mode, the new FIR runs alongside the old one
+// for its full length to fill its delay line, then is faded to over this many frames
+const FIR_FADE_LENGTH: usize = 1024;
+
+const DEFAULT_FREQS: [f32; EQ_BANDS] = [40.0, 100.0, 250.0, 600.0, 1500.0, 3500.0, 8000.0, 16000.0];
+
+const EQ_PARAMS: [Parameter; EQ_BANDS * PARAMS_PER_BAND + 2] = [
+	Parameter { kind: ParamKind::Int, text: "band1_type" },
+	Parameter { kind: ParamKind::Float, text: "band1_freq" },
+	Parameter { kind: ParamKind::Float, text: "band1_gain" },
+	Parameter { kind: ParamKind::Float, text: "band1_q" },
+	Parameter { kind: ParamKind::Int, text: "band2_type" },
+	Parameter { kind: ParamKind::Float, text: "band2_freq" },
+	Parameter { kind: ParamKind::Float, text: "band2_gain" },
+	Parameter { kind: ParamKind::Float, text: "band2_q" },
+	Parameter { kind: ParamKind::Int, text: "band3_type" },
+	Parameter { kind: ParamKind::Float, text: "band3_freq" },
+	Parameter { kind: ParamKind::Float, text: "band3_gain" },
+	Parameter { kind: ParamKind::Float, text: "band3_q" },
+	Parameter { kind: ParamKind::Int, text: "band4_type" },
+	Parameter { kind: ParamKind::Float, text: "band4_freq" },
+	Parameter { kind: ParamKind::Float, text: "band4_gain" },
+	Parameter { kind: ParamKind::Float, text: "band4_q" },
+	Parameter { kind: ParamKind::Int, text: "band5_type" },
+	Parameter { kind: ParamKind::Float, text: "band5_freq" },
+	Parameter { kind: ParamKind::Float, text: "band5_gain" },
+	Parameter { kind: ParamKind::Float, text: "band5_q" },
+	Parameter { kind: ParamKind::Int, text: "band6_type" },
+	Parameter { kind: ParamKind::Float, text: "band6_freq" },
+	Parameter { kind: ParamKind::Float, text: "band6_gain" },
+	Parameter { kind: ParamKind::Float, text: "band6_q" },
+	Parameter { kind: ParamKind::Int, text: "band7_type" },
+	Parameter { kind: ParamKind::Float, text: "band7_freq" },
+	Parameter { kind: ParamKind::Float, text: "band7_gain" },
+	Parameter { kind: ParamKind::Float, text: "band7_q" },
+	Parameter { kind: ParamKind::Int, text: "band8_type" },
+	Parameter { kind: ParamKind::Float, text: "band8_freq" },
+	Parameter { kind: ParamKind::Float, text: "band8_gain" },
+	Parameter { kind: ParamKind::Float, text: "band8_q" },
+	Parameter { kind: ParamKind::Bool, text: "linear_phase" },
+	Parameter { kind: ParamKind::Float, text: "output" },
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EqBandKind {
+	Off,
+	Bell,
+	LowShelf,
+	HighShelf,
+	LowCut,
+	HighCut,
+	Notch,
+}
+
+impl EqBandKind {
+	pub fn from_index(idx: i64) -> Self {
+		match idx {
+			0 => EqBandKind::Off,
+			1 => EqBandKind::Bell,
+			2 => EqBandKind::LowShelf,
+			3 => EqBandKind::HighShelf,
+			4 => EqBandKind::LowCut,
+			5 => EqBandKind::HighCut,
+			6 => EqBandKind::Notch,
+
+			_ => panic!("invalid EQ band type: {idx}")
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct EqBand {
+	pub kind: EqBandKind,
+	pub freq: f32,
+	pub gain: f32,
+	pub q: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Biquad {
+	b0: f32,
+	b1: f32,
+	b2: f32,
+	a1: f32,
+	a2: f32,
+}
+
+impl Biquad {
+	pub const IDENTITY: Biquad = Biquad { b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0 };
+
+	// Coefficients from the RBJ Audio EQ Cookbook
+	pub fn from_band(band: &EqBand, sample_rate: u32) -> Self {
+		let nyquist = sample_rate as f32 * 0.5;
+		let w0 = TAU * band.freq.clamp(1.0, nyquist * 0.99) / sample_rate as f32;
+		let (sin, cos) = w0.sin_cos();
+		let alpha = sin / (2.0 * band.q.max(0.01));
+		let a = 10.0f32.powf(band.gain / 40.0);
+		let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+		let (b0, b1, b2, a0, a1, a2) = match band.kind {
+			EqBandKind::Off => return Biquad::IDENTITY,
+
+			EqBandKind::Bell => (
+				1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a,
+				1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a,
+			),
+
+			EqBandKind::LowShelf => (
+				a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+				2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+				a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+				(a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+				-2.0 * ((a - 1.0) + (a + 1.0) * cos),
+				(a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+			),
+
+			EqBandKind::HighShelf => (
+				a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+				-2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+				a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+				(a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+				2.0 * ((a - 1.0) - (a + 1.0) * cos),
+				(a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+			),
+
+			EqBandKind::LowCut => (
+				(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0,
+				1.0 + alpha, -2.0 * cos, 1.0 - alpha,
+			),
+
+			EqBandKind::HighCut => (
+				(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0,
+				1.0 + alpha, -2.0 * cos, 1.0 - alpha,
+			),
+
+			EqBandKind::Notch => (
+				1.0, -2.0 * cos, 1.0,
+				1.0 + alpha, -2.0 * cos, 1.0 - alpha,
+			),
+		};
+
+		Biquad {
+			b0: b0 / a0,
+			b1: b1 / a0,
+			b2: b2 / a0,
+			a1: a1 / a0,
+			a2: a2 / a0,
+		}
+	}
+
+	pub fn magnitude(&self, freq: f32, sample_rate: u32) -> f32 {
+		let w = TAU * freq / sample_rate as f32;
+		let z1 = Complex::from_polar(1.0, -w);
+		let z2 = Complex::from_polar(1.0, -2.0 * w);
+
+		let num = Complex::new(self.b0, 0.0) + z1 * self.b1 + z2 * self.b2;
+		let den = Complex::new(1.0, 0.0) + z1 * self.a1 + z2 * self.a2;
+
+		num.norm() / den.norm()
+	}
+
+	// Transposed direct form II
+	fn process(&self, state: &mut [f32; 2], x: f32) -> f32 {
+		let y = self.b0 * x + state[0];
+
+		state[0] = self.b1 * x - self.a1 * y + state[1];
+		state[1] = self.b2 * x - self.a2 * y;
+
+		y
+	}
+}
+
+struct EqState {
+	filter_state: [[[f32; 2]; 2]; EQ_BANDS],
+	convolvers: [Convolver; 2],
+	active: usize,
+	// Frames since the inactive convolver got a new FIR, while switching over to it
+	fade: Option<usize>,
+}
+
+impl EqState {
+	fn process_linear_phase(&mut self, frame: Frame) -> Frame {
+		let active = self.active;
+		let out = self.convolvers[active].process(frame).0;
+
+		let Some(fade) = &mut self.fade else {
+			return out
+		};
+
+		let next = self.convolvers[1 - active].process(frame).0;
+		let t = (fade.saturating_sub(FIR_LENGTH) as f32 / FIR_FADE_LENGTH as f32).min(1.0);
+
+		*fade += 1;
+
+		if *fade >= FIR_LENGTH + FIR_FADE_LENGTH {
+			self.active = 1 - active;
+			self.fade = None;
+		}
+
+		out * (1.0 - t) + next * t
+	}
+}
+
+pub struct Equalizer {
+	sample_rate: u32,
+	bands: [EqBand; EQ_BANDS],
+	coefs: [Biquad; EQ_BANDS],
+	linear_phase: bool,
+	// The FIR no longer matches the bands, it's redesigned when the block is advanced
+	fir_dirty: bool,
+	output: f32,
+	state: Mutex<EqState>,
+}
+
+impl Equalizer {
+	pub fn new(sample_rate: u32) -> Self {
+		Equalizer {
+			sample_rate,
+			bands: DEFAULT_FREQS.map(|freq| EqBand {
+				kind: EqBandKind::Off,
+				freq,
+				gain: 0.0,
+				q: 0.707,
+			}),
+			coefs: [Biquad::IDENTITY; EQ_BANDS],
+			linear_phase: false,
+			fir_dirty: false,
+			output: 0.0,
+			state: Mutex::new(EqState {
+				filter_state: [[[0.0; 2]; 2]; EQ_BANDS],
+				convolvers: [Convolver::new(), Convolver::new()],
+				active: 0,
+				fade: None,
+			}),
+		}
+	}
+
+	// Combined magnitude of all bands at `freq`, not including output gain
+	pub fn magnitude(&self, freq: f32) -> f32 {
+		self.coefs
+			.iter()
+			.map(|coefs| coefs.magnitude(freq, self.sample_rate))
+			.product()
+	}
+
+	// Zero-phase FIR approximating the combined magnitude response, delayed by half its length
+	fn design_fir(&self) -> Vec<f32> {
+		let fft = Fft::new(FIR_LENGTH);
+		let mut spectrum = vec![Complex::ZERO; FIR_LENGTH];
+
+		for k in 0..=FIR_LENGTH / 2 {
+			let freq = k as f32 * self.sample_rate as f32 / FIR_LENGTH as f32;
+			let mag = self.magnitude(freq);
+
+			spectrum[k] = Complex::new(mag, 0.0);
+			spectrum[(FIR_LENGTH - k) % FIR_LENGTH] = Complex::new(mag, 0.0);
+		}
+
+		fft.inverse(&mut spectrum);
+
+		let half = FIR_LENGTH / 2;
+
+		(0..FIR_LENGTH)
+			.map(|n| {
+				let t = (n as f32 - half as f32) / half as f32;
+				spectrum[(n + half) % FIR_LENGTH].re * blackman(t)
+			})
+			.collect()
+	}
+
+	fn update_band(&mut self, band: usize) {
+		self.coefs[band] = Biquad::from_band(&self.bands[band], self.sample_rate);
+		self.state.get_mut().unwrap().filter_state[band] = [[0.0; 2]; 2];
+	}
+
+	// Loads a new FIR into the active convolver right away
+	fn load_fir(&mut self) {
+		let fir = self.design_fir();
+		let state = self.state.get_mut().unwrap();
+
+		state.convolvers[state.active].load_ir([fir.clone(), fir]);
+		state.fade = None;
+		self.fir_dirty = false;
+	}
+
+	// Loads a new FIR into the inactive convolver and starts switching over to it,
+	// once any switch still in progress is done
+	fn crossfade_fir(&mut self) {
+		if self.state.get_mut().unwrap().fade.is_some() {
+			return
+		}
+
+		let fir = self.design_fir();
+		let state = self.state.get_mut().unwrap();
+
+		state.convolvers[1 - state.active].load_ir([fir.clone(), fir]);
+		state.fade = Some(0);
+		self.fir_dirty = false;
+	}
+}
+
+impl Effect for Equalizer {
+	fn render_effect(&self, mut buffer: BufferAccess) {
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+		let output = db_to_amp(self.output);
+
+		for f in buffer.audio_mut().unwrap() {
+			let out = if self.linear_phase {
+				state.process_linear_phase(*f)
+			} else {
+				let mut out = *f;
+
+				for (band, coefs) in self.coefs.iter().enumerate() {
+					if self.bands[band].kind == EqBandKind::Off {
+						continue
+					}
+
+					let [state_l, state_r] = &mut state.filter_state[band];
+
+					out = Frame(coefs.process(state_l, out.0), coefs.process(state_r, out.1));
+				}
+
+				out
+			};
+
+			*f = out * output;
+		}
+	}
+
+	fn advance_effect(&mut self, _: usize, _: &Config) {
+		if self.linear_phase && self.fir_dirty {
+			self.crossfade_fir();
+		}
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&EQ_PARAMS
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		if param >= EQ_BANDS * PARAMS_PER_BAND {
+			return match param - EQ_BANDS * PARAMS_PER_BAND {
+				0 => Some(ParamValue::Bool(false)),
+				1 => Some(ParamValue::Float(0.0)),
+
+				_ => None
+			}
+		}
+
+		let band = param / PARAMS_PER_BAND;
+
+		match param % PARAMS_PER_BAND {
+			0 => Some(ParamValue::Int(0)),
+			1 => Some(ParamValue::Float(DEFAULT_FREQS[band] as f64)),
+			2 => Some(ParamValue::Float(0.0)),
+			3 => Some(ParamValue::Float(0.707)),
+
+			_ => unreachable!()
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		if param >= EQ_BANDS * PARAMS_PER_BAND {
+			match (param - EQ_BANDS * PARAMS_PER_BAND, value) {
+				(0, ParamValue::Bool(linear_phase)) => {
+					// Switching modes jumps in latency anyway, so there's nothing to fade from
+					if *linear_phase && !self.linear_phase {
+						self.load_fir();
+					}
+
+					self.linear_phase = *linear_phase;
+				}
+
+				(1, ParamValue::Float(output)) => self.output = *output as f32,
+
+				_ => panic!()
+			}
+
+			return
+		}
+
+		let band = param / PARAMS_PER_BAND;
+
+		match (param % PARAMS_PER_BAND, value) {
+			(0, ParamValue::Int(kind)) => self.bands[band].kind = EqBandKind::from_index(*kind),
+			(1, ParamValue::Float(freq)) => self.bands[band].freq = (*freq as f32).max(1.0),
+			(2, ParamValue::Float(gain)) => self.bands[band].gain = *gain as f32,
+			(3, ParamValue::Float(q)) => self.bands[band].q = (*q as f32).max(0.01),
+
+			_ => panic!()
+		}
+
+		self.update_band(band);
+		self.fir_dirty = true;
+	}
+
+	// Supported queries:
+	//   "get_magnitude", freq          -> combined response in dB, including output gain
+	//   "get_band_magnitude", band, freq -> response of a single band in dB
+	fn query(&self, keys: &[ParamValue]) -> Option<ParamValue> {
+		let [ParamValue::String(request), args @ ..] = keys else {
+			return None
+		};
+
+		match request.as_str() {
+			"get_magnitude" => {
+				let [ParamValue::Float(freq)] = args else {
+					return None
+				};
+
+				let db = amp_to_db(self.magnitude(*freq as f32)) + self.output;
+
+				Some(ParamValue::Float(db as f64))
+			}
+
+			"get_band_magnitude" => {
+				let [ParamValue::Int(band), ParamValue::Float(freq)] = args else {
+					return None
+				};
+
+				let coefs = self.coefs.get(*band as usize)?;
+
+				Some(ParamValue::Float(amp_to_db(coefs.magnitude(*freq as f32, self.sample_rate)) as f64))
+			}
+
+			_ => None
+		}
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Equalizer"
+	}
+}
@@ -1,20 +1,87 @@
 use crate::{engine::{Engine, Frame}, midi::{MidiMessage, MidiStatusByte}, node::NodeUtil, param::{ParamKind, ParamValue, Parameter}};
 
-use super::{BufferAccess, BusKind, Node, NodeInstance};
+use super::{multichannel::MAX_CHANNELS, BufferAccess, BusKind, Node, NodeInstance};
 
 
-pub struct Source;
-pub struct Sink;
+// Plays audio from the external feed named by the `input` parameter (see `Engine::add_audio_feed()`).
+// Outputs silence while the name is empty or no such feed exists.
+pub struct Source {
+	input: String,
+}
+
+impl Source {
+	pub fn new() -> Self {
+		Source {
+			input: String::new(),
+		}
+	}
+}
+
+impl Default for Source {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+// The engine's output. Its `channels` parameter sets the width of the input bus,
+// which should match the output device (see `Engine::set_output_channels()`).
+pub struct Sink {
+	inputs: [BusKind; 1],
+	layout_changed: bool,
+}
+
+impl Sink {
+	pub fn new() -> Self {
+		Sink {
+			inputs: [BusKind::Audio],
+			layout_changed: false,
+		}
+	}
+}
+
+impl Default for Sink {
+	fn default() -> Self {
+		Self::new()
+	}
+}
 
 impl Node for Sink {
 	fn get_inputs(&self) -> &[BusKind] {
-		&[BusKind::Audio]
+		&self.inputs
 	}
 
 	fn get_name(&self) -> &'static str {
 		"Sink"
 	}
 
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Int,
+				text: "channels",
+			}
+		]
+	}
+
+	fn get_param_default_value(&self, _: usize) -> Option<ParamValue> {
+		Some(ParamValue::Int(2))
+	}
+
+	fn take_layout_changed(&mut self) -> bool {
+		std::mem::take(&mut self.layout_changed)
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let (0, ParamValue::Int(channels)) = (param, value) else {
+			panic!()
+		};
+
+		let kind = BusKind::audio((*channels).clamp(1, MAX_CHANNELS as i64) as usize);
+
+		self.layout_changed = kind != self.inputs[0];
+		self.inputs[0] = kind;
+	}
+
 	fn render(&self, _: usize, mut buffer: BufferAccess, instance: &NodeInstance, engine: &Engine) {
 		self.poll_input_into_buffer(0, &mut buffer, instance, engine);
 	}
@@ -29,12 +96,19 @@ impl Node for Source {
 		"Source"
 	}
 	
-	fn render(&self, _: usize, buffer: BufferAccess, _: &NodeInstance, _: &Engine) {
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&["out"]
+	}
+
+	fn render(&self, _: usize, buffer: BufferAccess, _: &NodeInstance, engine: &Engine) {
 		let BufferAccess::Audio(buffer) = buffer else {
 			panic!()
 		};
 
-		buffer.fill(Frame(0.0f32, 0.0f32));
+		match engine.get_audio_feed(&self.input) {
+			Some(feed) => feed.lock().unwrap().read(buffer),
+			None => buffer.fill(Frame(0.0f32, 0.0f32)),
+		}
 	}
 
 	fn get_params(&self) -> &[Parameter] { 
@@ -53,9 +127,7 @@ impl Node for Source {
 			panic!()
 		};
 
-		if string != "" {
-			todo!()
-		}
+		self.input = string.clone();
 	}
 }
 
@@ -134,7 +206,15 @@ impl Node for MidiSplit {
 			.for_each(|(b, m)| {
 				
 				for msg in m {
-					if msg.status_byte().channel() != output as u8 {
+					let status = msg.status_byte();
+
+					// System messages go to every output
+					if !status.is_channel_message() {
+						b.push(*msg);
+						continue
+					}
+
+					if status.channel() != output as u8 {
 						continue
 					}
 
@@ -142,7 +222,7 @@ impl Node for MidiSplit {
 						b.push(*msg);
 					} else {
 						b.push(MidiMessage::new(
-							MidiStatusByte::new(msg.status_byte().code(), 0),
+							MidiStatusByte(status.0 & 0xF0),
 							[msg.data()[1], msg.data()[2]]
 						));
 					}
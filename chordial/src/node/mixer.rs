@@ -0,0 +1,241 @@
+use std::sync::Mutex;
+
+use crate::{engine::{Config, Engine, Frame}, param::{ParamKind, ParamValue, Parameter}, util::db_to_amp};
+
+use super::{stereo::PanLaw, BlockCache, BufferAccess, BusKind, Node, NodeInstance, NodeUtil};
+
+
+pub const MAX_MIXER_CHANNELS: usize = 16;
+pub const MAX_MIXER_SENDS: usize = 4;
+
+const GLOBAL_PARAMS: usize = 3;
+const PARAMS_PER_CHANNEL: usize = 4 + MAX_MIXER_SENDS;
+
+macro_rules! mixer_params {
+	($($n:literal)*) => {
+		[
+			Parameter { kind: ParamKind::Int, text: "channels" },
+			Parameter { kind: ParamKind::Int, text: "sends" },
+			Parameter { kind: ParamKind::Float, text: "master_gain" },
+			$(
+				Parameter { kind: ParamKind::Float, text: concat!("ch", $n, "_gain") },
+				Parameter { kind: ParamKind::Float, text: concat!("ch", $n, "_pan") },
+				Parameter { kind: ParamKind::Bool, text: concat!("ch", $n, "_mute") },
+				Parameter { kind: ParamKind::Bool, text: concat!("ch", $n, "_solo") },
+				Parameter { kind: ParamKind::Float, text: concat!("ch", $n, "_send1") },
+				Parameter { kind: ParamKind::Float, text: concat!("ch", $n, "_send2") },
+				Parameter { kind: ParamKind::Float, text: concat!("ch", $n, "_send3") },
+				Parameter { kind: ParamKind::Float, text: concat!("ch", $n, "_send4") },
+			)*
+		]
+	};
+}
+
+macro_rules! channel_names {
+	($($n:literal)*) => {
+		[$(concat!("ch", $n)),*]
+	};
+}
+
+const MIXER_PARAMS: [Parameter; GLOBAL_PARAMS + MAX_MIXER_CHANNELS * PARAMS_PER_CHANNEL] =
+	mixer_params!(1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16);
+
+const MIXER_INPUT_NAMES: [&str; MAX_MIXER_CHANNELS] =
+	channel_names!(1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16);
+
+const MIXER_OUTPUT_NAMES: [&str; MAX_MIXER_SENDS + 1] = ["master", "aux1", "aux2", "aux3", "aux4"];
+
+const MIXER_INPUTS: [BusKind; MAX_MIXER_CHANNELS] = [BusKind::Audio; MAX_MIXER_CHANNELS];
+const MIXER_OUTPUTS: [BusKind; MAX_MIXER_SENDS + 1] = [BusKind::Audio; MAX_MIXER_SENDS + 1];
+
+
+#[derive(Debug, Copy, Clone)]
+pub struct MixerChannel {
+	pub gain: f32,
+	pub pan: f32,
+	pub mute: bool,
+	pub solo: bool,
+	pub sends: [f32; MAX_MIXER_SENDS],
+}
+
+impl Default for MixerChannel {
+	fn default() -> Self {
+		MixerChannel {
+			gain: 0.0,
+			pan: 0.0,
+			mute: false,
+			solo: false,
+			sends: [0.0; MAX_MIXER_SENDS],
+		}
+	}
+}
+
+// Sums N stereo channels into a master output and up to four post-fader aux sends.
+//
+// Changing the `channels` or `sends` parameter changes the number of inputs and outputs.
+pub struct Mixer {
+	channel_count: usize,
+	send_count: usize,
+	layout_changed: bool,
+	master_gain: f32,
+	channels: [MixerChannel; MAX_MIXER_CHANNELS],
+	block: usize,
+	// Each channel's input, summed once per block and shared by all outputs
+	inputs: Mutex<[BlockCache; MAX_MIXER_CHANNELS]>,
+}
+
+impl Mixer {
+	pub fn new() -> Self {
+		Mixer {
+			channel_count: 4,
+			send_count: 2,
+			layout_changed: false,
+			master_gain: 0.0,
+			channels: [MixerChannel::default(); MAX_MIXER_CHANNELS],
+			block: 0,
+			inputs: Mutex::new(std::array::from_fn(|_| BlockCache::new())),
+		}
+	}
+
+	pub fn channel(&self, channel: usize) -> &MixerChannel {
+		&self.channels[channel]
+	}
+
+	fn any_solo(&self) -> bool {
+		self.channels[..self.channel_count].iter().any(|ch| ch.solo)
+	}
+}
+
+impl Default for Mixer {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Node for Mixer {
+	fn get_name(&self) -> &'static str {
+		"Mixer"
+	}
+
+	fn get_inputs(&self) -> &[BusKind] {
+		&MIXER_INPUTS[..self.channel_count]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&MIXER_OUTPUTS[..self.send_count + 1]
+	}
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&MIXER_INPUT_NAMES[..self.channel_count]
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&MIXER_OUTPUT_NAMES[..self.send_count + 1]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&MIXER_PARAMS[..GLOBAL_PARAMS + self.channel_count * PARAMS_PER_CHANNEL]
+	}
+
+	fn take_layout_changed(&mut self) -> bool {
+		std::mem::take(&mut self.layout_changed)
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => return Some(ParamValue::Int(4)),
+			1 => return Some(ParamValue::Int(2)),
+			2 => return Some(ParamValue::Float(0.0)),
+
+			_ => {}
+		}
+
+		match (param - GLOBAL_PARAMS) % PARAMS_PER_CHANNEL {
+			0 | 1 => Some(ParamValue::Float(0.0)),
+			2 | 3 => Some(ParamValue::Bool(false)),
+			_ => Some(ParamValue::Float(0.0)),
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		match (param, value) {
+			(0, ParamValue::Int(count)) => {
+				self.channel_count = (*count).clamp(1, MAX_MIXER_CHANNELS as i64) as usize;
+				self.layout_changed = true;
+				return
+			}
+
+			(1, ParamValue::Int(count)) => {
+				self.send_count = (*count).clamp(0, MAX_MIXER_SENDS as i64) as usize;
+				self.layout_changed = true;
+				return
+			}
+
+			(2, ParamValue::Float(gain)) => {
+				self.master_gain = *gain as f32;
+				return
+			}
+
+			_ => {}
+		}
+
+		let channel = &mut self.channels[(param - GLOBAL_PARAMS) / PARAMS_PER_CHANNEL];
+
+		match ((param - GLOBAL_PARAMS) % PARAMS_PER_CHANNEL, value) {
+			(0, ParamValue::Float(gain)) => channel.gain = *gain as f32,
+			(1, ParamValue::Float(pan)) => channel.pan = (*pan as f32).clamp(-1.0, 1.0),
+			(2, ParamValue::Bool(mute)) => channel.mute = *mute,
+			(3, ParamValue::Bool(solo)) => channel.solo = *solo,
+			(send, ParamValue::Float(level)) => channel.sends[send - 4] = (*level as f32).max(0.0),
+
+			_ => panic!()
+		}
+	}
+
+	fn render(
+		&self,
+		output: usize,
+		mut buffer: BufferAccess,
+		instance: &NodeInstance,
+		engine: &Engine
+	) {
+		let any_solo = self.any_solo();
+		let audio = buffer.audio_mut().unwrap();
+		let mut inputs = self.inputs.lock().unwrap();
+
+		for (idx, channel) in self.channels[..self.channel_count].iter().enumerate() {
+			if channel.mute || (any_solo && !channel.solo) {
+				continue
+			}
+
+			// Master output applies the master fader, sends are taken post-fader (pre-master)
+			let level = match output {
+				0 => db_to_amp(channel.gain) * db_to_amp(self.master_gain),
+				send => db_to_amp(channel.gain) * channel.sends[send - 1],
+			};
+
+			if level == 0.0 {
+				continue
+			}
+
+			let input = &mut inputs[idx];
+
+			if input.prepare(self.block, audio.len()) {
+				if let Some(buf) = self.poll_input(idx, audio.len(), instance, engine) {
+					input.audio.copy_from_slice(buf.audio().unwrap());
+				}
+			}
+
+			// Balance for stereo sources, unity at center
+			let (pan_l, pan_r) = PanLaw::Linear.gains(channel.pan);
+
+			for (out, f) in audio.iter_mut().zip(&input.audio) {
+				*out += Frame(f.0 * pan_l, f.1 * pan_r) * level;
+			}
+		}
+	}
+
+	fn advance(&mut self, _frames: usize, _config: &Config) {
+		self.block += 1;
+	}
+}
@@ -0,0 +1,346 @@
+use std::{f32::consts::{PI, TAU}, sync::Mutex};
+
+use crate::{engine::{Config, Frame}, param::{ParamKind, ParamValue, Parameter}, util::DelayLine};
+
+use super::{effect::{control_or, Effect}, BufferAccess};
+
+
+const MAX_DELAY_MS: f32 = 60.0;
+const MAX_PHASER_STAGES: usize = 12;
+
+// LFO values for both channels. `spread` offsets the right channel's phase by up to 180 degrees.
+fn stereo_lfo(phase: f32, spread: f32) -> (f32, f32) {
+	(
+		(TAU * phase).sin(),
+		(TAU * (phase + spread * 0.5)).sin(),
+	)
+}
+
+fn advance_phase(phase: &mut f32, rate: f32, sample_rate: u32) {
+	*phase = (*phase + rate / sample_rate as f32).rem_euclid(1.0);
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ModulatedDelayKind {
+	Chorus,
+	Flanger,
+}
+
+struct ModulatedDelayState {
+	phase: f32,
+	lines: (DelayLine, DelayLine),
+	feedback: Frame,
+}
+
+// Chorus and flanger: a short delay line per channel, swept by an LFO
+pub struct ModulatedDelay {
+	kind: ModulatedDelayKind,
+	sample_rate: u32,
+	rate: f32,
+	depth: f32,
+	delay: f32,
+	feedback: f32,
+	spread: f32,
+	mix: f32,
+	state: Mutex<ModulatedDelayState>,
+}
+
+impl ModulatedDelay {
+	pub fn new(kind: ModulatedDelayKind, sample_rate: u32) -> Self {
+		let [rate, depth, delay, feedback, spread, mix] = Self::defaults(kind);
+		let max_delay = (MAX_DELAY_MS / 1000.0 * sample_rate as f32) as usize + 2;
+
+		ModulatedDelay {
+			kind,
+			sample_rate,
+			rate,
+			depth,
+			delay,
+			feedback,
+			spread,
+			mix,
+			state: Mutex::new(ModulatedDelayState {
+				phase: 0.0,
+				lines: (DelayLine::new(max_delay), DelayLine::new(max_delay)),
+				feedback: Frame::ZERO,
+			}),
+		}
+	}
+
+	fn defaults(kind: ModulatedDelayKind) -> [f32; 6] {
+		match kind {
+			ModulatedDelayKind::Chorus => [0.8, 0.5, 10.0, 0.0, 0.5, 0.5],
+			ModulatedDelayKind::Flanger => [0.25, 0.7, 0.5, 0.5, 0.5, 0.5],
+		}
+	}
+
+	// How far the LFO sweeps the delay time at full depth, in ms
+	fn sweep(&self) -> f32 {
+		match self.kind {
+			ModulatedDelayKind::Chorus => 8.0,
+			ModulatedDelayKind::Flanger => 5.0,
+		}
+	}
+}
+
+impl Effect for ModulatedDelay {
+	fn render_effect(&self, buffer: BufferAccess) {
+		self.render_effect_modulated(buffer, &[]);
+	}
+
+	fn render_effect_modulated(&self, mut buffer: BufferAccess, controls: &[Option<&[f32]>]) {
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+		let ms_to_samples = self.sample_rate as f32 / 1000.0;
+
+		for (i, f) in buffer.audio_mut().unwrap().iter_mut().enumerate() {
+			let rate = control_or(controls, 0, i, self.rate);
+			let depth = control_or(controls, 1, i, self.depth).clamp(0.0, 1.0);
+			let (lfo_l, lfo_r) = stereo_lfo(state.phase, self.spread);
+
+			let delay_l = (self.delay + depth * self.sweep() * (lfo_l + 1.0) * 0.5) * ms_to_samples;
+			let delay_r = (self.delay + depth * self.sweep() * (lfo_r + 1.0) * 0.5) * ms_to_samples;
+
+			let wet = Frame(state.lines.0.read_frac(delay_l), state.lines.1.read_frac(delay_r));
+
+			state.lines.0.write(f.0 + state.feedback.0 * self.feedback);
+			state.lines.1.write(f.1 + state.feedback.1 * self.feedback);
+			state.feedback = wet;
+
+			*f = *f * (1.0 - self.mix) + wet * self.mix;
+
+			advance_phase(&mut state.phase, rate, self.sample_rate);
+		}
+	}
+
+	fn advance_effect(&mut self, _: usize, _: &Config) { }
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in", "rate", "depth"]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "rate",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "depth",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "delay",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "feedback",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "spread",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "mix",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		Self::defaults(self.kind)
+			.get(param)
+			.map(|value| ParamValue::Float(*value as f64))
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let ParamValue::Float(value) = value else {
+			panic!()
+		};
+
+		let value = *value as f32;
+
+		match param {
+			0 => self.rate = value.max(0.0),
+			1 => self.depth = value.clamp(0.0, 1.0),
+			2 => self.delay = value.clamp(0.0, MAX_DELAY_MS - self.sweep()),
+			3 => self.feedback = value.clamp(-0.95, 0.95),
+			4 => self.spread = value.clamp(0.0, 1.0),
+			5 => self.mix = value.clamp(0.0, 1.0),
+
+			_ => panic!()
+		}
+	}
+
+	fn get_name(&self) -> &'static str {
+		match self.kind {
+			ModulatedDelayKind::Chorus => "Chorus",
+			ModulatedDelayKind::Flanger => "Flanger",
+		}
+	}
+}
+
+
+struct PhaserState {
+	phase: f32,
+	allpass: [[f32; MAX_PHASER_STAGES]; 2],
+	feedback: Frame,
+}
+
+// Chain of first-order allpass filters whose break frequency is swept by an LFO
+pub struct Phaser {
+	sample_rate: u32,
+	rate: f32,
+	depth: f32,
+	stages: usize,
+	feedback: f32,
+	spread: f32,
+	mix: f32,
+	state: Mutex<PhaserState>,
+}
+
+impl Phaser {
+	const CENTER_FREQ: f32 = 1000.0;
+	const SWEEP_OCTAVES: f32 = 3.0;
+
+	pub fn new(sample_rate: u32) -> Self {
+		Phaser {
+			sample_rate,
+			rate: 0.5,
+			depth: 0.7,
+			stages: 4,
+			feedback: 0.3,
+			spread: 0.5,
+			mix: 0.5,
+			state: Mutex::new(PhaserState {
+				phase: 0.0,
+				allpass: [[0.0; MAX_PHASER_STAGES]; 2],
+				feedback: Frame::ZERO,
+			}),
+		}
+	}
+
+	fn allpass_coefficient(&self, lfo: f32, depth: f32) -> f32 {
+		let freq = Self::CENTER_FREQ * 2.0f32.powf(lfo * depth * Self::SWEEP_OCTAVES);
+		let freq = freq.min(self.sample_rate as f32 * 0.45);
+		let t = (PI * freq / self.sample_rate as f32).tan();
+
+		(t - 1.0) / (t + 1.0)
+	}
+
+	fn process_channel(stages: &mut [f32], coef: f32, mut x: f32) -> f32 {
+		for s in stages.iter_mut() {
+			let y = coef * x + *s;
+
+			*s = x - coef * y;
+			x = y;
+		}
+
+		x
+	}
+}
+
+impl Effect for Phaser {
+	fn render_effect(&self, buffer: BufferAccess) {
+		self.render_effect_modulated(buffer, &[]);
+	}
+
+	fn render_effect_modulated(&self, mut buffer: BufferAccess, controls: &[Option<&[f32]>]) {
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+
+		for (i, f) in buffer.audio_mut().unwrap().iter_mut().enumerate() {
+			let rate = control_or(controls, 0, i, self.rate);
+			let depth = control_or(controls, 1, i, self.depth).clamp(0.0, 1.0);
+			let (lfo_l, lfo_r) = stereo_lfo(state.phase, self.spread);
+
+			let [allpass_l, allpass_r] = &mut state.allpass;
+
+			let wet = Frame(
+				Self::process_channel(
+					&mut allpass_l[..self.stages],
+					self.allpass_coefficient(lfo_l, depth),
+					f.0 + state.feedback.0 * self.feedback
+				),
+				Self::process_channel(
+					&mut allpass_r[..self.stages],
+					self.allpass_coefficient(lfo_r, depth),
+					f.1 + state.feedback.1 * self.feedback
+				),
+			);
+
+			state.feedback = wet;
+
+			*f = *f * (1.0 - self.mix) + wet * self.mix;
+
+			advance_phase(&mut state.phase, rate, self.sample_rate);
+		}
+	}
+
+	fn advance_effect(&mut self, _: usize, _: &Config) { }
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in", "rate", "depth"]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "rate",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "depth",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "stages",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "feedback",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "spread",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "mix",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Float(0.5)),
+			1 => Some(ParamValue::Float(0.7)),
+			2 => Some(ParamValue::Int(4)),
+			3 => Some(ParamValue::Float(0.3)),
+			4 => Some(ParamValue::Float(0.5)),
+			5 => Some(ParamValue::Float(0.5)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		match (param, value) {
+			(0, ParamValue::Float(rate)) => self.rate = (*rate as f32).max(0.0),
+			(1, ParamValue::Float(depth)) => self.depth = (*depth as f32).clamp(0.0, 1.0),
+			(2, ParamValue::Int(stages)) => self.stages = (*stages).clamp(1, MAX_PHASER_STAGES as i64) as usize,
+			(3, ParamValue::Float(feedback)) => self.feedback = (*feedback as f32).clamp(-0.95, 0.95),
+			(4, ParamValue::Float(spread)) => self.spread = (*spread as f32).clamp(0.0, 1.0),
+			(5, ParamValue::Float(mix)) => self.mix = (*mix as f32).clamp(0.0, 1.0),
+
+			_ => panic!()
+		}
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Phaser"
+	}
+}
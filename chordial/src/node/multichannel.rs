@@ -0,0 +1,296 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use crate::{engine::{Engine, Frame}, param::{ParamKind, ParamValue, Parameter}};
+
+use super::{Buffer, BufferAccess, BusKind, Node, NodeInstance, NodeUtil};
+
+
+pub const MAX_CHANNELS: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Speaker {
+	Left,
+	Right,
+	Center,
+	Lfe,
+	SurroundLeft,
+	SurroundRight,
+}
+
+// Known speaker layouts are converted with the usual up/downmix coefficients,
+// any other channel count is treated as discrete channels (e.g. ambisonics)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChannelLayout {
+	Mono,
+	Stereo,
+	Quad,
+	// L, R, C, LFE, Ls, Rs
+	Surround51,
+	Discrete(usize),
+}
+
+impl ChannelLayout {
+	pub fn from_channels(channels: usize) -> Self {
+		match channels {
+			1 => ChannelLayout::Mono,
+			2 => ChannelLayout::Stereo,
+			4 => ChannelLayout::Quad,
+			6 => ChannelLayout::Surround51,
+			n => ChannelLayout::Discrete(n),
+		}
+	}
+
+	pub fn from_bus_kind(kind: BusKind) -> Option<Self> {
+		kind.channels().map(Self::from_channels)
+	}
+
+	pub fn channels(&self) -> usize {
+		match self {
+			ChannelLayout::Mono => 1,
+			ChannelLayout::Stereo => 2,
+			ChannelLayout::Quad => 4,
+			ChannelLayout::Surround51 => 6,
+			ChannelLayout::Discrete(n) => *n,
+		}
+	}
+
+	pub fn bus_kind(&self) -> BusKind {
+		BusKind::audio(self.channels())
+	}
+
+	fn speakers(&self) -> Option<&'static [Speaker]> {
+		use Speaker::*;
+
+		match self {
+			ChannelLayout::Mono => Some(&[Center]),
+			ChannelLayout::Stereo => Some(&[Left, Right]),
+			ChannelLayout::Quad => Some(&[Left, Right, SurroundLeft, SurroundRight]),
+			ChannelLayout::Surround51 => Some(&[Left, Right, Center, Lfe, SurroundLeft, SurroundRight]),
+			ChannelLayout::Discrete(_) => None,
+		}
+	}
+}
+
+// Gain from a source speaker to a destination speaker, for speakers missing in the destination layout
+fn fold_gain(from: Speaker, to: Speaker, from_mono: bool) -> f32 {
+	use Speaker::*;
+
+	match (from, to) {
+		// Mono sources are duplicated at full level, like every generator does for stereo
+		(Center, Left | Right) if from_mono => 1.0,
+		(Center, Left | Right) => FRAC_1_SQRT_2,
+		(Left | Right, Center) => 0.5,
+		(SurroundLeft, Left) | (SurroundRight, Right) => FRAC_1_SQRT_2,
+		(SurroundLeft | SurroundRight, Center) => 0.5 * FRAC_1_SQRT_2,
+
+		_ => 0.0,
+	}
+}
+
+// Row-major `to x from` gain matrix for converting between two channel layouts
+pub struct ChannelMatrix {
+	from: usize,
+	to: usize,
+	gains: Vec<f32>,
+}
+
+impl ChannelMatrix {
+	pub fn new(from: ChannelLayout, to: ChannelLayout) -> Self {
+		let (in_count, out_count) = (from.channels(), to.channels());
+		let mut gains = vec![0.0; in_count * out_count];
+
+		match (from.speakers(), to.speakers()) {
+			(Some(from_speakers), Some(to_speakers)) => {
+				for (i, src) in from_speakers.iter().enumerate() {
+					if let Some(o) = to_speakers.iter().position(|dst| dst == src) {
+						gains[o * in_count + i] = 1.0;
+						continue
+					}
+
+					for (o, dst) in to_speakers.iter().enumerate() {
+						gains[o * in_count + i] = fold_gain(*src, *dst, from == ChannelLayout::Mono);
+					}
+				}
+			}
+
+			// Discrete channels are passed through one-to-one, extra ones are dropped or silent
+			_ => {
+				for c in 0..in_count.min(out_count) {
+					gains[c * in_count + c] = 1.0;
+				}
+			}
+		}
+
+		ChannelMatrix {
+			from: in_count,
+			to: out_count,
+			gains,
+		}
+	}
+
+	pub fn input_channels(&self) -> usize {
+		self.from
+	}
+
+	pub fn output_channels(&self) -> usize {
+		self.to
+	}
+
+	pub fn process_frame(&self, input: &[f32], output: &mut [f32]) {
+		for (out, row) in output.iter_mut().zip(self.gains.chunks_exact(self.from)) {
+			*out = row.iter().zip(input).map(|(g, x)| g * x).sum();
+		}
+	}
+
+	// Like `process_frame()`, but adds onto `output`
+	pub fn mix_frame(&self, input: &[f32], output: &mut [f32]) {
+		for (out, row) in output.iter_mut().zip(self.gains.chunks_exact(self.from)) {
+			*out += row.iter().zip(input).map(|(g, x)| g * x).sum::<f32>();
+		}
+	}
+
+	// Converts interleaved samples, `output` must hold as many frames as `input`
+	pub fn process(&self, input: &[f32], output: &mut [f32]) {
+		for (i, o) in input.chunks_exact(self.from).zip(output.chunks_exact_mut(self.to)) {
+			self.process_frame(i, o);
+		}
+	}
+
+	pub fn process_to_stereo(&self, input: &[f32], output: &mut [Frame]) {
+		let mut frame = [0.0; 2];
+
+		for (i, o) in input.chunks_exact(self.from).zip(output.iter_mut()) {
+			self.process_frame(i, &mut frame);
+			*o = Frame(frame[0], frame[1]);
+		}
+	}
+
+	pub fn process_from_stereo(&self, input: &[Frame], output: &mut [f32]) {
+		for (i, o) in input.iter().zip(output.chunks_exact_mut(self.to)) {
+			self.process_frame(&[i.0, i.1], o);
+		}
+	}
+}
+
+
+// Converts between channel layouts. Setting `in_channels` or `out_channels`
+// changes the input or output bus; 2 channels is the regular stereo Audio bus.
+pub struct ChannelConvert {
+	inputs: [BusKind; 1],
+	outputs: [BusKind; 1],
+	matrix: ChannelMatrix,
+	layout_changed: bool,
+}
+
+impl ChannelConvert {
+	pub fn new() -> Self {
+		ChannelConvert {
+			inputs: [BusKind::Audio],
+			outputs: [BusKind::Audio],
+			matrix: ChannelMatrix::new(ChannelLayout::Stereo, ChannelLayout::Stereo),
+			layout_changed: false,
+		}
+	}
+
+	fn update_matrix(&mut self) {
+		self.matrix = ChannelMatrix::new(
+			ChannelLayout::from_bus_kind(self.inputs[0]).unwrap(),
+			ChannelLayout::from_bus_kind(self.outputs[0]).unwrap(),
+		);
+
+		self.layout_changed = true;
+	}
+}
+
+impl Default for ChannelConvert {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Node for ChannelConvert {
+	fn get_name(&self) -> &'static str {
+		"Channel Convert"
+	}
+
+	fn get_inputs(&self) -> &[BusKind] {
+		&self.inputs
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&self.outputs
+	}
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in"]
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&["out"]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Int,
+				text: "in_channels",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "out_channels",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 | 1 => Some(ParamValue::Int(2)),
+
+			_ => None
+		}
+	}
+
+	fn take_layout_changed(&mut self) -> bool {
+		std::mem::take(&mut self.layout_changed)
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let ParamValue::Int(channels) = value else {
+			panic!()
+		};
+
+		let kind = BusKind::audio((*channels).clamp(1, MAX_CHANNELS as i64) as usize);
+
+		match param {
+			0 => self.inputs[0] = kind,
+			1 => self.outputs[0] = kind,
+
+			_ => panic!()
+		}
+
+		self.update_matrix();
+	}
+
+	fn render(
+		&self,
+		_output: usize,
+		mut buffer: BufferAccess,
+		instance: &NodeInstance,
+		engine: &Engine
+	) {
+		let len = buffer.len();
+
+		let Some(input) = self.poll_input(0, len, instance, engine) else {
+			return
+		};
+
+		match (&*input, &mut buffer) {
+			(Buffer::Audio(input), BufferAccess::Audio(output)) => output.copy_from_slice(&input[..len]),
+			(Buffer::Audio(input), BufferAccess::Multichannel(_, output)) => self.matrix.process_from_stereo(input, output),
+			(Buffer::Multichannel(_, input), BufferAccess::Audio(output)) => self.matrix.process_to_stereo(input, output),
+			(Buffer::Multichannel(_, input), BufferAccess::Multichannel(_, output)) => self.matrix.process(input, output),
+
+			_ => panic!("channel converter input must be an audio bus")
+		}
+	}
+}
@@ -1,20 +1,91 @@
 use std::{f64::consts::TAU, sync::Mutex};
 
-use crate::{engine::{Config, Engine}, midi::{MonoVoiceTracker, PolyVoiceTracker}, param::{ParamKind, ParamValue, Parameter}, util};
+use crate::{engine::{Config, Engine, Frame}, midi::{MidiVoiceDesc, MonoVoiceTracker, MpeConfig, PolyVoiceTracker, VoiceStealing, DEFAULT_BEND_RANGE}, param::{ParamKind, ParamValue, Parameter}, resource::{ResourceHandle, ResourceHandleDyn}, transport::TransportEvent, tuning::Tuning, util};
+
+use super::{BlockCache, BufferAccess, BusKind, Node, NodeInstance, NodeUtil};
+
+
+pub(crate) const BEND_RANGE_PARAM: Parameter = Parameter {
+	kind: ParamKind::Float,
+	text: "bend_range",
+};
+
+// Parameters shared by the poly instruments
+pub(crate) const POLY_INSTRUMENT_PARAMS: &[Parameter] = &[
+	BEND_RANGE_PARAM,
+	Parameter {
+		kind: ParamKind::Bool,
+		text: "mpe",
+	},
+	Parameter {
+		kind: ParamKind::Int,
+		text: "polyphony",
+	},
+	Parameter {
+		kind: ParamKind::Int,
+		text: "voice_stealing",
+	},
+	Parameter {
+		kind: ParamKind::Bool,
+		text: "retrigger",
+	},
+];
+
+// Outputs shared by the pitched instruments: audio, and the pressure
+// (aftertouch) of the held notes as a modulation source
+pub(crate) const INSTRUMENT_OUTPUTS: &[BusKind] = &[BusKind::Audio, BusKind::Control];
+pub(crate) const INSTRUMENT_OUTPUT_NAMES: &[&str] = &["out", "pressure"];
+
+pub(crate) fn poly_instrument_param_default(param: usize) -> Option<ParamValue> {
+	match param {
+		0 => Some(ParamValue::Float(DEFAULT_BEND_RANGE as f64)),
+		1 => Some(ParamValue::Bool(false)),
+		2 => Some(ParamValue::Int(0)),
+		3 => Some(ParamValue::Int(0)),
+		4 => Some(ParamValue::Bool(true)),
+
+		_ => None
+	}
+}
+
+pub(crate) fn update_poly_instrument_param(tracker: &mut PolyVoiceTracker, param: usize, value: &ParamValue) {
+	match (param, value) {
+		(0, ParamValue::Float(range)) => tracker.channels.bend_range = *range as f32,
+		(1, ParamValue::Bool(mpe)) => tracker.channels.mpe = mpe.then(MpeConfig::new),
+		(2, ParamValue::Int(polyphony)) => tracker.polyphony = (*polyphony).clamp(0, u8::MAX as i64) as u8,
+		(3, ParamValue::Int(policy)) => tracker.stealing = VoiceStealing::from_index(*policy),
+		(4, ParamValue::Bool(retrigger)) => tracker.retrigger = *retrigger,
+
+		_ => panic!()
+	}
+}
 
-use super::{BufferAccess, BusKind, Node, NodeInstance, NodeUtil};
+// Frequency of a voice's note in the tuning, with bend and glide applied in semitones on top
+pub(crate) fn voice_freq(config: &Config, tuning: Option<&Tuning>, voice: &MidiVoiceDesc) -> Option<f64> {
+	let offset = (voice.pitch() - voice.note as f32) as f64;
 
+	Some(config.note_to_freq(voice.note, tuning)? * util::semitones_to_pitch_scale(offset))
+}
 
 pub struct Osc {
 	pos: usize,
+	block: usize,
+	// In milliseconds
+	glide: f32,
 	notes: Mutex<Option<MonoVoiceTracker>>,
+	cache: Mutex<BlockCache>,
+	tuning: ResourceHandle<Tuning>,
 }
 
 impl Osc {
 	pub fn new() -> Self {
 		Osc {
 			pos: 0,
-			notes: Mutex::new(Some(MonoVoiceTracker::new()))
+			block: 0,
+			glide: 0.0,
+			notes: Mutex::new(Some(MonoVoiceTracker::new())),
+			cache: Mutex::new(BlockCache::new()),
+			tuning: ResourceHandle::nil("Tuning"),
 		}
 	}
 }
@@ -25,55 +96,120 @@ impl Node for Osc {
 	}
 
 	fn get_outputs(&self) -> &[BusKind] {
-		&[BusKind::Audio]
+		INSTRUMENT_OUTPUTS
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		INSTRUMENT_OUTPUT_NAMES
 	}
 
 	fn get_name(&self) -> &'static str {
 		"Osc"
 	}
 
+	fn get_resource_names(&self) -> &'static [&'static str] {
+		&["tuning"]
+	}
+
+	fn get_resource(&self, name: &str) -> &dyn ResourceHandleDyn {
+		match name {
+			"tuning" => &self.tuning,
+
+			_ => panic!()
+		}
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			BEND_RANGE_PARAM,
+			Parameter {
+				kind: ParamKind::Bool,
+				text: "legato",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "glide",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Float(DEFAULT_BEND_RANGE as f64)),
+			1 => Some(ParamValue::Bool(false)),
+			2 => Some(ParamValue::Float(0.0)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let Some(tracker) = self.notes.get_mut().unwrap() else {
+			panic!()
+		};
+
+		match (param, value) {
+			(0, ParamValue::Float(range)) => tracker.channels.bend_range = *range as f32,
+			(1, ParamValue::Bool(legato)) => tracker.legato = *legato,
+			(2, ParamValue::Float(ms)) => self.glide = (*ms as f32).max(0.0),
+
+			_ => panic!()
+		}
+	}
+
 	fn render(
 		&self,
-		_output: usize,
-		mut buffer: BufferAccess,
+		output: usize,
+		buffer: BufferAccess,
 		instance: &NodeInstance,
 		engine: &Engine
 	) {
-		let Some(midi) = self.poll_input(0, buffer.len(), instance, engine) else {
-			return
-		};
+		let mut cache = self.cache.lock().unwrap();
 
-		let Some(mut tracker) = self.notes.lock().unwrap().take() else {
-			return
-		};
+		if cache.prepare(self.block, buffer.len()) {
+			let Some(midi) = self.poll_input(0, buffer.len(), instance, engine) else {
+				return
+			};
 
-		let midi = midi.midi().unwrap();
-		let audio = buffer.audio_mut().unwrap();
+			let Some(mut tracker) = self.notes.lock().unwrap().take() else {
+				return
+			};
 
-		audio
-			.iter_mut()
-			.zip(midi)
-			.enumerate()
-			.for_each(|(i, (f, m))| {
+			let cache = &mut *cache;
+			let midi = midi.midi().unwrap();
+			let sample_rate = engine.config.sample_rate as f64;
+
+			let tuning = self.tuning.inner();
+			let tuning = tuning.as_ref().map(|tuning| tuning.read().unwrap());
+			let tuning = tuning.as_ref().map(|tuning| &tuning.data);
+
+			tracker.glide_time = (self.glide as f64 / 1000.0 * sample_rate) as u32;
+
+			for (i, m) in midi.iter().take(cache.audio.len()).enumerate() {
 				tracker.apply_midi_chain(m, i as u32);
 
 				let Some(note) = &mut tracker.voice else {
-					return
+					continue
 				};
-				
-				let time = note.progress as f64 / engine.config.sample_rate as f64;
-				let rate = util::midi_to_freq(note.note);
-				let vel = note.velocity as f32 / 127.0;
 
-				f.0 += (TAU * time * rate).sin() as f32 * vel;
-				f.1 += (TAU * time * rate).sin() as f32 * vel;
+				// Unmapped notes stay silent
+				if let Some(freq) = voice_freq(&engine.config, tuning, note) {
+					let value = (TAU * note.phase).sin() as f32 * note.velocity as f32 / 127.0;
 
+					cache.audio[i] = Frame(value, value);
+					note.phase = (note.phase + freq / sample_rate).fract();
+				}
+
+				cache.control[i] = note.pressure;
 				note.progress += 1;
-			});
-		
-		tracker.purge_dead_voices();
+			}
+
+			tracker.purge_dead_voices();
 
-		*self.notes.lock().unwrap() = Some(tracker);
+			*self.notes.lock().unwrap() = Some(tracker);
+		}
+
+		cache.write_output(output, buffer);
 	}
 
 	fn advance(
@@ -82,6 +218,7 @@ impl Node for Osc {
 		_config: &Config
 	) {
 		self.pos += frames;
+		self.block += 1;
 	}
 
 	fn seek(
@@ -91,12 +228,23 @@ impl Node for Osc {
 	) {
 		self.pos = position;
 	}
+
+	fn transport_changed(&mut self, event: TransportEvent, _config: &Config) {
+		if event == TransportEvent::Stop {
+			if let Some(tracker) = self.notes.get_mut().unwrap() {
+				tracker.release_all_voices(0);
+			}
+		}
+	}
 }
 
 
 pub struct PolyOsc {
 	pos: usize,
+	block: usize,
 	notes: Mutex<Option<PolyVoiceTracker>>,
+	cache: Mutex<BlockCache>,
+	tuning: ResourceHandle<Tuning>,
 }
 
 
@@ -104,7 +252,10 @@ impl PolyOsc {
 	pub fn new() -> Self {
 		PolyOsc {
 			pos: 0,
-			notes: Mutex::new(Some(PolyVoiceTracker::new()))
+			block: 0,
+			notes: Mutex::new(Some(PolyVoiceTracker::new())),
+			cache: Mutex::new(BlockCache::new()),
+			tuning: ResourceHandle::nil("Tuning"),
 		}
 	}
 }
@@ -115,53 +266,93 @@ impl Node for PolyOsc {
 	}
 
 	fn get_outputs(&self) -> &[BusKind] {
-		&[BusKind::Audio]
+		INSTRUMENT_OUTPUTS
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		INSTRUMENT_OUTPUT_NAMES
 	}
 
 	fn get_name(&self) -> &'static str {
 		"PolyOsc"
 	}
 
+	fn get_resource_names(&self) -> &'static [&'static str] {
+		&["tuning"]
+	}
+
+	fn get_resource(&self, name: &str) -> &dyn ResourceHandleDyn {
+		match name {
+			"tuning" => &self.tuning,
+
+			_ => panic!()
+		}
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		POLY_INSTRUMENT_PARAMS
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		poly_instrument_param_default(param)
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let Some(tracker) = self.notes.get_mut().unwrap() else {
+			panic!()
+		};
+
+		update_poly_instrument_param(tracker, param, value);
+	}
+
 	fn render(
 		&self,
-		_output: usize,
-		mut buffer: BufferAccess,
+		output: usize,
+		buffer: BufferAccess,
 		instance: &NodeInstance,
 		engine: &Engine
 	) {
-		let Some(midi) = self.poll_input(0, buffer.len(), instance, engine) else {
-			return
-		};
+		let mut cache = self.cache.lock().unwrap();
 
-		let Some(mut tracker) = self.notes.lock().unwrap().take() else {
-			return
-		};
+		if cache.prepare(self.block, buffer.len()) {
+			let Some(midi) = self.poll_input(0, buffer.len(), instance, engine) else {
+				return
+			};
 
-		let midi = midi.midi().unwrap();
-		let audio = buffer.audio_mut().unwrap();
+			let Some(mut tracker) = self.notes.lock().unwrap().take() else {
+				return
+			};
 
-		audio
-			.iter_mut()
-			.zip(midi)
-			.enumerate()
-			.for_each(|(i, (f, m))| {
+			let cache = &mut *cache;
+			let midi = midi.midi().unwrap();
+			let sample_rate = engine.config.sample_rate as f64;
+
+			let tuning = self.tuning.inner();
+			let tuning = tuning.as_ref().map(|tuning| tuning.read().unwrap());
+			let tuning = tuning.as_ref().map(|tuning| &tuning.data);
+
+			for (i, m) in midi.iter().take(cache.audio.len()).enumerate() {
 				tracker.apply_midi_chain(m, i as u32);
 
-				for note in tracker.voices.values_mut() {
-					let time = note.progress as f64 / engine.config.sample_rate as f64;
-					let rate = util::midi_to_freq(note.note);
-					let vel = note.velocity as f32 / 127.0;
+				for note in &mut tracker.voices {
+					if let Some(freq) = voice_freq(&engine.config, tuning, note) {
+						let value = (TAU * note.phase).sin() as f32 * note.velocity as f32 / 127.0;
 
-					f.0 += (TAU * time * rate).sin() as f32 * vel;
-					f.1 += (TAU * time * rate).sin() as f32 * vel;
-					
+						cache.audio[i] += Frame(value, value);
+						note.phase = (note.phase + freq / sample_rate).fract();
+					}
+
+					cache.control[i] = cache.control[i].max(note.pressure);
 					note.progress += 1;
 				}
-			});
-		
-		tracker.purge_dead_voices();
+			}
+
+			tracker.purge_dead_voices();
 
-		*self.notes.lock().unwrap() = Some(tracker);
+			*self.notes.lock().unwrap() = Some(tracker);
+		}
+
+		cache.write_output(output, buffer);
 	}
 
 	fn advance(
@@ -170,6 +361,7 @@ impl Node for PolyOsc {
 		_config: &Config
 	) {
 		self.pos += frames;
+		self.block += 1;
 	}
 
 	fn seek(
@@ -185,6 +377,14 @@ impl Node for PolyOsc {
 
 		lock.kill_all_voices();
 	}
+
+	fn transport_changed(&mut self, event: TransportEvent, _config: &Config) {
+		if event == TransportEvent::Stop {
+			if let Some(tracker) = self.notes.get_mut().unwrap() {
+				tracker.release_all_voices(0);
+			}
+		}
+	}
 }
 
 
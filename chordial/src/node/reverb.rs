@@ -0,0 +1,237 @@
+use std::sync::Mutex;
+
+use crate::{engine::{Config, Frame}, param::{ParamKind, ParamValue, Parameter}, util::{lerp, DelayLine}};
+
+use super::{effect::Effect, BufferAccess};
+
+
+const LINE_COUNT: usize = 8;
+
+// Mutually prime delay lengths (in samples at 48kHz) for a room size of 1.0
+const LINE_LENGTHS: [usize; LINE_COUNT] = [1433, 1601, 1867, 2053, 2251, 2399, 2617, 2797];
+
+const MIN_SIZE_SCALE: f32 = 0.15;
+const MAX_PREDELAY_SECS: f32 = 0.5;
+
+struct ReverbState {
+	lines: Vec<DelayLine>,
+	damp_state: [f32; LINE_COUNT],
+	predelay: (DelayLine, DelayLine),
+}
+
+// Stereo feedback delay network reverb.
+//
+// Eight delay lines are fed back into each other through a normalized Hadamard
+// matrix. The left input feeds the even lines and the right input the odd ones,
+// and the outputs are collected the same way, which keeps the tail decorrelated.
+pub struct Reverb {
+	sample_rate: u32,
+	size: f32,
+	decay: f32,
+	predelay: f32,
+	damping: f32,
+	width: f32,
+	mix: f32,
+	state: Mutex<ReverbState>,
+}
+
+impl Reverb {
+	pub fn new(sample_rate: u32) -> Self {
+		let scale = sample_rate as f32 / 48000.0;
+		let max_predelay = (MAX_PREDELAY_SECS * sample_rate as f32) as usize;
+
+		Reverb {
+			sample_rate,
+			size: 0.5,
+			decay: 2.0,
+			predelay: 10.0,
+			damping: 0.5,
+			width: 1.0,
+			mix: 0.3,
+			state: Mutex::new(ReverbState {
+				lines: LINE_LENGTHS
+					.iter()
+					.map(|len| DelayLine::new((*len as f32 * scale).ceil() as usize + 1))
+					.collect(),
+				damp_state: [0.0; LINE_COUNT],
+				predelay: (DelayLine::new(max_predelay), DelayLine::new(max_predelay)),
+			}),
+		}
+	}
+
+	fn line_length(&self, line: usize) -> usize {
+		let scale = self.sample_rate as f32 / 48000.0;
+		let size_scale = lerp(MIN_SIZE_SCALE, 1.0, self.size.clamp(0.0, 1.0));
+
+		((LINE_LENGTHS[line] as f32 * scale * size_scale) as usize).max(1)
+	}
+
+	// Per-line feedback gain that reaches -60dB after `decay` seconds
+	fn line_gain(&self, length: usize) -> f32 {
+		if self.decay <= 0.0 {
+			return 0.0
+		}
+
+		let seconds = length as f32 / self.sample_rate as f32;
+
+		10.0f32.powf(-3.0 * seconds / self.decay)
+	}
+}
+
+fn hadamard(values: &mut [f32; LINE_COUNT]) {
+	let mut h = 1;
+
+	while h < LINE_COUNT {
+		for i in (0..LINE_COUNT).step_by(h * 2) {
+			for j in i..(i + h) {
+				let a = values[j];
+				let b = values[j + h];
+
+				values[j] = a + b;
+				values[j + h] = a - b;
+			}
+		}
+
+		h *= 2;
+	}
+
+	let norm = 1.0 / (LINE_COUNT as f32).sqrt();
+
+	values.iter_mut().for_each(|v| *v *= norm);
+}
+
+impl Effect for Reverb {
+	fn render_effect(&self, mut buffer: BufferAccess) {
+		let buffer = buffer.audio_mut().unwrap();
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+
+		let lengths: [usize; LINE_COUNT] = std::array::from_fn(|i| self.line_length(i));
+		let gains: [f32; LINE_COUNT] = std::array::from_fn(|i| self.line_gain(lengths[i]));
+		let predelay = (self.predelay / 1000.0 * self.sample_rate as f32) as usize;
+		let damping = self.damping.clamp(0.0, 0.99);
+		let out_scale = 1.0 / (LINE_COUNT / 2) as f32;
+
+		for f in buffer.iter_mut() {
+			let dry = *f;
+
+			let input = if predelay == 0 {
+				dry
+			} else {
+				let delayed = Frame(state.predelay.0.read(predelay), state.predelay.1.read(predelay));
+
+				state.predelay.0.write(dry.0);
+				state.predelay.1.write(dry.1);
+				delayed
+			};
+
+			let mut taps = [0f32; LINE_COUNT];
+
+			for (i, tap) in taps.iter_mut().enumerate() {
+				let out = state.lines[i].read(lengths[i]);
+				let damped = lerp(out, state.damp_state[i], damping);
+
+				state.damp_state[i] = damped;
+				*tap = damped;
+			}
+
+			let mut wet = Frame::ZERO;
+
+			for (i, tap) in taps.iter().enumerate() {
+				if i % 2 == 0 {
+					wet.0 += tap;
+				} else {
+					wet.1 += tap;
+				}
+			}
+
+			let mut feedback = taps;
+
+			for (i, fb) in feedback.iter_mut().enumerate() {
+				*fb *= gains[i];
+			}
+
+			hadamard(&mut feedback);
+
+			for (i, fb) in feedback.iter().enumerate() {
+				let inject = if i % 2 == 0 { input.0 } else { input.1 };
+
+				state.lines[i].write(fb + inject);
+			}
+
+			let wet = wet * out_scale;
+			let mid = (wet.0 + wet.1) * 0.5;
+			let side = (wet.0 - wet.1) * 0.5 * self.width;
+			let wet = Frame(mid + side, mid - side);
+
+			*f = dry * (1.0 - self.mix) + wet * self.mix;
+		}
+	}
+
+	fn advance_effect(&mut self, _: usize, _: &Config) { }
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "size",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "decay",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "predelay",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "damping",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "width",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "mix",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Float(0.5)),
+			1 => Some(ParamValue::Float(2.0)),
+			2 => Some(ParamValue::Float(10.0)),
+			3 => Some(ParamValue::Float(0.5)),
+			4 => Some(ParamValue::Float(1.0)),
+			5 => Some(ParamValue::Float(0.3)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let ParamValue::Float(value) = value else {
+			panic!()
+		};
+
+		let value = *value as f32;
+
+		match param {
+			0 => self.size = value.clamp(0.0, 1.0),
+			1 => self.decay = value.max(0.0),
+			2 => self.predelay = value.clamp(0.0, MAX_PREDELAY_SECS * 1000.0),
+			3 => self.damping = value.clamp(0.0, 1.0),
+			4 => self.width = value.clamp(0.0, 2.0),
+			5 => self.mix = value.clamp(0.0, 1.0),
+
+			_ => panic!()
+		}
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Reverb"
+	}
+}
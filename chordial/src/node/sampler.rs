@@ -1,22 +1,103 @@
-use std::sync::Mutex;
+use std::{f32::consts::FRAC_PI_2, sync::Mutex};
 
-use crate::{engine::{Config, Engine}, midi::PolyVoiceTracker, resource::{AudioData, ResourceHandle, ResourceHandleDyn}, util};
+use crate::{engine::{Config, Engine, Frame}, midi::PolyVoiceTracker, param::{ParamKind, ParamValue, Parameter}, resource::{AudioData, ResourceHandle, ResourceHandleDyn}, transport::TransportEvent, tuning::Tuning, util::{self, db_to_amp}};
 
-use super::{BufferAccess, BusKind, Node, NodeUtil, NodeInstance, TlUnit};
+use super::{osc::{poly_instrument_param_default, update_poly_instrument_param, voice_freq, INSTRUMENT_OUTPUTS, INSTRUMENT_OUTPUT_NAMES, POLY_INSTRUMENT_PARAMS}, BlockCache, BufferAccess, BusKind, Node, NodeUtil, NodeInstance, TlUnit};
 
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FadeCurve {
+	Linear,
+	EqualPower,
+	Exponential,
+	SCurve,
+}
+
+impl FadeCurve {
+	pub fn from_index(idx: i64) -> Self {
+		match idx {
+			0 => FadeCurve::Linear,
+			1 => FadeCurve::EqualPower,
+			2 => FadeCurve::Exponential,
+			3 => FadeCurve::SCurve,
+
+			_ => panic!("invalid fade curve: {idx}")
+		}
+	}
+
+	// Gain for a fade-in at progress `t` in [0, 1]. Fade-outs use `gain(1 - t)`.
+	pub fn gain(self, t: f32) -> f32 {
+		let t = t.clamp(0.0, 1.0);
+
+		match self {
+			FadeCurve::Linear => t,
+			FadeCurve::EqualPower => (t * FRAC_PI_2).sin(),
+			FadeCurve::Exponential => t * t,
+			FadeCurve::SCurve => t * t * (3.0 - 2.0 * t),
+		}
+	}
+}
+
+// Plays an AudioData resource on the timeline. Position and trimming come from
+// the instance's TimelineTransform, the offsets are applied after reversing.
 pub struct SampleNode {
 	sample: ResourceHandle<AudioData>,
 	playback_pos: usize,
-	position: TlUnit,
-	start_offset: TlUnit,
-	end_offset: TlUnit,
+	gain: f32,
+	fade_in: f32,
+	fade_out: f32,
+	fade_in_curve: FadeCurve,
+	fade_out_curve: FadeCurve,
+	reverse: bool,
 }
 
+impl SampleNode {
+	pub fn new() -> Self {
+		SampleNode {
+			sample: ResourceHandle::nil("AudioData"),
+			playback_pos: 0,
+			gain: 0.0,
+			fade_in: 0.0,
+			fade_out: 0.0,
+			fade_in_curve: FadeCurve::Linear,
+			fade_out_curve: FadeCurve::Linear,
+			reverse: false,
+		}
+	}
+
+	// Length of the whole sample at the engine rate, in frames
+	fn resampled_len(data: &AudioData, config: &Config) -> usize {
+		(data.data.len() as f64 * config.sample_rate as f64 / data.sample_rate as f64) as usize
+	}
+
+	fn fade_gain(&self, pos: usize, len: usize, sample_rate: u32) -> f32 {
+		let ms_to_frames = |ms: f32| ms / 1000.0 * sample_rate as f32;
+		let (fade_in, fade_out) = (ms_to_frames(self.fade_in), ms_to_frames(self.fade_out));
+		let mut gain = 1.0;
+
+		if (pos as f32) < fade_in {
+			gain *= self.fade_in_curve.gain(pos as f32 / fade_in);
+		}
+
+		let remaining = (len - pos) as f32;
+
+		if remaining < fade_out {
+			gain *= self.fade_out_curve.gain(remaining / fade_out);
+		}
+
+		gain
+	}
+}
+
+impl Default for SampleNode {
+	fn default() -> Self {
+		Self::new()
+	}
+}
 
 impl Node for SampleNode {
 	fn get_name(&self) -> &'static str {
-		"Sample Node"
+		"Audio Clip"
 	}
 
 	fn get_inputs(&self) -> &[BusKind] {
@@ -27,41 +108,118 @@ impl Node for SampleNode {
 		&[BusKind::Audio]
 	}
 
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&["out"]
+	}
+
+	fn get_resource_names(&self) -> &'static [&'static str] {
+		&["sample"]
+	}
+
+	fn get_resource(&self, name: &str) -> &dyn ResourceHandleDyn {
+		match name {
+			"sample" => &self.sample,
+
+			_ => panic!()
+		}
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "gain",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "fade_in",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "fade_out",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "fade_in_curve",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "fade_out_curve",
+			},
+			Parameter {
+				kind: ParamKind::Bool,
+				text: "reverse",
+			},
+		]
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		match (param, value) {
+			(0, ParamValue::Float(gain)) => self.gain = *gain as f32,
+			(1, ParamValue::Float(ms)) => self.fade_in = (*ms as f32).max(0.0),
+			(2, ParamValue::Float(ms)) => self.fade_out = (*ms as f32).max(0.0),
+			(3, ParamValue::Int(curve)) => self.fade_in_curve = FadeCurve::from_index(*curve),
+			(4, ParamValue::Int(curve)) => self.fade_out_curve = FadeCurve::from_index(*curve),
+			(5, ParamValue::Bool(reverse)) => self.reverse = *reverse,
+
+			_ => panic!()
+		}
+	}
+
 	fn render(
 		&self,
 		_output: usize,
 		mut buffer: BufferAccess,
-		_instance: &NodeInstance,
+		instance: &NodeInstance,
 		engine: &Engine
 	) {
+		let audio = buffer.audio_mut().unwrap();
+		audio.fill(Frame::ZERO);
+
 		let Some(sample) = &*self.sample.inner() else {
 			return
 		};
 
-		let audio = buffer.audio_mut().unwrap();
+		let sample = sample.read().unwrap();
+		let data = &sample.data;
+
+		if data.data.is_empty() {
+			return
+		}
 
-		let pos = engine.config.tl_units_to_frames(self.position);
-		let start_offset = engine.config.tl_units_to_frames(self.start_offset);
+		let config = &engine.config;
+		let pos = config.tl_units_to_frames(instance.get_timeline_position());
+		let start_offset = config.tl_units_to_frames(instance.get_timeline_start_offset());
+		let end_offset = config.tl_units_to_frames(instance.get_timeline_end_offset());
 
-		let sample_lock = sample.read().unwrap();
-		let mut length = sample_lock.data.data.len();
+		let full_len = Self::resampled_len(data, config);
+		let len = full_len.saturating_sub(start_offset + end_offset);
 
-		length -= start_offset;
-		length -= engine.config.tl_units_to_frames(self.end_offset);
+		let step = data.sample_rate as f64 / config.sample_rate as f64;
+		let gain = db_to_amp(self.gain);
 
-		audio
-			.iter_mut()
-			.enumerate()
-			.for_each(|(i, f)| {
-				let frame_pos = self.playback_pos + i;
+		for (i, f) in audio.iter_mut().enumerate() {
+			let frame_pos = self.playback_pos + i;
 
-				if frame_pos >= pos && frame_pos < length {
-					let relative = frame_pos - pos + start_offset;
-					
-					*f += sample_lock.data.data[relative];
-				}
-			});
-		
+			if frame_pos < pos || frame_pos - pos >= len {
+				continue
+			}
+
+			let local = frame_pos - pos;
+			let mut source_pos = (local + start_offset) as f64;
+
+			if self.reverse {
+				source_pos = (full_len - 1) as f64 - source_pos;
+			}
+
+			let frame = if data.sample_rate == config.sample_rate {
+				data.data[(source_pos as usize).min(data.data.len() - 1)]
+			} else {
+				util::interpolate_hermite(&data.data, source_pos * step)
+			};
+
+			*f = frame * gain * self.fade_gain(local, len, config.sample_rate);
+		}
 	}
 
 	fn advance(&mut self, frames: usize, _config: &Config) {
@@ -76,104 +234,169 @@ impl Node for SampleNode {
 		true
 	}
 
+	// Untrimmed length, the engine subtracts the TimelineTransform offsets itself
 	fn get_timeline_length(&self, config: &Config) -> TlUnit {
 		let Some(inner) = &*self.sample.inner() else {
 			return TlUnit(0)
 		};
 
-		let len_frames = inner.read().unwrap().data.data.len();
-		let len_units = config.frames_to_tl_units(len_frames);
+		let len = Self::resampled_len(&inner.read().unwrap().data, config);
 
-		len_units
+		config.frames_to_tl_units(len)
 	}
 }
 
 
 pub struct Sampler {
+	block: usize,
 	voices: Mutex<Option<PolyVoiceTracker>>,
+	cache: Mutex<BlockCache>,
 	sample: ResourceHandle<AudioData>,
+	tuning: ResourceHandle<Tuning>,
 }
 
 impl Sampler {
 	pub fn new() -> Self {
 		Sampler {
+			block: 0,
 			voices: Mutex::new(Some(PolyVoiceTracker::new())),
-			sample: ResourceHandle::nil("AudioData")
+			cache: Mutex::new(BlockCache::new()),
+			sample: ResourceHandle::nil("AudioData"),
+			tuning: ResourceHandle::nil("Tuning"),
 		}
 	}
 }
 
+impl Default for Sampler {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl Node for Sampler {
 	fn get_inputs(&self) -> &[BusKind] {
 		&[BusKind::Midi]
 	}
 
 	fn get_outputs(&self) -> &[BusKind] {
-		&[BusKind::Audio]
+		INSTRUMENT_OUTPUTS
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		INSTRUMENT_OUTPUT_NAMES
 	}
 
 	fn get_name(&self) -> &'static str {
 		"Sampler"
 	}
 
+	fn get_resource_names(&self) -> &'static [&'static str] {
+		&["sample", "tuning"]
+	}
+
 	fn get_resource(&self, name: &str) -> &dyn ResourceHandleDyn {
 		match name {
 			"sample" => &self.sample,
+			"tuning" => &self.tuning,
 			
 			_ => panic!()
 		}
 	}
 
+	fn get_params(&self) -> &[Parameter] {
+		POLY_INSTRUMENT_PARAMS
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		poly_instrument_param_default(param)
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let Some(tracker) = self.voices.get_mut().unwrap() else {
+			panic!()
+		};
+
+		update_poly_instrument_param(tracker, param, value);
+	}
+
+
 	fn render(
 		&self,
-		_output: usize,
-		mut buffer: BufferAccess,
+		output: usize,
+		buffer: BufferAccess,
 		instance: &NodeInstance,
 		engine: &Engine
 	) {
-		let Some(sample) = &*self.sample.inner() else {
-			return
-		};
+		let mut cache = self.cache.lock().unwrap();
 
-		let Some(midi) = self.poll_input(0, buffer.len(), instance, engine) else {
-			return
-		};
+		if cache.prepare(self.block, buffer.len()) {
+			let Some(sample) = &*self.sample.inner() else {
+				return
+			};
 
-		let Some(mut tracker) = self.voices.lock().unwrap().take() else {
-			return
-		};
+			let Some(midi) = self.poll_input(0, buffer.len(), instance, engine) else {
+				return
+			};
 
-		let sample = sample.read().unwrap();
-		let sample = &sample.data;
-		let midi = midi.midi().unwrap();
-		let audio = buffer.audio_mut().unwrap();
-		
-		audio
-			.iter_mut()
-			.zip(midi)
-			.enumerate()
-			.for_each(|(i, (f, chain))| {
-				tracker.apply_midi_chain(chain, i as u32);
+			let Some(mut tracker) = self.voices.lock().unwrap().take() else {
+				return
+			};
 
-				for note in tracker.voices.values_mut() {
-					let vel = note.velocity as f32 / 127.0;
+			let cache = &mut *cache;
+			let sample = sample.read().unwrap();
+			let sample = &sample.data;
+			let midi = midi.midi().unwrap();
+			let rate = sample.sample_rate as f64 / engine.config.sample_rate as f64;
 
-					let pitch_scale = util::note_offset_to_pitch_scale(
-						note.note as i32 - 72
-					);
+			let tuning = self.tuning.inner();
+			let tuning = tuning.as_ref().map(|tuning| tuning.read().unwrap());
+			let tuning = tuning.as_ref().map(|tuning| &tuning.data);
 
-					*f += util::resample(
-						&sample.data,
-						sample.sample_rate as f32,
-						engine.config.sample_rate as f32 / pitch_scale as f32,
-						note.progress as usize,
-						util::ResampleMethod::Linear
-					) * vel;
+			// The sample plays at its original pitch on C5, or on the reference
+			// frequency of tunings that leave C5 unmapped
+			let root_freq = {
+				let tuning = tuning.unwrap_or(&engine.config.tuning);
+
+				tuning.note_to_freq(72).unwrap_or(tuning.mapping().reference_freq)
+			};
+
+			for (i, chain) in midi.iter().take(cache.audio.len()).enumerate() {
+				tracker.apply_midi_chain(chain, i as u32);
 
+				for note in &mut tracker.voices {
+					cache.control[i] = cache.control[i].max(note.pressure);
 					note.progress += 1;
+
+					// Unmapped notes stay silent
+					let Some(freq) = voice_freq(&engine.config, tuning, note) else {
+						continue
+					};
+
+					if note.phase < sample.data.len() as f64 {
+						let vel = note.velocity as f32 / 127.0;
+
+						cache.audio[i] += util::interpolate_linear(&sample.data, note.phase) * vel;
+					}
+
+					note.phase += freq / root_freq * rate;
 				}
-			});
-		
-		*self.voices.lock().unwrap() = Some(tracker);
+			}
+
+			*self.voices.lock().unwrap() = Some(tracker);
+		}
+
+		cache.write_output(output, buffer);
 	}
-}
\ No newline at end of file
+
+	fn advance(&mut self, _frames: usize, _config: &Config) {
+		self.block += 1;
+	}
+
+	fn transport_changed(&mut self, event: TransportEvent, _config: &Config) {
+		if event == TransportEvent::Stop {
+			if let Some(tracker) = self.voices.get_mut().unwrap() {
+				tracker.release_all_voices(0);
+			}
+		}
+	}
+}
@@ -0,0 +1,328 @@
+use std::f32::consts::FRAC_PI_2;
+
+use crate::{engine::{Config, Frame}, param::{ParamKind, ParamValue, Parameter}};
+
+use super::{effect::{control_or, Effect}, BufferAccess};
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PanLaw {
+	// Constant power, -3 dB per channel at center
+	ConstantPower,
+	// Halfway between constant power and constant gain, -4.5 dB at center
+	Compromise,
+	// Constant gain, -6 dB at center
+	ConstantGain,
+	// Unity at center, the opposite side is attenuated linearly (like a balance control)
+	Linear,
+}
+
+impl PanLaw {
+	pub fn from_index(idx: i64) -> Self {
+		match idx {
+			0 => PanLaw::ConstantPower,
+			1 => PanLaw::Compromise,
+			2 => PanLaw::ConstantGain,
+			3 => PanLaw::Linear,
+
+			_ => panic!("invalid pan law: {idx}")
+		}
+	}
+
+	// Left and right gains for a pan position in [-1, 1]
+	pub fn gains(self, pan: f32) -> (f32, f32) {
+		let x = (pan.clamp(-1.0, 1.0) + 1.0) * 0.5;
+
+		match self {
+			PanLaw::ConstantPower => ((x * FRAC_PI_2).cos(), (x * FRAC_PI_2).sin()),
+			PanLaw::ConstantGain => (1.0 - x, x),
+
+			PanLaw::Compromise => (
+				((1.0 - x) * (x * FRAC_PI_2).cos()).sqrt(),
+				(x * (x * FRAC_PI_2).sin()).sqrt(),
+			),
+
+			PanLaw::Linear => (
+				(2.0 * (1.0 - x)).min(1.0),
+				(2.0 * x).min(1.0),
+			),
+		}
+	}
+}
+
+
+pub struct Pan {
+	pan: f32,
+	law: PanLaw,
+}
+
+impl Pan {
+	pub fn new() -> Self {
+		Pan {
+			pan: 0.0,
+			law: PanLaw::ConstantPower,
+		}
+	}
+}
+
+impl Default for Pan {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Effect for Pan {
+	fn render_effect(&self, buffer: BufferAccess) {
+		self.render_effect_modulated(buffer, &[]);
+	}
+
+	fn render_effect_modulated(&self, mut buffer: BufferAccess, controls: &[Option<&[f32]>]) {
+		let fixed = self.law.gains(self.pan);
+
+		for (i, f) in buffer.audio_mut().unwrap().iter_mut().enumerate() {
+			let (l, r) = match controls.first() {
+				Some(Some(_)) => self.law.gains(control_or(controls, 0, i, self.pan)),
+				_ => fixed,
+			};
+
+			*f = Frame(f.0 * l, f.1 * r);
+		}
+	}
+
+	fn advance_effect(&mut self, _: usize, _: &Config) { }
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in", "pan"]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "pan",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "law",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Float(0.0)),
+			1 => Some(ParamValue::Int(0)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		match (param, value) {
+			(0, ParamValue::Float(pan)) => self.pan = (*pan as f32).clamp(-1.0, 1.0),
+			(1, ParamValue::Int(law)) => self.law = PanLaw::from_index(*law),
+
+			_ => panic!()
+		}
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Pan"
+	}
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WidthMode {
+	// Scales the side signal, 0 is mono, 1 leaves the input unchanged
+	Width,
+	// L/R in, mid on the left channel and side on the right out
+	Encode,
+	// Mid on the left channel and side on the right in, L/R out
+	Decode,
+}
+
+impl WidthMode {
+	pub fn from_index(idx: i64) -> Self {
+		match idx {
+			0 => WidthMode::Width,
+			1 => WidthMode::Encode,
+			2 => WidthMode::Decode,
+
+			_ => panic!("invalid stereo width mode: {idx}")
+		}
+	}
+}
+
+pub struct StereoWidth {
+	width: f32,
+	mode: WidthMode,
+}
+
+impl StereoWidth {
+	pub fn new() -> Self {
+		StereoWidth {
+			width: 1.0,
+			mode: WidthMode::Width,
+		}
+	}
+}
+
+impl Default for StereoWidth {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Effect for StereoWidth {
+	fn render_effect(&self, buffer: BufferAccess) {
+		self.render_effect_modulated(buffer, &[]);
+	}
+
+	fn render_effect_modulated(&self, mut buffer: BufferAccess, controls: &[Option<&[f32]>]) {
+		for (i, f) in buffer.audio_mut().unwrap().iter_mut().enumerate() {
+			let mid = (f.0 + f.1) * 0.5;
+			let side = (f.0 - f.1) * 0.5;
+
+			*f = match self.mode {
+				WidthMode::Width => {
+					let width = control_or(controls, 0, i, self.width).max(0.0);
+
+					Frame(mid + side * width, mid - side * width)
+				}
+
+				WidthMode::Encode => Frame(mid, side),
+				WidthMode::Decode => Frame(f.0 + f.1, f.0 - f.1),
+			};
+		}
+	}
+
+	fn advance_effect(&mut self, _: usize, _: &Config) { }
+
+	fn get_input_names(&self) -> &'static [&'static str] {
+		&["in", "width"]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Float,
+				text: "width",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "mode",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Float(1.0)),
+			1 => Some(ParamValue::Int(0)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		match (param, value) {
+			(0, ParamValue::Float(width)) => self.width = (*width as f32).max(0.0),
+			(1, ParamValue::Int(mode)) => self.mode = WidthMode::from_index(*mode),
+
+			_ => panic!()
+		}
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Stereo Width"
+	}
+}
+
+
+// Mono sum, then polarity inversion, then channel swap
+pub struct ChannelUtility {
+	swap: bool,
+	invert_left: bool,
+	invert_right: bool,
+	mono: bool,
+}
+
+impl ChannelUtility {
+	pub fn new() -> Self {
+		ChannelUtility {
+			swap: false,
+			invert_left: false,
+			invert_right: false,
+			mono: false,
+		}
+	}
+}
+
+impl Default for ChannelUtility {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Effect for ChannelUtility {
+	fn render_effect(&self, mut buffer: BufferAccess) {
+		let left = if self.invert_left { -1.0 } else { 1.0 };
+		let right = if self.invert_right { -1.0 } else { 1.0 };
+
+		for f in buffer.audio_mut().unwrap() {
+			if self.mono {
+				let mid = (f.0 + f.1) * 0.5;
+				*f = Frame(mid, mid);
+			}
+
+			*f = Frame(f.0 * left, f.1 * right);
+
+			if self.swap {
+				*f = Frame(f.1, f.0);
+			}
+		}
+	}
+
+	fn advance_effect(&mut self, _: usize, _: &Config) { }
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Bool,
+				text: "swap",
+			},
+			Parameter {
+				kind: ParamKind::Bool,
+				text: "invert_left",
+			},
+			Parameter {
+				kind: ParamKind::Bool,
+				text: "invert_right",
+			},
+			Parameter {
+				kind: ParamKind::Bool,
+				text: "mono",
+			},
+		]
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		let ParamValue::Bool(value) = value else {
+			panic!()
+		};
+
+		match param {
+			0 => self.swap = *value,
+			1 => self.invert_left = *value,
+			2 => self.invert_right = *value,
+			3 => self.mono = *value,
+
+			_ => panic!()
+		}
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Channel Utility"
+	}
+}
@@ -1,8 +1,20 @@
-use crate::{engine::{Config, Engine}, midi::{MidiBlock, MidiMessage, MidiStatusByte, MidiStatusCode}, resource::{ResourceHandleDyn, ResourceHandle}};
+use std::{ops::Range, sync::Mutex};
+
+use crate::{engine::{Config, Engine, STEP_DIVISIONS}, midi::{MidiBlock, MidiBlockEvent, MidiNoteDesc, MidiMessage, MidiStatusByte, MidiStatusCode}, param::{ParamKind, ParamValue, Parameter}, resource::{ResourceHandleDyn, ResourceHandle}};
 
 use super::{BufferAccess, BusKind, Node, NodeInstance, TlUnit};
 
 
+// Timeline length of an endlessly repeating clip
+pub const INFINITE_LENGTH: TlUnit = TlUnit(u32::MAX as usize);
+
+// Note offs for the notes left on by the last rendered block, which ended at `block_end`
+#[derive(Default)]
+struct SoundingNotes {
+	block_end: usize,
+	note_offs: Vec<MidiMessage>,
+}
+
 pub struct MidiClipNote {
 	pub pos: TlUnit,
 	pub len: TlUnit,
@@ -10,9 +22,21 @@ pub struct MidiClipNote {
 	pub vel: u8
 }
 
+// Plays a MidiBlock from the instance's timeline position. The block is repeated every
+// `loop_length` units (its own length when 0), `repeats` times or forever when 0.
+// Notes are cut at the loop end, notes starting (after swing) at or past it are not played.
 pub struct MidiClip {
 	pub data: ResourceHandle<MidiBlock>,
 	pub playback_pos: usize,
+	loop_length: TlUnit,
+	repeats: usize,
+	transpose: i32,
+	velocity: f32,
+	// Delay of the notes on every second step, as a fraction of a step
+	swing: f32,
+	sounding: Mutex<SoundingNotes>,
+	// Sent at the start of the next block after seeking away from held notes
+	pending_note_offs: Vec<MidiMessage>,
 }
 
 impl MidiClip {
@@ -20,8 +44,74 @@ impl MidiClip {
 		MidiClip {
 			data,
 			playback_pos: 0,
+			loop_length: TlUnit(0),
+			repeats: 1,
+			transpose: 0,
+			velocity: 1.0,
+			swing: 0.0,
+			sounding: Mutex::new(SoundingNotes::default()),
+			pending_note_offs: vec![],
 		}
 	}
+
+	fn loop_length(&self, data: &MidiBlock) -> TlUnit {
+		if self.loop_length.0 > 0 {
+			self.loop_length
+		} else {
+			data.end()
+		}
+	}
+
+	fn swing_offset(&self, pos: TlUnit) -> TlUnit {
+		if (pos.0 / STEP_DIVISIONS as usize) % 2 == 1 {
+			TlUnit((self.swing * STEP_DIVISIONS as f32).round() as usize)
+		} else {
+			TlUnit(0)
+		}
+	}
+
+	// Swung start and end of a note within one loop, with the end cut at the loop end.
+	// None for notes that swing lands at or past the loop end, which aren't played.
+	fn note_span(&self, note: &MidiNoteDesc, loop_len: TlUnit) -> Option<(TlUnit, TlUnit)> {
+		let swing = self.swing_offset(note.pos);
+		let start = note.pos + swing;
+
+		if start >= loop_len {
+			return None
+		}
+
+		Some((start, (start + note.len).min(loop_len)))
+	}
+
+	// Position of an event within one loop
+	fn event_pos(&self, data: &MidiBlock, event: &MidiBlockEvent, loop_len: TlUnit) -> Option<TlUnit> {
+		let (start, end) = self.note_span(data.get_note(event), loop_len)?;
+
+		Some(if event.is_end { end } else { start })
+	}
+
+	// Notes transposed out of the MIDI range are dropped
+	fn transpose_note(&self, note: u8) -> Option<u8> {
+		u8::try_from(note as i32 + self.transpose).ok().filter(|n| *n < 128)
+	}
+
+	// A note on with velocity 0 is a note off, so scaled velocities stay above 0
+	fn scale_velocity(&self, vel: u8) -> u8 {
+		(vel as f32 * self.velocity).round().clamp(1.0, 127.0) as u8
+	}
+
+	fn event_message(&self, data: &MidiBlock, event: &MidiBlockEvent) -> Option<MidiMessage> {
+		let note = data.get_note(event);
+		let value = self.transpose_note(note.note)?;
+
+		let (code, vel) = if event.is_end {
+			(MidiStatusCode::NoteOff, note.vel)
+		} else {
+			(MidiStatusCode::NoteOn, self.scale_velocity(note.vel))
+		};
+
+		Some(MidiMessage::new(MidiStatusByte::new(code, event.channel), [value, vel]))
+	}
 }
 
 impl Node for MidiClip {
@@ -33,6 +123,55 @@ impl Node for MidiClip {
 		&[BusKind::Midi]
 	}
 
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Int,
+				text: "loop_length",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "repeats",
+			},
+			Parameter {
+				kind: ParamKind::Int,
+				text: "transpose",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "velocity",
+			},
+			Parameter {
+				kind: ParamKind::Float,
+				text: "swing",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Int(0)),
+			1 => Some(ParamValue::Int(1)),
+			2 => Some(ParamValue::Int(0)),
+			3 => Some(ParamValue::Float(1.0)),
+			4 => Some(ParamValue::Float(0.0)),
+
+			_ => None
+		}
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		match (param, value) {
+			(0, ParamValue::Int(len)) => self.loop_length = TlUnit((*len).max(0) as usize),
+			(1, ParamValue::Int(repeats)) => self.repeats = (*repeats).max(0) as usize,
+			(2, ParamValue::Int(transpose)) => self.transpose = (*transpose).clamp(-127, 127) as i32,
+			(3, ParamValue::Float(velocity)) => self.velocity = (*velocity as f32).max(0.0),
+			(4, ParamValue::Float(swing)) => self.swing = (*swing as f32).clamp(0.0, 1.0),
+
+			_ => panic!()
+		}
+	}
+
 	fn render(
 		&self,
 		_output: usize,
@@ -40,48 +179,88 @@ impl Node for MidiClip {
 		instance: &NodeInstance,
 		engine: &Engine
 	) {
-		
 		let buffer = buffer.midi_mut().unwrap();
 
+		if buffer.is_empty() {
+			return
+		}
+
+		buffer[0].extend_from_slice(&self.pending_note_offs);
+
 		let Some(data) = &*self.data.inner() else {
 			return
 		};
 
-		let data = data.read().unwrap();
-
-		buffer
-			.iter_mut()
-			.enumerate()
-			.for_each(|(i, m)| {
-				let sample_pos = self.playback_pos + i;
-				let tl_pos = engine.config.frames_to_tl_units(sample_pos);
-				let prev_tl_pos = if sample_pos > 0 {
-					engine.config.frames_to_tl_units(sample_pos - 1)
-				} else {
-					TlUnit(0)
-				};
-				
-				for channel in 0..data.data.channels.len() {
-					for note in &data.data.channels[channel] {
-						let note_pos = note.pos + instance.get_timeline_position();
-						let note_end = note_pos + note.len;
-
-						if tl_pos >= note_pos && (sample_pos == 0 || prev_tl_pos < note_pos) {
-							// emit note start
-							m.push(MidiMessage::new(
-								MidiStatusByte::new(MidiStatusCode::NoteOn, channel as u8),
-								[note.note, note.vel]
-							));
-						} else if tl_pos >= note_end && prev_tl_pos < note_end && note.len.0 > 0 {
-							// emit note end
-							m.push(MidiMessage::new(
-								MidiStatusByte::new(MidiStatusCode::NoteOff, channel as u8),
-								[note.note, note.vel]
-							));
+		let data = &data.read().unwrap().data;
+		let config = &engine.config;
+		let block = self.playback_pos..self.playback_pos + buffer.len();
+
+		let loop_len = self.loop_length(data);
+
+		if loop_len.0 == 0 {
+			return
+		}
+
+		let mut sounding = self.sounding.lock().unwrap();
+
+		// Already rendered, e.g. for a second output poll
+		let track_sounding = sounding.block_end != block.end;
+		sounding.block_end = block.end;
+
+		// Clip relative range of the events that can land in this block, widened
+		// by a step for swing. Each event then goes to the first frame at or after its exact time.
+		let clip_pos = instance.get_timeline_position();
+		let first = config.frames_to_tl_units(block.start.saturating_sub(1)).0.saturating_sub(clip_pos.0 + STEP_DIVISIONS as usize);
+		let last = (config.frames_to_tl_units(block.end - 1).0 + 1).saturating_sub(clip_pos.0);
+
+		let first_rep = first / loop_len.0;
+		let last_rep = last.saturating_sub(1) / loop_len.0;
+		let last_rep = if self.repeats > 0 { last_rep.min(self.repeats - 1) } else { last_rep };
+
+		let mut emit = |event: &MidiBlockEvent, rep_start: usize| {
+			let Some(event_pos) = self.event_pos(data, event, loop_len) else {
+				return
+			};
+
+			let pos = clip_pos.0 + rep_start + event_pos.0;
+			let frame = config.tl_units_to_frames(TlUnit(pos));
+
+			if block.contains(&frame) {
+				if let Some(message) = self.event_message(data, event) {
+					buffer[frame - block.start].push(message);
+
+					if track_sounding {
+						let note_offs = &mut sounding.note_offs;
+
+						if event.is_end {
+							if let Some(i) = note_offs.iter().position(|off| off.data()[..2] == message.data()[..2]) {
+								note_offs.swap_remove(i);
+							}
+						} else if let Some(off) = self.event_message(data, &MidiBlockEvent { is_end: true, ..*event }) {
+							note_offs.push(off);
 						}
 					}
 				}
-			});
+			}
+		};
+
+		for rep in first_rep..=last_rep {
+			let rep_start = rep * loop_len.0;
+			let range: Range<usize> = first.saturating_sub(rep_start)..last - rep_start;
+
+			for event in data.events_in(TlUnit(range.start)..TlUnit(range.end.min(loop_len.0))) {
+				emit(event, rep_start);
+			}
+
+			// Notes still playing at the loop end are cut there
+			if range.contains(&loop_len.0) {
+				for event in data.events_in(loop_len..TlUnit(usize::MAX)) {
+					if event.is_end && data.get_note(event).pos < loop_len {
+						emit(event, rep_start);
+					}
+				}
+			}
+		}
 	}
 
 	fn advance(
@@ -90,13 +269,23 @@ impl Node for MidiClip {
 		_config: &Config
 	) {
 		self.playback_pos += frames;
+		self.pending_note_offs.clear();
 	}
 
+	// Notes held at the end of the last block are released when playback doesn't continue
+	// from there, like when looping back, as their ends aren't played anymore
 	fn seek(
 		&mut self,
 		position: usize,
 		_config: &Config,
 	) {
+		let sounding = self.sounding.get_mut().unwrap();
+
+		if position != sounding.block_end {
+			self.pending_note_offs.append(&mut sounding.note_offs);
+		}
+
+		sounding.block_end = position;
 		self.playback_pos = position;
 	}
 
@@ -108,18 +297,49 @@ impl Node for MidiClip {
 		let Some(data) = &*self.data.inner() else {
 			return TlUnit(1)
 		};
-		
-		let channels = &data.read().unwrap().data.channels;
-		
-		let mut max = 0;
-
-		for channel in channels {
-			if let Some(channel_max) = channel.iter().max_by_key(|note| note.pos.0 + note.len.0) {
-				max = max.max(channel_max.pos.0 + channel_max.len.0);
+
+		let loop_len = self.loop_length(&data.read().unwrap().data);
+
+		match self.repeats {
+			0 => INFINITE_LENGTH,
+			repeats => TlUnit(loop_len.0 * repeats),
+		}
+	}
+
+	fn get_midi_notes(&self, until: TlUnit) -> Option<Vec<(u8, MidiNoteDesc)>> {
+		let data = self.data.inner();
+		let data = &data.as_ref()?.read().unwrap().data;
+
+		let loop_len = self.loop_length(data);
+		let mut result = vec![];
+
+		if loop_len.0 == 0 {
+			return Some(result)
+		}
+
+		let repeats = match self.repeats {
+			0 => until.0.div_ceil(loop_len.0).max(1),
+			repeats => repeats,
+		};
+
+		for rep in 0..repeats {
+			for (channel, notes) in data.channels().iter().enumerate() {
+				for note in notes {
+					let (Some(value), Some((start, end))) = (self.transpose_note(note.note), self.note_span(note, loop_len)) else {
+						continue
+					};
+
+					result.push((channel as u8, MidiNoteDesc {
+						pos: TlUnit(rep * loop_len.0 + start.0),
+						len: TlUnit(end.0 - start.0),
+						note: value,
+						vel: self.scale_velocity(note.vel),
+					}));
+				}
 			}
 		}
 
-		TlUnit(max)
+		Some(result)
 	}
 
 	fn get_resource_names(&self) -> &'static [&'static str] {
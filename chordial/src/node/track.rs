@@ -0,0 +1,140 @@
+use crate::{engine::Engine, param::{ParamKind, ParamValue, Parameter}};
+
+use super::{Buffer, BufferAccess, BusKind, Node, NodeInstance, OutputRef};
+
+
+// Sums the first output of every clip on a track. Created by `Engine::add_track()`,
+// nothing but note offs is rendered while the track is muted or another track is soloed.
+pub struct TrackInput {
+	track: usize,
+	outputs: [BusKind; 1],
+	layout_changed: bool,
+}
+
+impl TrackInput {
+	pub fn new() -> Self {
+		TrackInput {
+			track: usize::MAX,
+			outputs: [BusKind::Midi],
+			layout_changed: false,
+		}
+	}
+}
+
+impl Default for TrackInput {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Node for TrackInput {
+	fn get_name(&self) -> &'static str {
+		"Track Input"
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&self.outputs
+	}
+
+	fn get_output_names(&self) -> &'static [&'static str] {
+		&["out"]
+	}
+
+	fn get_params(&self) -> &[Parameter] {
+		&[
+			Parameter {
+				kind: ParamKind::Int,
+				text: "track",
+			},
+			// 0: MIDI, 1: Audio
+			Parameter {
+				kind: ParamKind::Int,
+				text: "kind",
+			},
+		]
+	}
+
+	fn get_param_default_value(&self, param: usize) -> Option<ParamValue> {
+		match param {
+			0 => Some(ParamValue::Int(-1)),
+			1 => Some(ParamValue::Int(0)),
+
+			_ => None
+		}
+	}
+
+	fn take_layout_changed(&mut self) -> bool {
+		std::mem::take(&mut self.layout_changed)
+	}
+
+	fn param_updated(&mut self, param: usize, value: &ParamValue) {
+		match (param, value) {
+			(0, ParamValue::Int(track)) => self.track = usize::try_from(*track).unwrap_or(usize::MAX),
+
+			(1, ParamValue::Int(kind)) => {
+				let kind = match kind {
+					0 => BusKind::Midi,
+					1 => BusKind::Audio,
+
+					_ => panic!("invalid track kind: {kind}")
+				};
+
+				self.layout_changed = kind != self.outputs[0];
+				self.outputs[0] = kind;
+			}
+
+			_ => panic!()
+		}
+	}
+
+	fn render(
+		&self,
+		_output: usize,
+		mut buffer: BufferAccess,
+		_instance: &NodeInstance,
+		engine: &Engine
+	) {
+		let Some(track) = engine.get_track(self.track) else {
+			return
+		};
+
+		// MIDI tracks keep their clips playing while muted and let only note offs
+		// through, so notes held when the track went quiet are released
+		let audible = engine.is_track_audible(self.track);
+
+		if !audible && !matches!(buffer, BufferAccess::Midi(_)) {
+			return
+		}
+
+		let len = buffer.len();
+
+		for clip in &track.clips {
+			let Some(clip_node) = engine.get_node(*clip) else {
+				continue
+			};
+
+			if clip_node.outputs.is_empty() {
+				continue
+			}
+
+			let clip_output = engine.poll_node_output(&OutputRef { node: *clip, output: 0 }, len);
+
+			match (&mut buffer, &*clip_output) {
+				(BufferAccess::Audio(out), Buffer::Audio(clip)) => {
+					out.iter_mut().zip(clip).for_each(|(a, b)| *a += *b);
+				}
+
+				(BufferAccess::Midi(out), Buffer::Midi(clip)) => {
+					if audible {
+						out.iter_mut().zip(clip).for_each(|(a, b)| a.extend_from_slice(b));
+					} else {
+						out.iter_mut().zip(clip).for_each(|(a, b)| a.extend(b.iter().filter(|msg| msg.is_note_off()).cloned()));
+					}
+				}
+
+				// Clips of the wrong kind are skipped
+				_ => {}
+			}
+		}
+	}
+}
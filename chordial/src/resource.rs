@@ -1,4 +1,4 @@
-use std::{any::Any, fs::File, io::BufReader, mem::size_of, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard, RwLock}};
+use std::{any::Any, fs::File, io::BufReader, mem::size_of, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex, MutexGuard, RwLock}};
 
 use hound::SampleFormat;
 
@@ -37,11 +37,32 @@ pub trait ResourceLoader: Clone + Send + Sync {
 }
 
 
+// Source of resource generations, unique across all resource data
+static GENERATION_COUNTER: AtomicUsize = AtomicUsize::new(1);
+
+fn next_generation() -> usize {
+	GENERATION_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+
 #[derive(Clone)]
 pub struct ResourceData<T: Resource> {
 	pub data: T,
 	pub path: Option<PathBuf>,
 	pub id: usize,
+	generation: usize,
+}
+
+impl<T: Resource> ResourceData<T> {
+	// Edits should go through here, so nodes deriving state from the data see the change
+	pub fn data_mut(&mut self) -> &mut T {
+		self.generation = next_generation();
+		&mut self.data
+	}
+
+	pub fn generation(&self) -> usize {
+		self.generation
+	}
 }
 
 
@@ -72,7 +93,8 @@ impl<T: Resource + 'static> ResourceHandle<T> {
 			inner: Mutex::new(Some(Arc::new(RwLock::new(ResourceData {
 					data,
 					path,
-					id
+					id,
+					generation: next_generation(),
 				})))
 			),
 			kind
@@ -115,6 +137,13 @@ impl<T: Resource + 'static> ResourceHandle<T> {
 
 		*self.inner() = resource.unwrap().inner.lock().unwrap().clone();
 	}
+
+	// Changes whenever the handle is linked to other data or its data is edited,
+	// 0 while it's empty. Nodes that derive state from a resource compare it
+	// against the generation they last built that state from.
+	pub fn generation(&self) -> usize {
+		self.inner().as_ref().map_or(0, |data| data.read().unwrap().generation)
+	}
 }
 
 
@@ -146,7 +175,7 @@ pub trait ResourceHandleDyn: Send + private::ResourceHandleSealed {
 impl<T: Resource + 'static> ResourceHandleDyn for ResourceHandle<T> {
 
 	fn apply_action(&self, action: &str, args: &[ParamValue]) {
-		self.inner().as_ref().unwrap().write().unwrap().data.apply_action(action, args)
+		self.inner().as_ref().unwrap().write().unwrap().data_mut().apply_action(action, args)
 	}
 
 	fn get(&self, keys: &[ParamValue]) -> Option<ParamValue> {
@@ -186,7 +215,7 @@ impl<T: Resource + 'static> ResourceHandleDyn for ResourceHandle<T> {
 	}
 
 	fn load(&mut self, data: &[u8]) {
-		self.inner().as_ref().unwrap().write().unwrap().data.load(data)
+		self.inner().as_ref().unwrap().write().unwrap().data_mut().load(data)
 	}
 
 	fn path(&self) -> Option<PathBuf> {
@@ -0,0 +1,490 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{engine::STEP_DIVISIONS, midi::{MidiBlock, MidiNoteDesc}, node::TlUnit, resource::ResourceLoader, tempo::{TempoMap, TempoRamp, TICKS_PER_BEAT}};
+
+
+// Reading and writing Standard MIDI Files (SMF)
+
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+const META_TIME_SIGNATURE: u8 = 0x58;
+const META_TRACK_NAME: u8 = 0x03;
+
+// Files with SMPTE timing have no tempo, their seconds are mapped to beats at this tempo
+const SMPTE_BPM: f64 = 120.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SmfChannelMapping {
+	// Notes keep the MIDI channel they were recorded on
+	ByChannel,
+	// Notes of the Nth track go to channel N (wrapping after 16), for files
+	// that put every part on the same MIDI channel
+	ByTrack,
+}
+
+pub struct SmfImport {
+	pub block: MidiBlock,
+	// Tempo and time signature changes found in the file, if there were any
+	pub tempo: Option<TempoMap>,
+	pub track_names: Vec<String>,
+}
+
+enum Timing {
+	// Ticks per quarter note
+	Metrical(u32),
+	// Ticks per second
+	Smpte(f64),
+}
+
+impl Timing {
+	fn ticks_to_tl(&self, ticks: u64) -> TlUnit {
+		let units = match self {
+			Timing::Metrical(division) => {
+				let division = *division as u64;
+				(ticks * TICKS_PER_BEAT as u64 + division / 2) / division
+			}
+
+			Timing::Smpte(per_sec) => {
+				(ticks as f64 / per_sec * SMPTE_BPM / 60.0 * TICKS_PER_BEAT as f64).round() as u64
+			}
+		};
+
+		TlUnit(units as usize)
+	}
+}
+
+struct Reader<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn is_empty(&self) -> bool {
+		self.pos >= self.data.len()
+	}
+
+	fn u8(&mut self) -> Option<u8> {
+		let byte = *self.data.get(self.pos)?;
+		self.pos += 1;
+
+		Some(byte)
+	}
+
+	fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+		let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+		self.pos += len;
+
+		Some(bytes)
+	}
+
+	fn u16(&mut self) -> Option<u16> {
+		Some(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+	}
+
+	fn u32(&mut self) -> Option<u32> {
+		Some(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+	}
+
+	fn var_len(&mut self) -> Option<u32> {
+		let mut value = 0u32;
+
+		for _ in 0..4 {
+			let byte = self.u8()?;
+			value = (value << 7) | (byte & 0x7F) as u32;
+
+			if byte & 0x80 == 0 {
+				return Some(value)
+			}
+		}
+
+		None
+	}
+
+	// Returns the chunk type and contents
+	fn chunk(&mut self) -> Option<(&'a [u8], &'a [u8])> {
+		let kind = self.bytes(4)?;
+		let len = self.u32()? as usize;
+
+		Some((kind, self.bytes(len)?))
+	}
+}
+
+pub fn read_smf(data: &[u8], mapping: SmfChannelMapping) -> Option<SmfImport> {
+	let mut reader = Reader { data, pos: 0 };
+
+	let (b"MThd", header) = reader.chunk()? else {
+		return None
+	};
+
+	let mut header = Reader { data: header, pos: 0 };
+	let _format = header.u16()?;
+	let _track_count = header.u16()?;
+	let division = header.u16()?;
+
+	let timing = if division & 0x8000 == 0 {
+		Timing::Metrical(division.max(1) as u32)
+	} else {
+		// Negative frames per second in the upper byte, ticks per frame in the lower one
+		let fps = match (division >> 8) as u8 as i8 {
+			-29 => 29.97,
+			fps => -(fps as f64),
+		};
+
+		Timing::Smpte((fps * (division & 0xFF) as f64).max(1.0))
+	};
+
+	let mut result = SmfImport {
+		block: MidiBlock::default(),
+		tempo: None,
+		track_names: vec![],
+	};
+
+	// Notes are collected first and indexed once at the end
+	let mut channels = Default::default();
+	let mut track = 0;
+
+	while !reader.is_empty() {
+		let (kind, chunk) = reader.chunk()?;
+
+		// Unknown chunks must be skipped
+		if kind != b"MTrk" {
+			continue
+		}
+
+		read_track(chunk, track, &timing, mapping, &mut channels, &mut result)?;
+		track += 1;
+	}
+
+	result.block = MidiBlock::from_channels(channels);
+
+	Some(result)
+}
+
+fn end_note(channels: &mut [Vec<MidiNoteDesc>; 16], channel: usize, idx: usize, end: TlUnit) {
+	let note = &mut channels[channel][idx];
+	note.len = TlUnit(end.0.saturating_sub(note.pos.0));
+}
+
+fn read_track(
+	data: &[u8],
+	track: usize,
+	timing: &Timing,
+	mapping: SmfChannelMapping,
+	channels: &mut [Vec<MidiNoteDesc>; 16],
+	result: &mut SmfImport
+) -> Option<()> {
+	let mut reader = Reader { data, pos: 0 };
+	let mut ticks = 0u64;
+	let mut running_status = None;
+
+	// Notes that are still held, as (channel, index) per (MIDI channel, key), oldest first
+	let mut held: HashMap<(u8, u8), Vec<(usize, usize)>> = HashMap::new();
+
+	while !reader.is_empty() {
+		ticks += reader.var_len()? as u64;
+		let pos = timing.ticks_to_tl(ticks);
+
+		let mut status = reader.u8()?;
+
+		// Running status, the previous status byte is reused
+		let first_data = if status < 0x80 {
+			let data = status;
+			status = running_status?;
+
+			Some(data)
+		} else {
+			None
+		};
+
+		match status {
+			0xFF => {
+				let kind = reader.u8()?;
+				let len = reader.var_len()? as usize;
+				let meta = reader.bytes(len)?;
+
+				match (kind, meta) {
+					(META_END_OF_TRACK, _) => break,
+
+					(META_TEMPO, [a, b, c]) => {
+						let micros = u32::from_be_bytes([0, *a, *b, *c]).max(1);
+						let tempo = result.tempo.get_or_insert_with(TempoMap::default);
+
+						tempo.set_tempo(pos, 60_000_000.0 / micros as f64, TempoRamp::Step);
+					}
+
+					(META_TIME_SIGNATURE, [numerator, denominator, ..]) => {
+						let denominator = 1u32.checked_shl(*denominator as u32).unwrap_or(0);
+
+						if *numerator > 0 && (1..=64).contains(&denominator) {
+							let tempo = result.tempo.get_or_insert_with(TempoMap::default);
+							tempo.set_time_signature(pos, *numerator as u32, denominator);
+						}
+					}
+
+					(META_TRACK_NAME, name) => {
+						result.track_names.push(String::from_utf8_lossy(name).into_owned());
+					}
+
+					_ => ()
+				}
+			}
+
+			0xF0 | 0xF7 => {
+				let len = reader.var_len()? as usize;
+				reader.bytes(len)?;
+			}
+
+			0x80..=0xEF => {
+				running_status = Some(status);
+
+				let code = status & 0xF0;
+				let midi_channel = status & 0x0F;
+
+				let data_len = match code {
+					0xC0 | 0xD0 => 1,
+					_ => 2,
+				};
+
+				let mut data = [0u8; 2];
+
+				for (i, byte) in data.iter_mut().take(data_len).enumerate() {
+					*byte = match (i, first_data) {
+						(0, Some(first)) => first,
+						_ => reader.u8()?,
+					};
+				}
+
+				let [key, vel] = data;
+
+				let channel = match mapping {
+					SmfChannelMapping::ByChannel => midi_channel as usize,
+					SmfChannelMapping::ByTrack => track % 16,
+				};
+
+				match (code, vel) {
+					(0x90, 1..) => {
+						channels[channel].push(MidiNoteDesc {
+							pos,
+							len: TlUnit(0),
+							note: key,
+							vel,
+						});
+
+						held
+							.entry((midi_channel, key))
+							.or_default()
+							.push((channel, channels[channel].len() - 1));
+					}
+
+					// Note off, or note on with velocity 0
+					(0x80 | 0x90, _) => {
+						if let Some(notes) = held.get_mut(&(midi_channel, key)) {
+							if !notes.is_empty() {
+								let (channel, idx) = notes.remove(0);
+								end_note(channels, channel, idx, pos);
+							}
+						}
+					}
+
+					_ => ()
+				}
+			}
+
+			// System common and realtime messages can't appear in a file, give up
+			_ => return None
+		}
+	}
+
+	// Notes still held at the end of the track end there
+	let pos = timing.ticks_to_tl(ticks);
+
+	for (channel, idx) in held.into_values().flatten() {
+		end_note(channels, channel, idx, pos);
+	}
+
+	Some(())
+}
+
+
+#[derive(Clone)]
+pub struct SmfLoader {
+	pub mapping: SmfChannelMapping,
+}
+
+impl SmfLoader {
+	pub fn new() -> Self {
+		SmfLoader {
+			mapping: SmfChannelMapping::ByChannel,
+		}
+	}
+}
+
+impl Default for SmfLoader {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl ResourceLoader for SmfLoader {
+	type Output = MidiBlock;
+
+	fn extensions(&self) -> &'static [&'static str] {
+		&["mid", "midi", "smf"]
+	}
+
+	fn load_resource(&self, file: &Path) -> Option<MidiBlock> {
+		Some(read_smf(&fs::read(file).ok()?, self.mapping)?.block)
+	}
+}
+
+
+pub struct SmfTrack {
+	pub name: Option<String>,
+	// (channel, note) pairs
+	pub notes: Vec<(u8, MidiNoteDesc)>,
+}
+
+impl SmfTrack {
+	pub fn from_block(block: &MidiBlock, name: Option<String>) -> Self {
+		let notes = block.channels()
+			.iter()
+			.enumerate()
+			.flat_map(|(channel, notes)| notes.iter().map(move |note| (channel as u8, *note)))
+			.collect();
+
+		SmfTrack {
+			name,
+			notes,
+		}
+	}
+}
+
+fn push_var_len(out: &mut Vec<u8>, mut value: u32) {
+	let mut bytes = [(value & 0x7F) as u8; 4];
+	let mut len = 1;
+
+	value >>= 7;
+
+	while value > 0 {
+		bytes[len] = (value & 0x7F) as u8 | 0x80;
+		value >>= 7;
+		len += 1;
+	}
+
+	out.extend(bytes[..len].iter().rev());
+}
+
+fn push_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+	out.extend_from_slice(kind);
+	out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+	out.extend_from_slice(data);
+}
+
+// Sorts the events by time and writes them as an MTrk chunk.
+// Events at the same time keep their order, apart from note offs which go first.
+// Channel messages repeating the previous status byte leave it out (running status).
+fn push_track(out: &mut Vec<u8>, mut events: Vec<(TlUnit, Vec<u8>)>) {
+	let is_note_off = |event: &[u8]| event[0] & 0xF0 == 0x80;
+	events.sort_by_key(|(pos, event)| (*pos, !is_note_off(event)));
+
+	let mut data = vec![];
+	let mut last = 0;
+	let mut running_status = None;
+
+	for (pos, event) in events {
+		push_var_len(&mut data, (pos.0 - last) as u32);
+		last = pos.0;
+
+		let status = event[0];
+
+		if running_status == Some(status) {
+			data.extend(&event[1..]);
+			continue
+		}
+
+		// Meta and SysEx events cancel running status
+		running_status = (0x80..0xF0).contains(&status).then_some(status);
+		data.extend(event);
+	}
+
+	push_var_len(&mut data, 0);
+	data.extend([0xFF, META_END_OF_TRACK, 0]);
+
+	push_chunk(out, b"MTrk", &data);
+}
+
+fn meta_event(kind: u8, data: &[u8]) -> Vec<u8> {
+	let mut event = vec![0xFF, kind];
+
+	push_var_len(&mut event, data.len() as u32);
+	event.extend_from_slice(data);
+
+	event
+}
+
+fn tempo_event(bpm: f64) -> Vec<u8> {
+	let micros = (60_000_000.0 / bpm).round().clamp(1.0, 0xFF_FFFF as f64) as u32;
+
+	meta_event(META_TEMPO, &micros.to_be_bytes()[1..])
+}
+
+// SMF has no tempo ramps, so linear ramps are written as a tempo change every step
+fn tempo_events(tempo: &TempoMap) -> Vec<(TlUnit, Vec<u8>)> {
+	let mut events = vec![];
+	let points = tempo.tempo_points();
+
+	for (idx, point) in points.iter().enumerate() {
+		events.push((point.pos, tempo_event(point.bpm)));
+
+		if let (TempoRamp::Linear, Some(next)) = (point.ramp, points.get(idx + 1)) {
+			for pos in (point.pos.0..next.pos.0).step_by(STEP_DIVISIONS as usize).skip(1) {
+				events.push((TlUnit(pos), tempo_event(tempo.bpm_at(TlUnit(pos)))));
+			}
+		}
+	}
+
+	for sig in tempo.time_signatures() {
+		// Clocks per metronome click and 32nd notes per quarter note
+		let data = [sig.numerator as u8, sig.denominator.trailing_zeros() as u8, 24, 8];
+		events.push((sig.pos, meta_event(META_TIME_SIGNATURE, &data)));
+	}
+
+	events
+}
+
+// Writes a format 1 file with the tempo map in the first track, one tick per TlUnit.
+// Zero length notes are written one unit long so they're not lost.
+pub fn write_smf(tracks: &[SmfTrack], tempo: Option<&TempoMap>) -> Vec<u8> {
+	let mut out = vec![];
+
+	let mut header = vec![];
+	header.extend_from_slice(&1u16.to_be_bytes());
+	header.extend_from_slice(&(tracks.len() as u16 + 1).to_be_bytes());
+	header.extend_from_slice(&(TICKS_PER_BEAT as u16).to_be_bytes());
+
+	push_chunk(&mut out, b"MThd", &header);
+	push_track(&mut out, tempo.map(tempo_events).unwrap_or_default());
+
+	for track in tracks {
+		let mut events = vec![];
+
+		if let Some(name) = &track.name {
+			events.push((TlUnit(0), meta_event(META_TRACK_NAME, name.as_bytes())));
+		}
+
+		for (channel, note) in &track.notes {
+			let channel = channel & 0x0F;
+
+			events.push((note.pos, vec![0x90 | channel, note.note, note.vel.max(1)]));
+			events.push((note.pos + TlUnit(note.len.0.max(1)), vec![0x80 | channel, note.note, 0x40]));
+		}
+
+		push_track(&mut out, events);
+	}
+
+	out
+}
+
+pub fn write_block_smf(block: &MidiBlock, tempo: Option<&TempoMap>) -> Vec<u8> {
+	write_smf(&[SmfTrack::from_block(block, None)], tempo)
+}
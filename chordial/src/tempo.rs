@@ -0,0 +1,399 @@
+use crate::{engine::{BEAT_DIVISIONS, STEP_DIVISIONS}, node::TlUnit};
+
+
+pub const TICKS_PER_BEAT: u32 = STEP_DIVISIONS * BEAT_DIVISIONS;
+
+// How the tempo moves from a tempo point to the next one
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TempoRamp {
+	Step,
+	Linear,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TempoPoint {
+	pub pos: TlUnit,
+	pub bpm: f64,
+	pub ramp: TempoRamp,
+}
+
+// A time signature change, which also starts a new bar.
+// Beats are counted in units of `denominator` notes (a quarter note is one timeline beat).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeSignature {
+	pub pos: TlUnit,
+	pub numerator: u32,
+	pub denominator: u32,
+}
+
+impl TimeSignature {
+	pub fn beat_ticks(&self) -> usize {
+		(TICKS_PER_BEAT * 4 / self.denominator) as usize
+	}
+
+	pub fn bar_ticks(&self) -> usize {
+		self.beat_ticks() * self.numerator as usize
+	}
+}
+
+// A non-negative rational number, used to convert between ticks and time exactly
+// while the tempo is constant. Arithmetic returns None on overflow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Ratio {
+	num: u128,
+	den: u128,
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+	while b != 0 {
+		(a, b) = (b, a % b);
+	}
+
+	a
+}
+
+impl Ratio {
+	pub(crate) fn new(num: u128, den: u128) -> Self {
+		assert!(den != 0, "zero denominator!");
+
+		let divisor = gcd(num, den);
+
+		Ratio {
+			num: num / divisor,
+			den: den / divisor,
+		}
+	}
+
+	pub(crate) fn int(value: u128) -> Self {
+		Ratio {
+			num: value,
+			den: 1,
+		}
+	}
+
+	// The exact value of a float, None if it's negative, not finite or out of range
+	pub(crate) fn from_f64(value: f64) -> Option<Self> {
+		if !value.is_finite() || value < 0.0 {
+			return None
+		}
+
+		let bits = value.to_bits();
+		let exponent = ((bits >> 52) & 0x7FF) as i32;
+		let fraction = (bits & ((1 << 52) - 1)) as u128;
+
+		let (mantissa, exponent) = match exponent {
+			0 => (fraction, -1074),
+			_ => (fraction | 1 << 52, exponent - 1075),
+		};
+
+		if exponent >= 0 {
+			Some(Self::int(mantissa.checked_mul(1u128.checked_shl(exponent as u32)?)?))
+		} else {
+			Some(Self::new(mantissa, 1u128.checked_shl(exponent.unsigned_abs())?))
+		}
+	}
+
+	pub(crate) fn floor(&self) -> u128 {
+		self.num / self.den
+	}
+
+	pub(crate) fn ceil(&self) -> u128 {
+		self.num.div_ceil(self.den)
+	}
+
+	pub(crate) fn checked_add(&self, other: &Self) -> Option<Self> {
+		let divisor = gcd(self.den, other.den);
+		let num = self.num.checked_mul(other.den / divisor)?.checked_add(other.num.checked_mul(self.den / divisor)?)?;
+
+		Some(Self::new(num, (self.den / divisor).checked_mul(other.den)?))
+	}
+
+	// None if the result would be negative
+	pub(crate) fn checked_sub(&self, other: &Self) -> Option<Self> {
+		let divisor = gcd(self.den, other.den);
+		let num = self.num.checked_mul(other.den / divisor)?.checked_sub(other.num.checked_mul(self.den / divisor)?)?;
+
+		Some(Self::new(num, (self.den / divisor).checked_mul(other.den)?))
+	}
+
+	pub(crate) fn checked_mul(&self, other: &Self) -> Option<Self> {
+		let (a, b) = (gcd(self.num, other.den), gcd(other.num, self.den));
+
+		// Both gcds are 0 only when multiplying 0 by 0
+		let (a, b) = (a.max(1), b.max(1));
+
+		Some(Self::new((self.num / a).checked_mul(other.num / b)?, (self.den / b).checked_mul(other.den / a)?))
+	}
+
+	pub(crate) fn checked_div(&self, other: &Self) -> Option<Self> {
+		if other.num == 0 {
+			return None
+		}
+
+		self.checked_mul(&Ratio { num: other.den, den: other.num })
+	}
+
+	pub(crate) fn checked_le(&self, other: &Self) -> Option<bool> {
+		Some(self.num.checked_mul(other.den)? <= other.num.checked_mul(self.den)?)
+	}
+}
+
+// Bars and beats are zero-based
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MusicalPosition {
+	pub bar: usize,
+	pub beat: usize,
+	pub tick: usize,
+}
+
+// Tempo and time signature changes along the timeline. There is always
+// a tempo point and a time signature at position 0.
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+	tempos: Vec<TempoPoint>,
+	signatures: Vec<TimeSignature>,
+
+	// Time in seconds at each tempo point
+	point_secs: Vec<f64>,
+
+	// The same as exact fractions, known up to the first tempo ramp
+	exact_point_secs: Vec<Option<Ratio>>,
+	// Exact seconds per tick of each segment with a constant tempo
+	exact_tick_secs: Vec<Option<Ratio>>,
+}
+
+impl TempoMap {
+	pub fn constant(bpm: f64) -> Self {
+		TempoMap {
+			tempos: vec![TempoPoint { pos: TlUnit(0), bpm, ramp: TempoRamp::Step }],
+			signatures: vec![TimeSignature { pos: TlUnit(0), numerator: 4, denominator: 4 }],
+			point_secs: vec![0.0],
+			exact_point_secs: vec![Some(Ratio::int(0))],
+			exact_tick_secs: vec![Self::exact_tick_secs(bpm)],
+		}
+	}
+
+	pub fn tempo_points(&self) -> &[TempoPoint] {
+		&self.tempos
+	}
+
+	pub fn time_signatures(&self) -> &[TimeSignature] {
+		&self.signatures
+	}
+
+	// Adds a tempo point, or replaces the one at the same position
+	pub fn set_tempo(&mut self, pos: TlUnit, bpm: f64, ramp: TempoRamp) {
+		assert!(bpm > 0.0, "tempo must be positive!");
+
+		let point = TempoPoint { pos, bpm, ramp };
+
+		match self.tempos.binary_search_by_key(&pos, |p| p.pos) {
+			Ok(idx) => self.tempos[idx] = point,
+			Err(idx) => self.tempos.insert(idx, point),
+		}
+
+		self.update_point_secs();
+	}
+
+	// The tempo point at position 0 can be changed but not removed
+	pub fn remove_tempo(&mut self, pos: TlUnit) {
+		if pos.0 == 0 {
+			return
+		}
+
+		self.tempos.retain(|p| p.pos != pos);
+		self.update_point_secs();
+	}
+
+	pub fn set_time_signature(&mut self, pos: TlUnit, numerator: u32, denominator: u32) {
+		assert!(numerator > 0 && denominator.is_power_of_two() && denominator <= 64, "invalid time signature!");
+
+		let signature = TimeSignature { pos, numerator, denominator };
+
+		match self.signatures.binary_search_by_key(&pos, |s| s.pos) {
+			Ok(idx) => self.signatures[idx] = signature,
+			Err(idx) => self.signatures.insert(idx, signature),
+		}
+	}
+
+	pub fn remove_time_signature(&mut self, pos: TlUnit) {
+		if pos.0 != 0 {
+			self.signatures.retain(|s| s.pos != pos);
+		}
+	}
+
+	fn segment_index(&self, ticks: f64) -> usize {
+		self.tempos.partition_point(|p| p.pos.0 as f64 <= ticks).saturating_sub(1)
+	}
+
+	// Tempo at the end of segment `idx`, and its length in ticks (None for the last one)
+	fn segment_end(&self, idx: usize) -> Option<(f64, f64)> {
+		let next = self.tempos.get(idx + 1)?;
+		let point = &self.tempos[idx];
+
+		let end_bpm = match point.ramp {
+			TempoRamp::Step => point.bpm,
+			TempoRamp::Linear => next.bpm,
+		};
+
+		Some((end_bpm, (next.pos.0 - point.pos.0) as f64))
+	}
+
+	// Seconds from the start of segment `idx` to `ticks` ticks into it
+	fn segment_secs(&self, idx: usize, ticks: f64) -> f64 {
+		let start_bpm = self.tempos[idx].bpm;
+		let beats = ticks / TICKS_PER_BEAT as f64;
+
+		match self.segment_end(idx) {
+			Some((end_bpm, len)) if end_bpm != start_bpm => {
+				let len_beats = len / TICKS_PER_BEAT as f64;
+				let slope = (end_bpm - start_bpm) / len_beats;
+
+				60.0 / slope * ((start_bpm + slope * beats) / start_bpm).ln()
+			}
+
+			_ => beats * 60.0 / start_bpm,
+		}
+	}
+
+	fn exact_tick_secs(bpm: f64) -> Option<Ratio> {
+		Ratio::int(60).checked_div(&Ratio::from_f64(bpm)?.checked_mul(&Ratio::int(TICKS_PER_BEAT as u128))?)
+	}
+
+	fn update_point_secs(&mut self) {
+		self.point_secs = vec![0.0; self.tempos.len()];
+
+		for idx in 1..self.tempos.len() {
+			let len = (self.tempos[idx].pos.0 - self.tempos[idx - 1].pos.0) as f64;
+			self.point_secs[idx] = self.point_secs[idx - 1] + self.segment_secs(idx - 1, len);
+		}
+
+		self.exact_tick_secs = (0..self.tempos.len())
+			.map(|idx| match self.segment_end(idx) {
+				Some((end_bpm, _)) if end_bpm != self.tempos[idx].bpm => None,
+				_ => Self::exact_tick_secs(self.tempos[idx].bpm),
+			})
+			.collect();
+
+		self.exact_point_secs = vec![Some(Ratio::int(0)); self.tempos.len()];
+
+		for idx in 1..self.tempos.len() {
+			let len = Ratio::int((self.tempos[idx].pos.0 - self.tempos[idx - 1].pos.0) as u128);
+
+			self.exact_point_secs[idx] = self.exact_point_secs[idx - 1]
+				.zip(self.exact_tick_secs[idx - 1])
+				.and_then(|(start, tick_secs)| start.checked_add(&len.checked_mul(&tick_secs)?));
+		}
+	}
+
+	pub fn bpm_at(&self, pos: TlUnit) -> f64 {
+		let idx = self.segment_index(pos.0 as f64);
+		let point = &self.tempos[idx];
+
+		match self.segment_end(idx) {
+			Some((end_bpm, len)) => point.bpm + (end_bpm - point.bpm) * (pos.0 - point.pos.0) as f64 / len,
+			None => point.bpm,
+		}
+	}
+
+	// Position in seconds of a (possibly fractional) tick position
+	pub fn ticks_to_secs(&self, ticks: f64) -> f64 {
+		let idx = self.segment_index(ticks);
+
+		self.point_secs[idx] + self.segment_secs(idx, ticks - self.tempos[idx].pos.0 as f64)
+	}
+
+	pub fn secs_to_ticks(&self, secs: f64) -> f64 {
+		let idx = self.point_secs.partition_point(|s| *s <= secs).saturating_sub(1);
+		let point = &self.tempos[idx];
+		let secs = secs - self.point_secs[idx];
+
+		let beats = match self.segment_end(idx) {
+			Some((end_bpm, len)) if end_bpm != point.bpm => {
+				let slope = (end_bpm - point.bpm) / (len / TICKS_PER_BEAT as f64);
+
+				point.bpm * ((secs * slope / 60.0).exp() - 1.0) / slope
+			}
+
+			_ => secs * point.bpm / 60.0,
+		};
+
+		point.pos.0 as f64 + beats * TICKS_PER_BEAT as f64
+	}
+
+	// Exact position in seconds of a tick, None inside or after a tempo ramp
+	pub(crate) fn ticks_to_secs_exact(&self, pos: TlUnit) -> Option<Ratio> {
+		let idx = self.tempos.partition_point(|p| p.pos <= pos).saturating_sub(1);
+		let ticks = Ratio::int((pos.0 - self.tempos[idx].pos.0) as u128);
+
+		self.exact_point_secs[idx]?.checked_add(&ticks.checked_mul(&self.exact_tick_secs[idx]?)?)
+	}
+
+	// Exact tick position of a time in seconds, None inside or after a tempo ramp
+	pub(crate) fn secs_to_ticks_exact(&self, secs: Ratio) -> Option<Ratio> {
+		let mut idx = 0;
+
+		for (next, start) in self.exact_point_secs.iter().enumerate().skip(1) {
+			if !start.as_ref()?.checked_le(&secs)? {
+				break
+			}
+
+			idx = next;
+		}
+
+		let ticks = secs.checked_sub(&self.exact_point_secs[idx]?)?.checked_div(&self.exact_tick_secs[idx]?)?;
+
+		ticks.checked_add(&Ratio::int(self.tempos[idx].pos.0 as u128))
+	}
+
+	fn signature_index(&self, pos: TlUnit) -> usize {
+		self.signatures.partition_point(|s| s.pos <= pos).saturating_sub(1)
+	}
+
+	pub fn time_signature_at(&self, pos: TlUnit) -> TimeSignature {
+		self.signatures[self.signature_index(pos)]
+	}
+
+	// Bar index of each time signature change. A change that doesn't fall on a bar line
+	// cuts the previous bar short.
+	fn signature_bars(&self) -> impl Iterator<Item = (usize, &TimeSignature)> {
+		let mut bar = 0;
+
+		self.signatures.iter().enumerate().map(move |(idx, sig)| {
+			let start = bar;
+
+			if let Some(next) = self.signatures.get(idx + 1) {
+				bar += (next.pos.0 - sig.pos.0).div_ceil(sig.bar_ticks());
+			}
+
+			(start, sig)
+		})
+	}
+
+	pub fn musical_position(&self, pos: TlUnit) -> MusicalPosition {
+		let idx = self.signature_index(pos);
+		let (first_bar, sig) = self.signature_bars().nth(idx).unwrap();
+		let ticks = pos.0 - sig.pos.0;
+
+		MusicalPosition {
+			bar: first_bar + ticks / sig.bar_ticks(),
+			beat: ticks % sig.bar_ticks() / sig.beat_ticks(),
+			tick: ticks % sig.beat_ticks(),
+		}
+	}
+
+	pub fn musical_to_tl(&self, pos: MusicalPosition) -> TlUnit {
+		let (first_bar, sig) = self.signature_bars()
+			.take_while(|(bar, _)| *bar <= pos.bar)
+			.last()
+			.unwrap();
+
+		TlUnit(sig.pos.0 + (pos.bar - first_bar) * sig.bar_ticks() + pos.beat * sig.beat_ticks() + pos.tick)
+	}
+}
+
+impl Default for TempoMap {
+	fn default() -> Self {
+		Self::constant(120.0)
+	}
+}
@@ -0,0 +1,25 @@
+// A lane of timeline clips. The clips are only rendered through the track's input node
+// (a `chordial.track_input` node owned by the track), which sums them into a single
+// output that can feed a regular node chain.
+#[derive(Debug, Clone)]
+pub struct Track {
+	pub id: usize,
+	pub name: String,
+	pub input_node: usize,
+	pub clips: Vec<usize>,
+	pub mute: bool,
+	pub solo: bool,
+}
+
+impl Track {
+	pub fn new(id: usize, name: String, input_node: usize) -> Self {
+		Track {
+			id,
+			name,
+			input_node,
+			clips: vec![],
+			mute: false,
+			solo: false,
+		}
+	}
+}
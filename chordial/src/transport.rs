@@ -0,0 +1,71 @@
+use std::ops::Range;
+
+use crate::{engine::Config, node::TlUnit};
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransportEvent {
+	Play,
+	Stop,
+}
+
+// Loop and punch ranges, in frames. A range is only active while enabled and non-empty.
+#[derive(Debug, Clone, Default)]
+pub struct Transport {
+	pub loop_enabled: bool,
+	pub loop_range: Range<usize>,
+
+	pub punch_enabled: bool,
+	pub punch_range: Range<usize>,
+}
+
+impl Transport {
+	pub fn set_loop(&mut self, range: Range<usize>) {
+		self.loop_range = range;
+		self.loop_enabled = true;
+	}
+
+	pub fn set_loop_tl(&mut self, start: TlUnit, end: TlUnit, config: &Config) {
+		self.set_loop(config.tl_units_to_frames(start)..config.tl_units_to_frames(end));
+	}
+
+	pub fn set_punch(&mut self, range: Range<usize>) {
+		self.punch_range = range;
+		self.punch_enabled = true;
+	}
+
+	pub fn set_punch_tl(&mut self, start: TlUnit, end: TlUnit, config: &Config) {
+		self.set_punch(config.tl_units_to_frames(start)..config.tl_units_to_frames(end));
+	}
+
+	pub fn active_loop(&self) -> Option<Range<usize>> {
+		(self.loop_enabled && !self.loop_range.is_empty()).then(|| self.loop_range.clone())
+	}
+
+	// Frames until playback starting at `position` reaches the loop end and wraps,
+	// if it does so within `len` frames. Positions past the loop end don't wrap.
+	pub fn frames_until_wrap(&self, position: usize, len: usize) -> Option<usize> {
+		let range = self.active_loop()?;
+
+		(position < range.end && range.end - position <= len).then(|| range.end - position)
+	}
+
+	// Whether recording should happen at `position`. Without an active punch range,
+	// recording is never restricted.
+	pub fn is_punched_in(&self, position: usize) -> bool {
+		!self.punch_enabled || self.punch_range.is_empty() || self.punch_range.contains(&position)
+	}
+
+	// The part of the block starting at `position` that lies inside the punch range,
+	// relative to the block start
+	pub fn punch_in_block(&self, position: usize, len: usize) -> Option<Range<usize>> {
+		if !self.punch_enabled || self.punch_range.is_empty() {
+			return Some(0..len)
+		}
+
+		let start = self.punch_range.start.max(position);
+		let end = self.punch_range.end.min(position + len);
+
+		(start < end).then(|| start - position..end - position)
+	}
+}
@@ -0,0 +1,356 @@
+use std::{fs, path::Path};
+
+use crate::{param::ParamValue, resource::{Resource, ResourceLoader}};
+
+
+// Separates the scale from the keyboard mapping in saved Tunings. Scala
+// comment lines start with '!', so neither file can contain it.
+const KBM_SEPARATOR: &str = "!!kbm\n";
+
+// Iterates the lines of a Scala file that aren't comments
+fn scala_lines(text: &str) -> impl Iterator<Item = &str> {
+	text.lines()
+		.map(|line| line.trim_end_matches('\r'))
+		.filter(|line| !line.starts_with('!'))
+}
+
+// A pitch line is in cents if it contains a period, a ratio or integer otherwise.
+// Anything after the value is a comment.
+fn parse_pitch(line: &str) -> Option<f64> {
+	let value = line.split_whitespace().next()?;
+
+	if value.contains('.') {
+		return value.parse().ok()
+	}
+
+	let (num, den) = value.split_once('/').unwrap_or((value, "1"));
+	let (num, den) = (num.parse::<f64>().ok()?, den.parse::<f64>().ok()?);
+
+	if num <= 0.0 || den <= 0.0 {
+		return None
+	}
+
+	Some(1200.0 * (num / den).log2())
+}
+
+fn parse_field<T: std::str::FromStr>(line: Option<&str>) -> Option<T> {
+	line?.split_whitespace().next()?.parse().ok()
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+	pub description: String,
+	// In cents above the root, the last degree is the period (usually 1200)
+	pub degrees: Vec<f64>,
+}
+
+impl Scale {
+	pub fn equal(steps: usize) -> Self {
+		Scale {
+			description: format!("{steps}-tone equal temperament"),
+			degrees: (1..=steps).map(|step| 1200.0 * step as f64 / steps as f64).collect(),
+		}
+	}
+
+	// Parses a Scala .scl file
+	pub fn parse(text: &str) -> Option<Self> {
+		let mut lines = scala_lines(text);
+
+		let description = lines.next()?.trim().to_string();
+		let count: usize = parse_field(lines.next())?;
+
+		let degrees = lines
+			.filter(|line| !line.trim().is_empty())
+			.take(count)
+			.map(parse_pitch)
+			.collect::<Option<Vec<_>>>()?;
+
+		if degrees.len() != count || count == 0 {
+			return None
+		}
+
+		Some(Scale {
+			description,
+			degrees,
+		})
+	}
+
+	pub fn to_scl(&self) -> String {
+		let mut result = format!("{}\n{}\n", self.description, self.degrees.len());
+
+		// Debug formatting keeps the period on whole numbers, marking them as cents
+		for cents in &self.degrees {
+			result += &format!("{cents:?}\n");
+		}
+
+		result
+	}
+
+	pub fn period(&self) -> f64 {
+		*self.degrees.last().unwrap()
+	}
+
+	// Cents of a scale degree, counting from 0 at the root and wrapping at the period
+	pub fn degree_cents(&self, degree: i32) -> f64 {
+		let len = self.degrees.len() as i32;
+		let (periods, degree) = (degree.div_euclid(len), degree.rem_euclid(len));
+
+		let cents = match degree {
+			0 => 0.0,
+			degree => self.degrees[degree as usize - 1],
+		};
+
+		periods as f64 * self.period() + cents
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+	// Notes outside this range are unmapped
+	pub first_note: u8,
+	pub last_note: u8,
+	// The note where the first key of the mapping sits
+	pub middle_note: u8,
+	pub reference_note: u8,
+	pub reference_freq: f64,
+	// Scale degree the mapping repeats at
+	pub octave_degree: usize,
+	// Scale degree of each key in the mapping, None for unmapped keys.
+	// Empty maps every key to the next scale degree.
+	pub keys: Vec<Option<usize>>,
+}
+
+impl KeyboardMapping {
+	// Scala's default mapping: scale root on C4, A4 at `reference_freq`
+	pub fn linear(reference_freq: f64) -> Self {
+		KeyboardMapping {
+			first_note: 0,
+			last_note: 127,
+			middle_note: 60,
+			reference_note: 69,
+			reference_freq,
+			octave_degree: 0,
+			keys: vec![],
+		}
+	}
+
+	// Parses a Scala .kbm file
+	pub fn parse(text: &str) -> Option<Self> {
+		let mut lines = scala_lines(text).filter(|line| !line.trim().is_empty());
+
+		let size: usize = parse_field(lines.next())?;
+		let first_note: u8 = parse_field(lines.next())?;
+		let last_note: u8 = parse_field(lines.next())?;
+		let middle_note: u8 = parse_field(lines.next())?;
+		let reference_note: u8 = parse_field(lines.next())?;
+		let reference_freq: f64 = parse_field(lines.next())?;
+		let octave_degree: usize = parse_field(lines.next())?;
+
+		let mut keys = lines
+			.take(size)
+			.map(|line| match line.split_whitespace().next()? {
+				"x" | "X" => Some(None),
+				degree => degree.parse().ok().map(Some),
+			})
+			.collect::<Option<Vec<_>>>()?;
+
+		// Missing entries at the end are unmapped
+		keys.resize(size, None);
+
+		if reference_freq <= 0.0 || [first_note, last_note, middle_note, reference_note].iter().any(|note| *note > 127) {
+			return None
+		}
+
+		Some(KeyboardMapping {
+			first_note,
+			last_note,
+			middle_note,
+			reference_note,
+			reference_freq,
+			octave_degree,
+			keys,
+		})
+	}
+
+	pub fn to_kbm(&self) -> String {
+		let mut result = format!(
+			"{}\n{}\n{}\n{}\n{}\n{:?}\n{}\n",
+			self.keys.len(),
+			self.first_note,
+			self.last_note,
+			self.middle_note,
+			self.reference_note,
+			self.reference_freq,
+			self.octave_degree,
+		);
+
+		for key in &self.keys {
+			match key {
+				Some(degree) => result += &format!("{degree}\n"),
+				None => result += "x\n",
+			}
+		}
+
+		result
+	}
+
+	// The scale degree a note plays, None if it's unmapped
+	pub fn note_degree(&self, note: u8, scale: &Scale) -> Option<i32> {
+		if note < self.first_note || note > self.last_note {
+			return None
+		}
+
+		let offset = note as i32 - self.middle_note as i32;
+
+		if self.keys.is_empty() {
+			return Some(offset)
+		}
+
+		let len = self.keys.len() as i32;
+
+		// An octave degree of 0 repeats the mapping at the scale's period
+		let octave_degree = match self.octave_degree {
+			0 => scale.degrees.len(),
+			degree => degree,
+		};
+
+		let degree = self.keys[offset.rem_euclid(len) as usize]?;
+
+		Some(offset.div_euclid(len) * octave_degree as i32 + degree as i32)
+	}
+}
+
+
+// A Scala scale and keyboard mapping, with the frequency of every MIDI note
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+	scale: Scale,
+	mapping: KeyboardMapping,
+	freqs: [Option<f64>; 128],
+}
+
+impl Tuning {
+	pub fn new(scale: Scale, mapping: KeyboardMapping) -> Self {
+		let cents = |note| Some(scale.degree_cents(mapping.note_degree(note, &scale)?));
+
+		// The reference note doesn't have to be mapped, the middle note is the root then
+		let reference = cents(mapping.reference_note).unwrap_or(0.0);
+		let mut freqs = [None; 128];
+
+		for (note, freq) in freqs.iter_mut().enumerate() {
+			*freq = cents(note as u8).map(|cents| mapping.reference_freq * 2.0f64.powf((cents - reference) / 1200.0));
+		}
+
+		Tuning {
+			scale,
+			mapping,
+			freqs,
+		}
+	}
+
+	// 12-tone equal temperament with A4 at `a4`
+	pub fn equal_temperament(a4: f64) -> Self {
+		Self::new(Scale::equal(12), KeyboardMapping::linear(a4))
+	}
+
+	// Without a keyboard mapping, the scale root sits on C4 and A4 is 440 Hz
+	pub fn from_scala(scl: &str, kbm: Option<&str>) -> Option<Self> {
+		let scale = Scale::parse(scl)?;
+
+		let mapping = match kbm {
+			Some(kbm) => KeyboardMapping::parse(kbm)?,
+			None => KeyboardMapping::linear(440.0),
+		};
+
+		Some(Self::new(scale, mapping))
+	}
+
+	pub fn load(scl: &Path, kbm: Option<&Path>) -> Option<Self> {
+		let kbm = match kbm {
+			Some(kbm) => Some(fs::read_to_string(kbm).ok()?),
+			None => None,
+		};
+
+		Self::from_scala(&fs::read_to_string(scl).ok()?, kbm.as_deref())
+	}
+
+	pub fn scale(&self) -> &Scale {
+		&self.scale
+	}
+
+	pub fn mapping(&self) -> &KeyboardMapping {
+		&self.mapping
+	}
+
+	// None for notes the keyboard mapping leaves unmapped
+	pub fn note_to_freq(&self, note: u8) -> Option<f64> {
+		self.freqs.get(note as usize).copied().flatten()
+	}
+}
+
+impl Default for Tuning {
+	fn default() -> Self {
+		Self::equal_temperament(440.0)
+	}
+}
+
+impl Resource for Tuning {
+	fn resource_kind(&self) -> &'static str {
+		"Tuning"
+	}
+
+	fn get(&self, keys: &[ParamValue]) -> Option<ParamValue> {
+		let [ParamValue::String(request), args @ ..] = keys else {
+			return None
+		};
+
+		match request.as_str() {
+			"get_description" => Some(ParamValue::String(self.scale.description.clone())),
+
+			"get_freq" => {
+				let [ParamValue::Int(note)] = args else {
+					return None
+				};
+
+				Some(ParamValue::Float(self.note_to_freq(u8::try_from(*note).ok()?)?))
+			}
+
+			_ => None
+		}
+	}
+
+	fn save(&self) -> Vec<u8> {
+		format!("{}{KBM_SEPARATOR}{}", self.scale.to_scl(), self.mapping.to_kbm()).into_bytes()
+	}
+
+	fn load(&mut self, data: &[u8]) {
+		let text = String::from_utf8_lossy(data);
+
+		let Some((scl, kbm)) = text.split_once(KBM_SEPARATOR) else {
+			panic!("invalid tuning data")
+		};
+
+		*self = Self::from_scala(scl, Some(kbm)).expect("invalid tuning data");
+	}
+}
+
+
+// Loads .scl files, along with the .kbm file of the same name if there is one
+#[derive(Clone)]
+pub struct ScalaLoader;
+
+impl ResourceLoader for ScalaLoader {
+	type Output = Tuning;
+
+	fn extensions(&self) -> &'static [&'static str] {
+		&["scl"]
+	}
+
+	fn load_resource(&self, file: &Path) -> Option<Tuning> {
+		let kbm = file.with_extension("kbm");
+
+		Tuning::load(file, kbm.exists().then_some(kbm.as_path()))
+	}
+}
@@ -1,9 +1,22 @@
+use std::f32::consts::PI;
+
 use crate::engine::Frame;
 
+// Power ratio of a level in dB (+10 dB is 10x the power). For gain applied to
+// samples, use `db_to_amp()`.
 pub fn db_to_factor(db: f32) -> f32 {
 	10.0f32.powf(db / 10.0)
 }
 
+// Amplitude ratio of a level in dB (+20 dB is 10x the amplitude)
+pub fn db_to_amp(db: f32) -> f32 {
+	10.0f32.powf(db / 20.0)
+}
+
+pub fn amp_to_db(amp: f32) -> f32 {
+	20.0 * amp.max(1e-9).log10()
+}
+
 pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
 	(1.0 - t) * a + b * t
 }
@@ -21,7 +34,11 @@ pub fn midi_to_freq(note: u8) -> f64 {
 }
 
 pub fn note_offset_to_pitch_scale(offset: i32) -> f64 {
-	2.0f64.powf(offset as f64 / 12.0)
+	semitones_to_pitch_scale(offset as f64)
+}
+
+pub fn semitones_to_pitch_scale(semitones: f64) -> f64 {
+	2.0f64.powf(semitones / 12.0)
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -64,6 +81,234 @@ pub fn resample(
 			)
 		}
 
-		_ => todo!()
+		ResampleMethod::Hermite => interpolate_hermite(input, output_offset as f64 / ratio as f64),
+
+		ResampleMethod::Sinc8 => resample_sinc(input, ratio, output_offset, 8),
+		ResampleMethod::Sinc16 => resample_sinc(input, ratio, output_offset, 16),
+		ResampleMethod::Sinc32 => resample_sinc(input, ratio, output_offset, 32),
+	}
+}
+
+// Reads `input` at a fractional frame position, clamped to its bounds
+pub fn interpolate_linear(input: &[Frame], pos: f64) -> Frame {
+	let j = pos.clamp(0.0, input.len().saturating_sub(1) as f64);
+	let i = j.floor() as usize;
+	let t = (j - j.floor()) as f32;
+
+	let a = input[i];
+	let b = input[(i + 1).min(input.len() - 1)];
+
+	Frame(lerp(a.0, b.0, t), lerp(a.1, b.1, t))
+}
+
+// Reads `input` at a fractional frame position, clamped to its bounds
+pub fn interpolate_hermite(input: &[Frame], pos: f64) -> Frame {
+	let j = pos.clamp(0.0, input.len() as f64 - 1.0);
+	let i = j.floor() as isize;
+	let t = (j - j.floor()) as f32;
+
+	let at = |k: isize| input[k.clamp(0, input.len() as isize - 1) as usize];
+	let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+
+	Frame(
+		hermite(p0.0, p1.0, p2.0, p3.0, t),
+		hermite(p0.1, p1.1, p2.1, p3.1, t)
+	)
+}
+
+fn hermite(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+	let c1 = 0.5 * (p2 - p0);
+	let c2 = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+	let c3 = 0.5 * (p3 - p0) + 1.5 * (p1 - p2);
+
+	((c3 * t + c2) * t + c1) * t + p1
+}
+
+pub fn sinc(x: f32) -> f32 {
+	if x.abs() < 1e-6 {
+		1.0
+	} else {
+		(PI * x).sin() / (PI * x)
+	}
+}
+
+// Blackman window over `t` in [-1, 1]
+pub fn blackman(t: f32) -> f32 {
+	if t.abs() > 1.0 {
+		return 0.0
+	}
+
+	0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos()
+}
+
+// Windowed sinc interpolation. When downsampling, the kernel is widened
+// so that it also acts as an anti-aliasing filter at the output rate.
+fn resample_sinc(input: &[Frame], ratio: f32, output_offset: usize, taps: usize) -> Frame {
+	let j = output_offset as f32 / ratio;
+	let cutoff = ratio.min(1.0);
+	let half_width = taps as f32 / 2.0 / cutoff;
+
+	let first = (j - half_width).ceil().max(0.0) as usize;
+	let last = ((j + half_width).floor() as usize).min(input.len().saturating_sub(1));
+
+	let mut result = Frame::ZERO;
+
+	for (k, frame) in input.iter().enumerate().take(last + 1).skip(first) {
+		let x = j - k as f32;
+		let weight = cutoff * sinc(x * cutoff) * blackman(x / half_width);
+
+		result += *frame * weight;
+	}
+
+	result
+}
+
+
+// Polyphase FIR up/downsampler for running nonlinear processing at
+// an integer multiple of the engine sample rate.
+pub struct Oversampler {
+	factor: usize,
+	kernel: Vec<f32>,
+	up_history: Vec<Frame>,
+	up_pos: usize,
+	down_history: Vec<Frame>,
+	down_pos: usize,
+}
+
+impl Oversampler {
+	const TAPS_PER_PHASE: usize = 12;
+
+	pub fn new(factor: usize) -> Self {
+		let factor = factor.max(1);
+		let len = Self::TAPS_PER_PHASE * factor;
+		let center = (len - 1) as f32 / 2.0;
+
+		// Lowpass slightly below the base rate's Nyquist frequency
+		let cutoff = 0.9 / factor as f32;
+
+		let kernel = (0..len)
+			.map(|n| {
+				let x = n as f32 - center;
+				cutoff * sinc(x * cutoff) * blackman(x / (center + 1.0))
+			})
+			.collect::<Vec<_>>();
+
+		let sum: f32 = kernel.iter().sum();
+
+		Oversampler {
+			factor,
+			kernel: kernel.iter().map(|h| h / sum).collect(),
+			up_history: vec![Frame::ZERO; Self::TAPS_PER_PHASE],
+			up_pos: 0,
+			down_history: vec![Frame::ZERO; len],
+			down_pos: 0,
+		}
 	}
-}
\ No newline at end of file
+
+	pub fn factor(&self) -> usize {
+		self.factor
+	}
+
+	// Combined group delay of the up- and downsampling filters, in base rate samples
+	pub fn latency(&self) -> f32 {
+		if self.factor == 1 {
+			0.0
+		} else {
+			(self.kernel.len() - 1) as f32 / self.factor as f32
+		}
+	}
+
+	// Writes `factor` oversampled frames for one input frame
+	pub fn upsample(&mut self, input: Frame, output: &mut [Frame]) {
+		if self.factor == 1 {
+			output[0] = input;
+			return
+		}
+
+		let taps = Self::TAPS_PER_PHASE;
+
+		self.up_pos = (self.up_pos + taps - 1) % taps;
+		self.up_history[self.up_pos] = input;
+
+		for (phase, out) in output.iter_mut().enumerate().take(self.factor) {
+			let mut acc = Frame::ZERO;
+
+			for k in 0..taps {
+				acc += self.up_history[(self.up_pos + k) % taps] * self.kernel[phase + self.factor * k];
+			}
+
+			*out = acc * self.factor as f32;
+		}
+	}
+
+	// Filters and decimates `factor` oversampled frames back down to one
+	pub fn downsample(&mut self, input: &[Frame]) -> Frame {
+		if self.factor == 1 {
+			return input[0]
+		}
+
+		let len = self.down_history.len();
+
+		for frame in input.iter().take(self.factor) {
+			self.down_pos = (self.down_pos + len - 1) % len;
+			self.down_history[self.down_pos] = *frame;
+		}
+
+		let mut acc = Frame::ZERO;
+
+		for (n, h) in self.kernel.iter().enumerate() {
+			acc += self.down_history[(self.down_pos + n) % len] * *h;
+		}
+
+		acc
+	}
+
+	pub fn reset(&mut self) {
+		self.up_history.fill(Frame::ZERO);
+		self.down_history.fill(Frame::ZERO);
+	}
+}
+
+pub struct DelayLine {
+	buffer: Vec<f32>,
+	write_pos: usize,
+}
+
+impl DelayLine {
+	pub fn new(max_delay: usize) -> Self {
+		DelayLine {
+			buffer: vec![0.0; max_delay.max(1) + 1],
+			write_pos: 0,
+		}
+	}
+
+	pub fn max_delay(&self) -> usize {
+		self.buffer.len() - 1
+	}
+
+	pub fn read(&self, delay: usize) -> f32 {
+		let delay = delay.clamp(1, self.max_delay());
+		let len = self.buffer.len();
+
+		self.buffer[(self.write_pos + len - delay) % len]
+	}
+
+	pub fn read_frac(&self, delay: f32) -> f32 {
+		let delay = delay.clamp(1.0, (self.max_delay() as f32 - 1.0).max(1.0));
+		let whole = delay.floor();
+		let t = delay - whole;
+
+		lerp(self.read(whole as usize), self.read(whole as usize + 1), t)
+	}
+
+	// Delays are measured relative to the next write, so reading a delay
+	// of `n` before calling `write()` yields the input from `n` samples ago
+	pub fn write(&mut self, value: f32) {
+		self.buffer[self.write_pos] = value;
+		self.write_pos = (self.write_pos + 1) % self.buffer.len();
+	}
+
+	pub fn clear(&mut self) {
+		self.buffer.fill(0.0);
+	}
+}
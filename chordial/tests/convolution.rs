@@ -0,0 +1,73 @@
+use chordial::{engine::{Engine, Frame}, node::{BufferAccess, BusKind, Node, NodeInstance, OutputRef}, resource::AudioData};
+
+
+const BLOCK: usize = 256;
+
+// Outputs an impulse on the first frame it renders
+struct Impulse;
+
+impl Node for Impulse {
+	fn get_inputs(&self) -> &[BusKind] {
+		&[]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&[BusKind::Audio]
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Impulse"
+	}
+
+	fn render(&self, _output: usize, mut buffer: BufferAccess, _instance: &NodeInstance, engine: &Engine) {
+		if engine.position() == 0 {
+			buffer.audio_mut().unwrap()[0] = Frame(1.0, 1.0);
+		}
+	}
+}
+
+// Peak of the left channel over a few blocks, starting at the engine's position
+fn peak(engine: &mut Engine, node: usize) -> f32 {
+	let mut peak = 0.0f32;
+
+	for _ in 0..8 {
+		let output = engine.poll_node_output(&OutputRef { node, output: 0 }, BLOCK);
+
+		peak = output.audio().unwrap().iter().fold(peak, |peak, f| peak.max(f.0.abs()));
+
+		drop(output);
+		engine.render(&mut [Frame::ZERO; BLOCK]);
+	}
+
+	peak
+}
+
+#[test]
+fn ir_changes_are_picked_up() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let impulse = engine.add_node(Impulse, "impulse");
+	let convolution = engine.create_node("chordial.convolution").unwrap();
+
+	engine.get_node_mut(convolution).unwrap().inputs[0].0.push(OutputRef { node: impulse, output: 0 });
+
+	let ir = engine.add_resource(AudioData {
+		data: vec![Frame(0.5, 0.5)],
+		sample_rate: 48000,
+	});
+
+	// Linked directly instead of through `Engine::link_resource()`
+	engine.get_node(convolution).unwrap().node.get_resource("ir").link_dyn(&ir);
+	engine.render(&mut [Frame::ZERO; BLOCK]);
+	engine.seek(0);
+
+	assert!((peak(&mut engine, convolution) - 0.5).abs() < 1e-3);
+
+	// Edited in place
+	ir.inner().as_ref().unwrap().write().unwrap().data_mut().data[0] = Frame(0.25, 0.25);
+	engine.render(&mut [Frame::ZERO; BLOCK]);
+	engine.seek(0);
+
+	assert!((peak(&mut engine, convolution) - 0.25).abs() < 1e-3);
+}
@@ -0,0 +1,65 @@
+use std::sync::Mutex;
+
+use chordial::{engine::{Engine, Frame}, node::{BufferAccess, BusKind, Node, NodeInstance, OutputRef}, param::ParamValue};
+
+
+const BLOCK: usize = 512;
+
+// Loud noise with bursts, from a xorshift generator
+struct Noise(Mutex<u32>);
+
+impl Node for Noise {
+	fn get_inputs(&self) -> &[BusKind] {
+		&[]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&[BusKind::Audio]
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Noise"
+	}
+
+	fn render(&self, _output: usize, mut buffer: BufferAccess, _instance: &NodeInstance, _engine: &Engine) {
+		let mut seed = self.0.lock().unwrap();
+
+		for f in buffer.audio_mut().unwrap() {
+			*seed ^= *seed << 13;
+			*seed ^= *seed >> 17;
+			*seed ^= *seed << 5;
+
+			let value = *seed as f32 / u32::MAX as f32 * 2.0 - 1.0;
+			let burst = if seed.is_multiple_of(97) { 8.0 } else { 2.0 };
+
+			*f = Frame(value * burst, -value);
+		}
+	}
+}
+
+#[test]
+fn limiter_holds_the_ceiling() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let noise = engine.add_node(Noise(Mutex::new(1)), "noise");
+	let limiter = engine.create_node("chordial.limiter").unwrap();
+
+	engine.get_node_mut(limiter).unwrap().inputs[0].0.push(OutputRef { node: noise, output: 0 });
+	engine.set_node_param(limiter, 0, ParamValue::Float(-6.0));
+	engine.set_node_param(limiter, 2, ParamValue::Float(3.0));
+
+	let ceiling = 10.0f32.powf(-6.0 / 20.0);
+
+	// Long enough for a running sum to drift
+	for _ in 0..2000 {
+		let output = engine.poll_node_output(&OutputRef { node: limiter, output: 0 }, BLOCK);
+
+		for f in output.audio().unwrap() {
+			assert!(f.0.abs().max(f.1.abs()) <= ceiling * 1.0001, "{f:?}");
+		}
+
+		drop(output);
+		engine.render(&mut [Frame::ZERO; BLOCK]);
+	}
+}
@@ -0,0 +1,107 @@
+use std::{f32::consts::TAU, sync::Mutex};
+
+use chordial::{engine::{Engine, Frame}, node::{BufferAccess, BusKind, Node, NodeInstance, OutputRef}, param::ParamValue};
+
+
+const BLOCK: usize = 256;
+const FREQ: f32 = 1000.0;
+
+// A sine at `FREQ`, continuing where the last block left off
+struct Sine(Mutex<usize>);
+
+impl Node for Sine {
+	fn get_inputs(&self) -> &[BusKind] {
+		&[]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&[BusKind::Audio]
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Sine"
+	}
+
+	fn render(&self, _output: usize, mut buffer: BufferAccess, _instance: &NodeInstance, engine: &Engine) {
+		let mut pos = self.0.lock().unwrap();
+
+		for f in buffer.audio_mut().unwrap() {
+			let value = (TAU * FREQ * *pos as f32 / engine.config.sample_rate as f32).sin();
+
+			*f = Frame(value, value);
+			*pos += 1;
+		}
+	}
+}
+
+fn query(engine: &Engine, eq: usize, keys: &[ParamValue]) -> f64 {
+	let Some(ParamValue::Float(db)) = engine.get_node(eq).unwrap().node.query(keys) else {
+		panic!()
+	};
+
+	db
+}
+
+// Bell on the 4th band at `FREQ`
+fn set_bell(engine: &mut Engine, eq: usize, gain: f64) {
+	engine.set_node_param(eq, 12, ParamValue::Int(1));
+	engine.set_node_param(eq, 13, ParamValue::Float(FREQ as f64));
+	engine.set_node_param(eq, 14, ParamValue::Float(gain));
+	engine.set_node_param(eq, 15, ParamValue::Float(1.0));
+}
+
+#[test]
+fn magnitude_query() {
+	let mut engine = Engine::new(48000);
+	let eq = engine.create_node("chordial.eq").unwrap();
+
+	set_bell(&mut engine, eq, 6.0);
+	engine.set_node_param(eq, 33, ParamValue::Float(-2.0));
+
+	let band = |freq: f64| query(&engine, eq, &[ParamValue::String("get_band_magnitude".to_string()), ParamValue::Int(3), ParamValue::Float(freq)]);
+	let total = |freq: f64| query(&engine, eq, &[ParamValue::String("get_magnitude".to_string()), ParamValue::Float(freq)]);
+
+	assert!((band(FREQ as f64) - 6.0).abs() < 1e-3, "{}", band(FREQ as f64));
+	assert!(band(20.0).abs() < 0.1, "{}", band(20.0));
+
+	// Including the output gain
+	assert!((total(FREQ as f64) - 4.0).abs() < 1e-3, "{}", total(FREQ as f64));
+}
+
+#[test]
+fn linear_phase_band_changes_are_smooth() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let sine = engine.add_node(Sine(Mutex::new(0)), "sine");
+	let eq = engine.create_node("chordial.eq").unwrap();
+
+	engine.get_node_mut(eq).unwrap().inputs[0].0.push(OutputRef { node: sine, output: 0 });
+	engine.set_node_param(eq, 32, ParamValue::Bool(true));
+
+	let mut output = vec![];
+
+	for block in 0..200 {
+		// A few changes in a row, each one can only start fading in once the last is done
+		if (40..43).contains(&block) {
+			set_bell(&mut engine, eq, 6.0 + (block - 40) as f64);
+		}
+
+		let buffer = engine.poll_node_output(&OutputRef { node: eq, output: 0 }, BLOCK);
+
+		output.extend(buffer.audio().unwrap().iter().map(|f| f.0));
+
+		drop(buffer);
+		engine.render(&mut [Frame::ZERO; BLOCK]);
+	}
+
+	// Past the FIR's latency, the output changes no faster than a sine of the final amplitude
+	let steepest = (TAU * FREQ / 48000.0) * 10.0f32.powf(8.0 / 20.0);
+
+	assert!(output[BLOCK * 30..].windows(2).all(|pair| (pair[1] - pair[0]).abs() <= steepest * 1.05));
+
+	// And ends up at the final gain
+	let peak = output[output.len() - BLOCK * 4..].iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+
+	assert!((peak - 10.0f32.powf(8.0 / 20.0)).abs() < 0.02, "{peak}");
+}
@@ -0,0 +1,75 @@
+use chordial::{engine::{Engine, Frame}, feed::audio_feed, node::OutputRef, param::ParamValue};
+
+
+const BLOCK: usize = 64;
+
+fn source(engine: &mut Engine, feed: &str) -> usize {
+	let node = engine.create_node("chordial.source").unwrap();
+	engine.set_node_param(node, 0, ParamValue::String(feed.to_string()));
+
+	node
+}
+
+fn poll(engine: &Engine, node: usize) -> Vec<f32> {
+	engine.poll_node_output(&OutputRef { node, output: 0 }, BLOCK).audio().unwrap().iter().map(|f| f.0).collect()
+}
+
+#[test]
+fn sources_share_a_feed() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let (mut writer, reader) = audio_feed(1024, 4);
+	let ramp = (0..BLOCK * 4).map(|i| Frame(i as f32, i as f32)).collect::<Vec<_>>();
+
+	writer.write(&ramp);
+	engine.add_audio_feed("input", reader);
+
+	let a = source(&mut engine, "input");
+	let b = source(&mut engine, "input");
+
+	for block in 0..2 {
+		let first = poll(&engine, a);
+
+		// Both get every frame of the feed, not every other one. The
+		// reader speeds up a little to catch up with the writer.
+		assert_eq!(first, poll(&engine, b));
+		assert!((first[0] - (block * BLOCK) as f32).abs() < 0.1, "{first:?}");
+		assert!(first.windows(2).all(|pair| (pair[1] - pair[0] - 1.0).abs() < 0.01), "{first:?}");
+
+		engine.render(&mut [Frame::ZERO; BLOCK]);
+	}
+}
+
+#[test]
+fn stopped_engine_drains_feeds() {
+	let mut engine = Engine::new(48000);
+
+	let (mut writer, reader) = audio_feed(1024, 4);
+	engine.add_audio_feed("input", reader);
+
+	for _ in 0..100 {
+		writer.write(&[Frame(1.0, 1.0); BLOCK]);
+		engine.render(&mut [Frame::ZERO; BLOCK]);
+	}
+
+	let feed = engine.get_audio_feed("input").unwrap().lock().unwrap();
+
+	assert_eq!(feed.reader().available(), 0);
+	assert_eq!(feed.reader().overruns(), 0);
+}
+
+#[test]
+fn feeds_resample_other_rates() {
+	let (mut writer, mut reader) = audio_feed(1024, 64);
+	reader.set_sample_rates(24000, 48000);
+
+	let ramp = (0..256).map(|i| Frame(i as f32, i as f32)).collect::<Vec<_>>();
+	writer.write(&ramp);
+
+	let mut output = [Frame::ZERO; BLOCK];
+	reader.read(&mut output);
+
+	// Half a writer frame per engine frame, sped up a little to catch up with the writer
+	assert!(output.windows(2).all(|pair| (pair[1].0 - pair[0].0 - 0.5).abs() < 0.01), "{output:?}");
+}
@@ -0,0 +1,458 @@
+use chordial::{midi::{MidiBlock, MidiMessage, MidiMessageKind, MidiNoteDesc, MidiStatusByte, MidiStatusCode, MonoVoiceTracker, MpeConfig, MpeZoneKind, PolyVoiceTracker, SysExPool, VoiceStealing, SYSEX_SLOTS}, node::TlUnit};
+
+
+#[test]
+fn every_status_byte_parses_without_panicking() {
+	for byte in 0..=u8::MAX {
+		let code = MidiStatusByte(byte).code();
+
+		match byte {
+			0x00..=0x7F | 0xF4 | 0xF5 | 0xF7 | 0xF9 | 0xFD => assert_eq!(code, None, "{byte:#x}"),
+			_ => assert!(code.is_some(), "{byte:#x}"),
+		}
+
+		// Whatever the data, looking at the message must not panic either
+		let msg = MidiMessage::new(MidiStatusByte(byte), [0x7F, 0x7F]);
+		let _ = msg.kind();
+		let _ = msg.bytes();
+	}
+}
+
+#[test]
+fn channel_status_bytes() {
+	let cases = [
+		(0x80, MidiStatusCode::NoteOff),
+		(0x93, MidiStatusCode::NoteOn),
+		(0xA5, MidiStatusCode::PolyKeyPressure),
+		(0xB0, MidiStatusCode::CtrlChange),
+		(0xCF, MidiStatusCode::ProgramChange),
+		(0xD1, MidiStatusCode::ChannelPressure),
+		(0xE9, MidiStatusCode::PitchBendChange),
+	];
+
+	for (byte, expected) in cases {
+		let status = MidiStatusByte(byte);
+
+		assert_eq!(status.code(), Some(expected));
+		assert_eq!(status.channel(), byte & 0x0F);
+		assert!(status.is_channel_message());
+		assert_eq!(MidiStatusByte::new(expected, byte & 0x0F), status);
+	}
+}
+
+#[test]
+fn system_status_bytes() {
+	let cases = [
+		(0xF0, MidiStatusCode::SysEx),
+		(0xF1, MidiStatusCode::TimeCode),
+		(0xF2, MidiStatusCode::SongPosition),
+		(0xF3, MidiStatusCode::SongSelect),
+		(0xF6, MidiStatusCode::TuneRequest),
+		(0xF8, MidiStatusCode::TimingClock),
+		(0xFA, MidiStatusCode::Start),
+		(0xFB, MidiStatusCode::Continue),
+		(0xFC, MidiStatusCode::Stop),
+		(0xFE, MidiStatusCode::ActiveSensing),
+		(0xFF, MidiStatusCode::Reset),
+	];
+
+	for (byte, expected) in cases {
+		let status = MidiStatusByte(byte);
+
+		assert_eq!(status.code(), Some(expected));
+		assert!(!status.is_channel_message());
+		assert_eq!(expected.is_realtime(), byte >= 0xF8);
+
+		// System messages have no channel
+		assert_eq!(MidiStatusByte::new(expected, 5), status);
+	}
+}
+
+#[test]
+fn data_lengths() {
+	assert_eq!(MidiStatusCode::NoteOn.data_len(), 2);
+	assert_eq!(MidiStatusCode::ProgramChange.data_len(), 1);
+	assert_eq!(MidiStatusCode::ChannelPressure.data_len(), 1);
+	assert_eq!(MidiStatusCode::SongPosition.data_len(), 2);
+	assert_eq!(MidiStatusCode::TuneRequest.data_len(), 0);
+	assert_eq!(MidiStatusCode::TimingClock.data_len(), 0);
+}
+
+#[test]
+fn from_bytes() {
+	let msg = MidiMessage::from_bytes(&[0x91, 60, 100]).unwrap();
+	assert_eq!(msg.kind(), Some(MidiMessageKind::NoteOn { channel: 1, note: 60, velocity: 100 }));
+	assert_eq!(msg.bytes(), &[0x91, 60, 100]);
+
+	let msg = MidiMessage::from_bytes(&[0xC2, 7]).unwrap();
+	assert_eq!(msg.kind(), Some(MidiMessageKind::ProgramChange { channel: 2, program: 7 }));
+	assert_eq!(msg.bytes(), &[0xC2, 7]);
+
+	let msg = MidiMessage::from_bytes(&[0xF8]).unwrap();
+	assert_eq!(msg.kind(), Some(MidiMessageKind::TimingClock));
+	assert_eq!(msg.bytes(), &[0xF8]);
+
+	// Incomplete, data bytes with the high bit set, lone data bytes, undefined and SysEx
+	assert_eq!(MidiMessage::from_bytes(&[0x90, 60]), None);
+	assert_eq!(MidiMessage::from_bytes(&[0x90, 60, 0x80]), None);
+	assert_eq!(MidiMessage::from_bytes(&[0x40, 0x40]), None);
+	assert_eq!(MidiMessage::from_bytes(&[0xF4]), None);
+	assert_eq!(MidiMessage::from_bytes(&[0xF0, 1, 2, 0xF7]), None);
+	assert_eq!(MidiMessage::from_bytes(&[]), None);
+}
+
+#[test]
+fn pitch_bend_is_centered() {
+	let bend = |lsb, msb| MidiMessage::from_bytes(&[0xE0, lsb, msb]).unwrap().kind();
+
+	assert_eq!(bend(0x00, 0x40), Some(MidiMessageKind::PitchBend { channel: 0, value: 0 }));
+	assert_eq!(bend(0x00, 0x00), Some(MidiMessageKind::PitchBend { channel: 0, value: -8192 }));
+	assert_eq!(bend(0x7F, 0x7F), Some(MidiMessageKind::PitchBend { channel: 0, value: 8191 }));
+}
+
+#[test]
+fn kinds_round_trip() {
+	let kinds = [
+		MidiMessageKind::NoteOff { channel: 15, note: 0, velocity: 64 },
+		MidiMessageKind::NoteOn { channel: 0, note: 127, velocity: 1 },
+		MidiMessageKind::PolyPressure { channel: 3, note: 60, pressure: 90 },
+		MidiMessageKind::ControlChange { channel: 9, controller: 1, value: 127 },
+		MidiMessageKind::ProgramChange { channel: 4, program: 42 },
+		MidiMessageKind::ChannelPressure { channel: 2, pressure: 10 },
+		MidiMessageKind::PitchBend { channel: 1, value: -1234 },
+		MidiMessageKind::SysEx { slot: 200 },
+		MidiMessageKind::TimeCode { piece: 7, value: 3 },
+		MidiMessageKind::SongPosition { beats: 5000 },
+		MidiMessageKind::SongSelect { song: 12 },
+		MidiMessageKind::TuneRequest,
+		MidiMessageKind::TimingClock,
+		MidiMessageKind::Start,
+		MidiMessageKind::Continue,
+		MidiMessageKind::Stop,
+		MidiMessageKind::ActiveSensing,
+		MidiMessageKind::Reset,
+	];
+
+	for kind in kinds {
+		assert_eq!(MidiMessage::from_kind(kind).kind(), Some(kind));
+	}
+}
+
+#[test]
+fn note_on_with_zero_velocity_is_note_off() {
+	let msg = MidiMessage::from_bytes(&[0x90, 60, 0]).unwrap();
+
+	assert!(msg.is_note_off());
+	assert!(!msg.is_note_on());
+}
+
+#[test]
+fn sysex_pool() {
+	let pool = SysExPool::new();
+	let payload = [0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7];
+
+	let msg = pool.insert(&payload);
+
+	assert_eq!(msg.status_byte().code(), Some(MidiStatusCode::SysEx));
+	assert_eq!(pool.get(&msg).as_deref(), Some(&payload[..]));
+	assert_eq!(pool.get(&MidiMessage::from_bytes(&[0xF8]).unwrap()), None);
+
+	// Slots are reused once the pool wraps around
+	for _ in 0..SYSEX_SLOTS {
+		pool.insert(&[0xF0, 0x01, 0xF7]);
+	}
+
+	assert_eq!(pool.get(&msg).as_deref(), Some(&[0xF0, 0x01, 0xF7][..]));
+}
+
+#[test]
+fn trackers_ignore_system_messages() {
+	let mut mono = MonoVoiceTracker::new();
+	let mut poly = PolyVoiceTracker::new();
+
+	let note_on = MidiMessage::from_bytes(&[0x90, 60, 100]).unwrap();
+
+	for byte in [0xF8, 0xFA, 0xFC, 0xFE, 0xFF] {
+		let msg = MidiMessage::from_bytes(&[byte]).unwrap();
+
+		mono.apply_midi_message(msg, 0);
+		poly.apply_midi_message(msg, 0);
+	}
+
+	mono.apply_midi_message(note_on, 0);
+	poly.apply_midi_message(note_on, 0);
+	mono.apply_midi_message(MidiMessage::from_bytes(&[0xC0, 5]).unwrap(), 0);
+	poly.apply_midi_message(MidiMessage::from_bytes(&[0xC0, 5]).unwrap(), 0);
+
+	assert_eq!(mono.voice.map(|voice| voice.note), Some(60));
+	assert_eq!(poly.voices.len(), 1);
+}
+
+#[test]
+fn trackers_follow_channel_controllers() {
+	let mut mono = MonoVoiceTracker::new();
+	let mut poly = PolyVoiceTracker::new();
+
+	let msgs = [
+		MidiMessageKind::PitchBend { channel: 0, value: 8192 / 2 },
+		MidiMessageKind::NoteOn { channel: 0, note: 60, velocity: 100 },
+		MidiMessageKind::ControlChange { channel: 0, controller: 1, value: 127 },
+		MidiMessageKind::ChannelPressure { channel: 0, pressure: 127 },
+		// Other channels don't affect the voice
+		MidiMessageKind::PitchBend { channel: 1, value: -8192 },
+	];
+
+	for kind in msgs {
+		mono.apply_midi_message(MidiMessage::from_kind(kind), 0);
+		poly.apply_midi_message(MidiMessage::from_kind(kind), 0);
+	}
+
+	for voice in [mono.voice.unwrap(), *poly.voice(0, 60).unwrap()] {
+		assert_eq!(voice.pitch(), 61.0);
+		assert_eq!(voice.modulation, 1.0);
+		assert_eq!(voice.pressure, 1.0);
+	}
+
+	let poly_pressure = MidiMessageKind::PolyPressure { channel: 0, note: 60, pressure: 0 };
+	let reset = MidiMessageKind::ControlChange { channel: 0, controller: 121, value: 0 };
+
+	poly.apply_midi_message(MidiMessage::from_kind(poly_pressure), 0);
+	assert_eq!(poly.voice(0, 60).unwrap().pressure, 0.0);
+
+	poly.apply_midi_message(MidiMessage::from_kind(reset), 0);
+	assert_eq!(poly.voice(0, 60).unwrap().pitch(), 60.0);
+	assert_eq!(poly.voice(0, 60).unwrap().modulation, 0.0);
+}
+
+fn cc(channel: u8, controller: u8, value: u8) -> MidiMessage {
+	MidiMessage::from_kind(MidiMessageKind::ControlChange { channel, controller, value })
+}
+
+fn send_rpn(tracker: &mut PolyVoiceTracker, channel: u8, rpn: u8, value: u8) {
+	for msg in [cc(channel, 101, 0), cc(channel, 100, rpn), cc(channel, 6, value)] {
+		tracker.apply_midi_message(msg, 0);
+	}
+}
+
+#[test]
+fn mpe_zones() {
+	let mut mpe = MpeConfig::new();
+
+	assert_eq!(mpe.zone_of(0), Some(MpeZoneKind::Lower));
+	assert_eq!(mpe.zone_of(15), Some(MpeZoneKind::Lower));
+
+	mpe.configure(MpeZoneKind::Upper, 4);
+
+	// The lower zone shrinks to make room
+	assert_eq!(mpe.member_channels(MpeZoneKind::Upper), 11..=14);
+	assert_eq!(mpe.member_channels(MpeZoneKind::Lower), 1..=10);
+	assert_eq!(mpe.zone_of(12), Some(MpeZoneKind::Upper));
+
+	mpe.configure(MpeZoneKind::Lower, 0);
+
+	assert_eq!(mpe.zone_of(0), None);
+	assert_eq!(mpe.zone_of(5), None);
+
+	mpe.configure(MpeZoneKind::Lower, 12);
+
+	assert_eq!(mpe.member_channels(MpeZoneKind::Lower), 1..=12);
+	assert_eq!(mpe.member_channels(MpeZoneKind::Upper), 13..=14);
+	assert_eq!(mpe.zone_of(13), Some(MpeZoneKind::Upper));
+}
+
+#[test]
+fn mpe_per_note_expression() {
+	let mut poly = PolyVoiceTracker::new();
+	poly.channels.mpe = Some(MpeConfig::new());
+
+	let on = |channel, note| MidiMessage::from_kind(MidiMessageKind::NoteOn { channel, note, velocity: 100 });
+	let bend = |channel, value| MidiMessage::from_kind(MidiMessageKind::PitchBend { channel, value });
+	let pressure = |channel, pressure| MidiMessage::from_kind(MidiMessageKind::ChannelPressure { channel, pressure });
+
+	for msg in [on(1, 60), on(2, 64), bend(1, 4096), pressure(2, 127), cc(2, 74, 0)] {
+		poly.apply_midi_message(msg, 0);
+	}
+
+	assert_eq!(poly.voice(1, 60).unwrap().pitch(), 84.0);
+	assert_eq!(poly.voice(1, 60).unwrap().pressure, 0.0);
+	assert_eq!(poly.voice(2, 64).unwrap().pitch(), 64.0);
+	assert_eq!(poly.voice(2, 64).unwrap().pressure, 1.0);
+	assert_eq!(poly.voice(2, 64).unwrap().slide, 0.0);
+
+	// Manager channel bend applies to the whole zone, on top of per-note bend
+	poly.apply_midi_message(bend(0, -8192), 0);
+
+	assert_eq!(poly.voice(1, 60).unwrap().pitch(), 82.0);
+	assert_eq!(poly.voice(2, 64).unwrap().pitch(), 62.0);
+
+	// Bend sensitivity sent to a member channel applies to every member
+	poly.apply_midi_message(bend(0, 0), 0);
+	send_rpn(&mut poly, 2, 0, 12);
+
+	assert_eq!(poly.voice(1, 60).unwrap().pitch(), 66.0);
+}
+
+#[test]
+fn mpe_configuration_message() {
+	let mut poly = PolyVoiceTracker::new();
+
+	// Ignored outside MPE mode
+	send_rpn(&mut poly, 15, 6, 3);
+	assert_eq!(poly.channels.mpe, None);
+
+	poly.channels.mpe = Some(MpeConfig::new());
+	send_rpn(&mut poly, 15, 6, 3);
+
+	let mpe = poly.channels.mpe.unwrap();
+
+	assert_eq!(mpe.member_channels(MpeZoneKind::Upper), 12..=14);
+	assert_eq!(mpe.member_channels(MpeZoneKind::Lower), 1..=11);
+
+	// Channels outside the zones behave like regular MIDI
+	send_rpn(&mut poly, 0, 6, 0);
+	poly.apply_midi_message(MidiMessage::from_kind(MidiMessageKind::NoteOn { channel: 3, note: 60, velocity: 100 }), 0);
+	poly.apply_midi_message(MidiMessage::from_kind(MidiMessageKind::PitchBend { channel: 3, value: 4096 }), 0);
+
+	assert_eq!(poly.voice(3, 60).unwrap().pitch(), 61.0);
+}
+
+fn note_on(channel: u8, note: u8, velocity: u8) -> MidiMessage {
+	MidiMessage::from_kind(MidiMessageKind::NoteOn { channel, note, velocity })
+}
+
+fn note_off(channel: u8, note: u8) -> MidiMessage {
+	MidiMessage::from_kind(MidiMessageKind::NoteOff { channel, note, velocity: 0 })
+}
+
+#[test]
+fn voice_stealing() {
+	let cases = [
+		(VoiceStealing::Oldest, [60, 67, 72]),
+		(VoiceStealing::Quietest, [60, 67, 72]),
+		(VoiceStealing::Lowest, [64, 67, 72]),
+		(VoiceStealing::Highest, [60, 64, 72]),
+		(VoiceStealing::ReleasedFirst, [60, 64, 72]),
+	];
+
+	for (stealing, expected) in cases {
+		let mut poly = PolyVoiceTracker::new();
+		poly.polyphony = 3;
+		poly.release_length = 100;
+		poly.stealing = stealing;
+
+		for msg in [note_on(0, 64, 10), note_on(0, 60, 100), note_on(0, 67, 100), note_off(0, 67)] {
+			poly.apply_midi_message(msg, 0);
+		}
+
+		poly.apply_midi_message(note_on(0, 72, 100), 0);
+
+		let mut notes = poly.voices.iter().map(|v| v.note).collect::<Vec<_>>();
+		notes.sort();
+
+		assert_eq!(notes, expected, "{stealing:?}");
+	}
+
+	assert_eq!(VoiceStealing::from_index(4), VoiceStealing::ReleasedFirst);
+	assert_eq!(VoiceStealing::from_index(-1), VoiceStealing::Oldest);
+	assert_eq!(VoiceStealing::from_index(99), VoiceStealing::Oldest);
+}
+
+#[test]
+fn retrigger() {
+	let mut poly = PolyVoiceTracker::new();
+	poly.release_length = 100;
+
+	poly.apply_midi_message(note_on(0, 60, 50), 0);
+	poly.voices[0].progress = 500;
+	poly.voices[0].phase = 0.25;
+	poly.apply_midi_message(note_off(0, 60), 0);
+	poly.apply_midi_message(note_on(0, 60, 90), 0);
+
+	assert_eq!(poly.voices.len(), 1);
+	assert_eq!((poly.voices[0].progress, poly.voices[0].velocity, poly.voices[0].released), (0, 90, false));
+	assert_eq!(poly.voices[0].phase, 0.0);
+
+	// Without retrigger, the released voice rings out next to the new one
+	poly.retrigger = false;
+	poly.apply_midi_message(note_off(0, 60), 0);
+	poly.apply_midi_message(note_on(0, 60, 70), 0);
+
+	assert_eq!(poly.voices.len(), 2);
+	assert!(poly.voices[0].released);
+
+	poly.apply_midi_message(note_off(0, 60), 0);
+
+	assert!(poly.voices.iter().all(|v| v.released));
+}
+
+#[test]
+fn mono_legato_and_glide() {
+	let mut mono = MonoVoiceTracker::new();
+	mono.legato = true;
+	mono.glide_time = 100;
+
+	mono.apply_midi_message(note_on(0, 60, 100), 0);
+	mono.voice.as_mut().unwrap().progress = 1000;
+	mono.apply_midi_message(note_on(0, 72, 20), 0);
+
+	let voice = mono.voice.as_mut().unwrap();
+
+	// The envelope carries on, the pitch starts from the previous note
+	assert_eq!((voice.note, voice.progress, voice.velocity), (72, 1000, 100));
+	assert_eq!(voice.pitch(), 60.0);
+
+	voice.progress += 50;
+	assert_eq!(voice.pitch(), 66.0);
+
+	voice.progress += 50;
+	assert_eq!(voice.pitch(), 72.0);
+
+	// Releasing the last note goes back to the one still held
+	mono.apply_midi_message(note_off(0, 72), 0);
+
+	assert_eq!(mono.voice.map(|v| (v.note, v.released)), Some((60, false)));
+
+	mono.apply_midi_message(note_off(0, 60), 0);
+
+	assert_eq!(mono.voice, None);
+
+	// Without legato, new notes restart the voice
+	mono.legato = false;
+	mono.glide_time = 0;
+
+	mono.apply_midi_message(note_on(0, 60, 100), 0);
+	mono.voice.as_mut().unwrap().progress = 1000;
+	mono.apply_midi_message(note_on(0, 64, 100), 0);
+
+	assert_eq!(mono.voice.map(|v| (v.note, v.progress)), Some((64, 0)));
+	assert_eq!(mono.voice.unwrap().pitch(), 64.0);
+}
+
+#[test]
+fn block_edits_keep_the_index() {
+	let note = |pos, len, note| MidiNoteDesc { pos: TlUnit(pos), len: TlUnit(len), note, vel: 100 };
+
+	let mut block = MidiBlock::default();
+
+	block.add_note(0, note(96, 24, 60));
+	block.add_note(0, note(0, 48, 62));
+	block.add_note(3, note(24, 0, 64));
+	block.add_note(0, note(24, 24, 65));
+	block.update_note(0, 1, note(48, 48, 67));
+	block.remove_note(0, 0);
+	block.add_note(3, note(48, 12, 69));
+
+	// The same as indexing the final notes from scratch
+	let rebuilt = MidiBlock::from_channels(block.channels().clone());
+
+	assert_eq!(block.events(), rebuilt.events());
+	assert_eq!(block.events().len(), 7);
+	assert_eq!(block.end(), TlUnit(96));
+
+	for event in block.events() {
+		let note = block.get_note(event);
+		let pos = if event.is_end { note.pos + note.len } else { note.pos };
+
+		assert_eq!(pos, event.pos);
+	}
+}
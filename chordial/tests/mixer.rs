@@ -0,0 +1,185 @@
+use chordial::{engine::{Engine, Frame}, node::{BufferAccess, BusKind, Node, NodeInstance, OutputRef}, param::ParamValue};
+
+
+const BLOCK: usize = 64;
+
+// Outputs a constant frame
+struct Constant(f32);
+
+impl Node for Constant {
+	fn get_inputs(&self) -> &[BusKind] {
+		&[]
+	}
+
+	fn get_outputs(&self) -> &[BusKind] {
+		&[BusKind::Audio]
+	}
+
+	fn get_name(&self) -> &'static str {
+		"Constant"
+	}
+
+	fn render(&self, _output: usize, mut buffer: BufferAccess, _instance: &NodeInstance, _engine: &Engine) {
+		buffer.audio_mut().unwrap().fill(Frame(self.0, self.0));
+	}
+}
+
+fn connect(engine: &mut Engine, from: usize, to: usize, input: usize) {
+	engine.get_node_mut(to).unwrap().inputs[input].0.push(OutputRef { node: from, output: 0 });
+}
+
+fn poll(engine: &Engine, node: usize, output: usize) -> (f32, f32) {
+	let frame = engine.poll_node_output(&OutputRef { node, output }, BLOCK).audio().unwrap()[0];
+
+	(frame.0, frame.1)
+}
+
+// Advances every node to the next block
+fn next_block(engine: &mut Engine) {
+	engine.render(&mut [Frame::ZERO; BLOCK]);
+}
+
+#[test]
+fn inputs_are_summed_once_per_block() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let mixer = engine.create_node("chordial.mixer").unwrap();
+	let a = engine.add_node(Constant(0.25), "constant");
+	let b = engine.add_node(Constant(0.5), "constant");
+
+	connect(&mut engine, a, mixer, 0);
+	connect(&mut engine, b, mixer, 0);
+
+	// Channel 1 fully into both sends
+	engine.set_node_param(mixer, 7, ParamValue::Float(1.0));
+	engine.set_node_param(mixer, 8, ParamValue::Float(1.0));
+
+	for _ in 0..3 {
+		// Every output sees the input summed exactly once, whatever order they're rendered in
+		for output in [2, 0, 1] {
+			assert_eq!(poll(&engine, mixer, output), (0.75, 0.75), "output {output}");
+		}
+
+		next_block(&mut engine);
+	}
+}
+
+#[test]
+fn pan_is_a_balance_control() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let mixer = engine.create_node("chordial.mixer").unwrap();
+	let a = engine.add_node(Constant(1.0), "constant");
+
+	connect(&mut engine, a, mixer, 0);
+
+	assert_eq!(poll(&engine, mixer, 0), (1.0, 1.0));
+
+	next_block(&mut engine);
+	engine.set_node_param(mixer, 4, ParamValue::Float(0.5));
+
+	assert_eq!(poll(&engine, mixer, 0), (0.5, 1.0));
+}
+
+#[test]
+fn regrown_channels_start_at_defaults() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let mixer = engine.create_node("chordial.mixer").unwrap();
+	let a = engine.add_node(Constant(1.0), "constant");
+
+	// Mute channel 4, then remove and re-add it
+	engine.set_node_param(mixer, 29, ParamValue::Bool(true));
+	engine.set_node_param(mixer, 0, ParamValue::Int(3));
+	engine.set_node_param(mixer, 0, ParamValue::Int(4));
+
+	assert!(matches!(engine.get_node(mixer).unwrap().get_params()[29].1, ParamValue::Bool(false)));
+
+	connect(&mut engine, a, mixer, 3);
+
+	assert_eq!(poll(&engine, mixer, 0), (1.0, 1.0));
+}
+
+#[test]
+fn inputs_changing_kind_are_disconnected() {
+	let mut engine = Engine::new(48000);
+
+	let sink = engine.create_node("chordial.sink").unwrap();
+	let a = engine.add_node(Constant(1.0), "constant");
+
+	connect(&mut engine, a, sink, 0);
+
+	let removed = engine.set_node_param(sink, 0, ParamValue::Int(1));
+
+	assert_eq!(removed.len(), 1);
+	assert_eq!((removed[0].from.node, removed[0].to_node, removed[0].to_input), (a, sink, 0));
+	assert!(engine.get_node(sink).unwrap().inputs[0].0.is_empty());
+
+	// Setting the same width again keeps new connections
+	connect(&mut engine, a, sink, 0);
+
+	assert!(engine.set_node_param(sink, 0, ParamValue::Int(1)).is_empty());
+	assert_eq!(engine.get_node(sink).unwrap().inputs[0].0.len(), 1);
+}
+
+#[test]
+fn save_load_keeps_multichannel_connections() {
+	let mut engine = Engine::new(48000);
+
+	let gain = engine.create_node("chordial.gain").unwrap();
+	let upmix = engine.create_node("chordial.channel_convert").unwrap();
+	let convert = engine.create_node("chordial.channel_convert").unwrap();
+
+	engine.set_node_param(upmix, 1, ParamValue::Int(6));
+	engine.set_node_param(convert, 0, ParamValue::Int(6));
+	engine.set_node_param(convert, 1, ParamValue::Int(6));
+	engine.set_node_param(0, 0, ParamValue::Int(6));
+
+	connect(&mut engine, gain, upmix, 0);
+	connect(&mut engine, upmix, convert, 0);
+	connect(&mut engine, convert, 0, 0);
+
+	let path = std::env::temp_dir().join(format!("chordial-save-load-{}.chrd", std::process::id()));
+
+	engine.save(&mut std::fs::File::create(&path).unwrap()).unwrap();
+	engine.load(&path);
+	std::fs::remove_file(&path).unwrap();
+
+	for (from, to) in [(gain, upmix), (upmix, convert), (convert, 0)] {
+		let inputs = &engine.get_node(to).unwrap().inputs[0].0;
+
+		assert_eq!(inputs.iter().map(|r| (r.node, r.output)).collect::<Vec<_>>(), [(from, 0)], "input of {to}");
+	}
+
+	assert_eq!(engine.output_channels(), 6);
+}
+
+#[test]
+fn mismatched_channel_counts_are_converted() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let a = engine.add_node(Constant(0.5), "constant");
+	let upmix = engine.create_node("chordial.channel_convert").unwrap();
+	let quad = engine.create_node("chordial.channel_convert").unwrap();
+	let gain = engine.create_node("chordial.gain").unwrap();
+
+	engine.set_node_param(upmix, 1, ParamValue::Int(6));
+	engine.set_node_param(quad, 1, ParamValue::Int(4));
+	engine.set_node_param(0, 0, ParamValue::Int(6));
+
+	// 5.1 into a stereo input, then stereo and quad into a 5.1 input
+	connect(&mut engine, a, upmix, 0);
+	connect(&mut engine, a, quad, 0);
+	connect(&mut engine, upmix, gain, 0);
+	connect(&mut engine, gain, 0, 0);
+	connect(&mut engine, quad, 0, 0);
+
+	let mut buffer = [0.0; BLOCK * 6];
+	engine.render_interleaved(&mut buffer, 6);
+
+	assert_eq!(buffer[..6], [1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+}
@@ -0,0 +1,159 @@
+use chordial::{engine::Engine, midi::{MidiBlock, MidiNoteDesc}, node::{timeline::MidiClip, TimelineTransform, TlUnit}, param::ParamValue, smf::{read_smf, write_block_smf, write_smf, SmfChannelMapping, SmfTrack}, tempo::{TempoMap, TempoRamp}};
+
+
+fn note(pos: usize, len: usize, note: u8, vel: u8) -> MidiNoteDesc {
+	MidiNoteDesc {
+		pos: TlUnit(pos),
+		len: TlUnit(len),
+		note,
+		vel,
+	}
+}
+
+// Notes of a channel as (pos, len, note, vel), sorted
+fn channel_notes(block: &MidiBlock, channel: usize) -> Vec<(usize, usize, u8, u8)> {
+	let mut notes = block.channel(channel)
+		.iter()
+		.map(|n| (n.pos.0, n.len.0, n.note, n.vel))
+		.collect::<Vec<_>>();
+
+	notes.sort();
+	notes
+}
+
+#[test]
+fn round_trip() {
+	let mut block = MidiBlock::default();
+
+	// A chord, which is written with running status
+	for key in [60, 64, 67] {
+		block.add_note(0, note(0, 96, key, 100));
+	}
+
+	// The same key overlapping itself, and a zero length note
+	block.add_note(3, note(24, 48, 50, 80));
+	block.add_note(3, note(48, 48, 50, 90));
+	block.add_note(3, note(120, 0, 52, 1));
+	block.add_note(9, note(384, 12, 36, 127));
+
+	let mut tempo = TempoMap::constant(120.0);
+	tempo.set_tempo(TlUnit(384), 90.0, TempoRamp::Step);
+	tempo.set_time_signature(TlUnit(0), 3, 4);
+	tempo.set_time_signature(TlUnit(288), 6, 8);
+
+	let data = write_smf(&[SmfTrack::from_block(&block, Some("lead".to_string()))], Some(&tempo));
+	let import = read_smf(&data, SmfChannelMapping::ByChannel).unwrap();
+
+	assert_eq!(import.track_names, ["lead"]);
+
+	for channel in [0, 3, 9] {
+		let mut expected = channel_notes(&block, channel);
+
+		// Zero length notes come back one unit long
+		for note in &mut expected {
+			note.1 = note.1.max(1);
+		}
+
+		assert_eq!(channel_notes(&import.block, channel), expected, "channel {channel}");
+	}
+
+	assert_eq!(import.block.events().len(), block.events().len() + 1);
+
+	let imported = import.tempo.unwrap();
+
+	// Tempos are stored in whole microseconds per beat
+	for (imported, point) in imported.tempo_points().iter().zip(tempo.tempo_points()) {
+		assert_eq!((imported.pos, imported.ramp), (point.pos, point.ramp));
+		assert!((imported.bpm - point.bpm).abs() < 1e-3, "{} != {}", imported.bpm, point.bpm);
+	}
+
+	assert_eq!(imported.tempo_points().len(), tempo.tempo_points().len());
+	assert_eq!(imported.time_signatures(), tempo.time_signatures());
+}
+
+#[test]
+fn running_status() {
+	let mut block = MidiBlock::default();
+
+	for key in [60, 64, 67] {
+		block.add_note(0, note(0, 24, key, 100));
+	}
+
+	let data = write_block_smf(&block, None);
+
+	// Header, then a tempo track with only the end of track event
+	let track = &data[14 + 12..];
+	let len = u32::from_be_bytes(track[4..8].try_into().unwrap());
+
+	// Three deltas and a full note on, two without status, the same for the note offs,
+	// then the end of track event
+	assert_eq!(len, 3 + 3 + 2 * 2 + 3 + 3 + 2 * 2 + 4);
+
+	// Running status over a note off written as a note on with velocity 0
+	let track: &[u8] = &[
+		0x00, 0x90, 60, 100,
+		0x00, 62, 100,
+		0x60, 60, 0,
+		0x00, 62, 0,
+		0x00, 0xFF, 0x2F, 0x00,
+	];
+
+	let mut data = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk".to_vec();
+	data.extend((track.len() as u32).to_be_bytes());
+	data.extend(track);
+
+	let import = read_smf(&data, SmfChannelMapping::ByChannel).unwrap();
+
+	assert_eq!(channel_notes(&import.block, 0), [(0, 96, 60, 100), (0, 96, 62, 100)]);
+	assert!(import.tempo.is_none());
+}
+
+#[test]
+fn channel_mapping() {
+	let track = |key| SmfTrack {
+		name: None,
+		notes: vec![(0, note(0, 24, key, 100))],
+	};
+
+	let data = write_smf(&[track(60), track(62)], None);
+
+	let by_channel = read_smf(&data, SmfChannelMapping::ByChannel).unwrap();
+
+	assert_eq!(channel_notes(&by_channel.block, 0), [(0, 24, 60, 100), (0, 24, 62, 100)]);
+
+	// The tempo track is track 0
+	let by_track = read_smf(&data, SmfChannelMapping::ByTrack).unwrap();
+
+	assert_eq!(channel_notes(&by_track.block, 1), [(0, 24, 60, 100)]);
+	assert_eq!(channel_notes(&by_track.block, 2), [(0, 24, 62, 100)]);
+}
+
+#[test]
+fn export_cuts_clips_to_their_offsets() {
+	let mut engine = Engine::new(48000);
+
+	let mut block = MidiBlock::default();
+	block.add_note(0, note(0, 48, 60, 100));
+	block.add_note(0, note(40, 40, 62, 100));
+	block.add_note(0, note(80, 8, 64, 100));
+
+	let data = engine.add_resource(block);
+	let clip = engine.add_node(MidiClip::new(data), "chordial.midi_clip");
+
+	// 96 units long, the first and last 24 hidden
+	engine.set_node_param(clip, 0, ParamValue::Int(96));
+	engine.get_node_mut(clip).unwrap().set_timeline_transform(TimelineTransform {
+		position: TlUnit(96),
+		start_offset: TlUnit(24),
+		end_offset: TlUnit(24),
+	});
+
+	let path = std::env::temp_dir().join(format!("chordial-export-{}.mid", std::process::id()));
+
+	engine.export_smf(&path).unwrap();
+
+	let import = read_smf(&std::fs::read(&path).unwrap(), SmfChannelMapping::ByChannel).unwrap();
+	std::fs::remove_file(&path).unwrap();
+
+	assert_eq!(channel_notes(&import.block, 0), [(96, 24, 60, 100), (112, 32, 62, 100)]);
+}
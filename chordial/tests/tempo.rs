@@ -0,0 +1,109 @@
+use chordial::{engine::Config, node::TlUnit, tempo::{MusicalPosition, TempoMap, TempoRamp}, tuning::Tuning};
+
+
+fn config(sample_rate: u32, tempo: TempoMap) -> Config {
+	Config {
+		sample_rate,
+		tempo,
+		tuning: Tuning::default(),
+	}
+}
+
+// Every timeline unit maps to the first frame at or after it, and back
+fn assert_round_trip(config: &Config, units: usize) {
+	for unit in 1..units {
+		let frame = config.tl_units_to_frames(TlUnit(unit));
+
+		assert_eq!(config.frames_to_tl_units(frame), TlUnit(unit), "unit {unit} at frame {frame}");
+		assert_eq!(config.frames_to_tl_units(frame - 1), TlUnit(unit - 1), "frame before unit {unit}");
+	}
+}
+
+#[test]
+fn constant_tempo_is_exact() {
+	// 250 frames per unit
+	let config_48k = config(48000, TempoMap::constant(120.0));
+
+	assert_eq!(config_48k.tl_units_to_frames(TlUnit(96)), 24000);
+	assert_eq!(config_48k.frames_to_tl_units(24000), TlUnit(96));
+	assert_eq!(config_48k.frames_to_tl_units(23999), TlUnit(95));
+
+	// 196.875 frames per unit, every 8th unit lands exactly on a frame
+	let config_44k = config(44100, TempoMap::constant(140.0));
+
+	assert_eq!(config_44k.tl_units_to_frames(TlUnit(1)), 197);
+	assert_eq!(config_44k.tl_units_to_frames(TlUnit(8)), 1575);
+	assert_eq!(config_44k.frames_to_tl_units(1575), TlUnit(8));
+	assert_eq!(config_44k.frames_to_tl_units(1574), TlUnit(7));
+
+	assert_round_trip(&config_44k, 100_000);
+
+	// Fractional tempos
+	let config_frac = config(44100, TempoMap::constant(128.3));
+
+	assert_round_trip(&config_frac, 100_000);
+
+	// Far along the timeline
+	let far = 1 << 30;
+	let frame = config_44k.tl_units_to_frames(TlUnit(far));
+
+	assert_eq!(frame, (far as u128 * 1575 / 8) as usize);
+	assert_eq!(config_44k.frames_to_tl_units(frame), TlUnit(far));
+}
+
+#[test]
+fn tempo_changes() {
+	let mut tempo = TempoMap::constant(120.0);
+	tempo.set_tempo(TlUnit(96), 90.0, TempoRamp::Step);
+
+	let config = config(48000, tempo);
+
+	// One beat at 120, then 1000/3 frames per unit
+	assert_eq!(config.tl_units_to_frames(TlUnit(96)), 24000);
+	assert_eq!(config.tl_units_to_frames(TlUnit(97)), 24334);
+	assert_eq!(config.tl_units_to_frames(TlUnit(99)), 25000);
+	assert_eq!(config.frames_to_tl_units(24999), TlUnit(98));
+	assert_eq!(config.frames_to_tl_units(25000), TlUnit(99));
+
+	assert_round_trip(&config, 10_000);
+}
+
+#[test]
+fn tempo_ramps() {
+	let mut tempo = TempoMap::constant(120.0);
+	tempo.set_tempo(TlUnit(96), 120.0, TempoRamp::Linear);
+	tempo.set_tempo(TlUnit(192), 240.0, TempoRamp::Step);
+
+	assert_eq!(tempo.bpm_at(TlUnit(144)), 180.0);
+	assert_eq!(tempo.bpm_at(TlUnit(500)), 240.0);
+
+	let config = config(48000, tempo);
+
+	// Exact up to the ramp, and monotonic through it
+	assert_eq!(config.tl_units_to_frames(TlUnit(96)), 24000);
+
+	let frames = (0..400).map(|unit| config.tl_units_to_frames(TlUnit(unit))).collect::<Vec<_>>();
+
+	assert!(frames.windows(2).all(|pair| pair[0] < pair[1]));
+
+	// The ramp takes ln(2) / 2 seconds
+	let ramp_end = 24000.0 + 48000.0 * 2.0f64.ln() / 2.0;
+
+	assert!((frames[192] as f64 - ramp_end).abs() <= 1.0);
+}
+
+#[test]
+fn musical_positions() {
+	let mut tempo = TempoMap::default();
+
+	// Two bars of 4/4, then 6/8 from halfway through the third bar, which cuts it short
+	tempo.set_time_signature(TlUnit(96 * 10), 6, 8);
+
+	assert_eq!(tempo.musical_position(TlUnit(96 * 5 + 24)), MusicalPosition { bar: 1, beat: 1, tick: 24 });
+	assert_eq!(tempo.musical_position(TlUnit(96 * 10)), MusicalPosition { bar: 3, beat: 0, tick: 0 });
+	assert_eq!(tempo.musical_position(TlUnit(96 * 10 + 48 * 7 + 5)), MusicalPosition { bar: 4, beat: 1, tick: 5 });
+
+	for pos in [0, 96 * 5 + 24, 96 * 10, 96 * 10 + 48 * 7 + 5] {
+		assert_eq!(tempo.musical_to_tl(tempo.musical_position(TlUnit(pos))), TlUnit(pos));
+	}
+}
@@ -0,0 +1,164 @@
+use chordial::{engine::{Engine, Frame}, midi::{MidiBlock, MidiNoteDesc}, node::{Buffer, BusKind, OutputRef, TlUnit, timeline::MidiClip}, param::ParamValue};
+
+
+const BLOCK: usize = 256;
+
+fn note(pos: usize, len: usize, note: u8) -> MidiNoteDesc {
+	MidiNoteDesc {
+		pos: TlUnit(pos),
+		len: TlUnit(len),
+		note,
+		vel: 100,
+	}
+}
+
+// Every note on and off the clip plays until `frames`, as (frame, note, is_on)
+fn render_events(engine: &mut Engine, clip: usize, frames: usize) -> Vec<(usize, u8, bool)> {
+	let mut events = vec![];
+
+	for pos in (0..frames).step_by(BLOCK) {
+		engine.seek(pos);
+		engine.get_node_mut(clip).unwrap().clear_buffers();
+
+		let output = engine.poll_node_output(&OutputRef { node: clip, output: 0 }, BLOCK);
+
+		let Buffer::Midi(midi) = &*output else {
+			panic!()
+		};
+
+		for (i, chain) in midi[..BLOCK].iter().enumerate() {
+			for msg in chain {
+				if msg.is_note_on() || msg.is_note_off() {
+					events.push((pos + i, msg.data()[1], msg.is_note_on()));
+				}
+			}
+		}
+	}
+
+	events
+}
+
+#[test]
+fn swing_past_the_loop_end_drops_notes() {
+	let mut engine = Engine::new(48000);
+
+	let mut block = MidiBlock::default();
+	block.add_note(0, note(0, 48, 60));
+	block.add_note(0, note(20, 4, 65));
+	// On the second step, swung onto the loop end
+	block.add_note(0, note(24, 4, 62));
+
+	let data = engine.add_resource(block);
+	let clip = engine.add_node(MidiClip::new(data), "chordial.midi_clip");
+
+	engine.set_node_param(clip, 0, ParamValue::Int(30));
+	engine.set_node_param(clip, 1, ParamValue::Int(2));
+	engine.set_node_param(clip, 4, ParamValue::Float(0.25));
+
+	let loop_frames = engine.config.tl_units_to_frames(TlUnit(30));
+	let events = render_events(&mut engine, clip, loop_frames * 2 + BLOCK);
+
+	assert!(events.iter().all(|(_, note, _)| *note != 62), "{events:?}");
+
+	// Every note that starts also ends, cut at the loop end
+	for note in [60, 65] {
+		let note_events = events.iter().filter(|e| e.1 == note).map(|e| e.2).collect::<Vec<_>>();
+
+		assert_eq!(note_events, [true, false, true, false], "note {note}");
+	}
+
+	let off_60 = events.iter().find(|e| e.1 == 60 && !e.2).unwrap();
+
+	assert_eq!(off_60.0, loop_frames);
+
+	let notes = engine.get_node(clip).unwrap().node.get_midi_notes(TlUnit(0)).unwrap();
+	let notes = notes.iter().map(|(_, n)| (n.pos.0, n.len.0, n.note)).collect::<Vec<_>>();
+
+	assert_eq!(notes, [(0, 30, 60), (20, 4, 65), (30, 30, 60), (50, 4, 65)]);
+}
+
+#[test]
+fn muting_a_track_releases_held_notes() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let mut block = MidiBlock::default();
+	block.add_note(0, note(0, 48, 60));
+	block.add_note(0, note(24, 12, 64));
+
+	let data = engine.add_resource(block);
+	let clip = engine.add_node(MidiClip::new(data), "chordial.midi_clip");
+	let track = engine.add_track("lead", BusKind::Midi);
+	let input = engine.get_track(track).unwrap().input_node;
+
+	engine.add_clip_to_track(track, clip);
+
+	let mut events = vec![];
+
+	for i in 0..60 {
+		// Muted after the first note started
+		if i == 1 {
+			engine.set_track_mute(track, true);
+		}
+
+		let output = engine.poll_node_output(&OutputRef { node: input, output: 0 }, BLOCK);
+
+		let Buffer::Midi(midi) = &*output else {
+			panic!()
+		};
+
+		for msg in midi.iter().flatten() {
+			events.push((msg.data()[1], msg.is_note_on()));
+		}
+
+		drop(output);
+		engine.render(&mut [Frame::ZERO; BLOCK]);
+	}
+
+	// The second note starts while muted, only its note off comes through
+	assert_eq!(events, [(60, true), (64, false), (60, false)]);
+}
+
+#[test]
+fn looping_back_releases_held_notes() {
+	let mut engine = Engine::new(48000);
+	engine.playing = true;
+
+	let mut block = MidiBlock::default();
+	block.add_note(0, note(0, 48, 60));
+
+	let data = engine.add_resource(block);
+	let clip = engine.add_node(MidiClip::new(data), "chordial.midi_clip");
+
+	// Loop back halfway through the note, at a block boundary
+	const LOOP_BLOCK: usize = 250;
+
+	engine.transport.set_loop(0..LOOP_BLOCK * 24);
+
+	let mut events = vec![];
+
+	for i in 0..50 {
+		let output = engine.poll_node_output(&OutputRef { node: clip, output: 0 }, LOOP_BLOCK);
+
+		let Buffer::Midi(midi) = &*output else {
+			panic!()
+		};
+
+		for (frame, chain) in midi.iter().enumerate() {
+			for msg in chain {
+				events.push((i * LOOP_BLOCK + frame, msg.data()[1], msg.is_note_on()));
+			}
+		}
+
+		drop(output);
+		engine.render(&mut [Frame::ZERO; LOOP_BLOCK]);
+	}
+
+	assert_eq!(events, [
+		(0, 60, true),
+		(LOOP_BLOCK * 24, 60, false),
+		(LOOP_BLOCK * 24, 60, true),
+		(LOOP_BLOCK * 48, 60, false),
+		(LOOP_BLOCK * 48, 60, true),
+	]);
+}
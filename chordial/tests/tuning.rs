@@ -0,0 +1,143 @@
+use chordial::{engine::Engine, resource::Resource, tuning::{KeyboardMapping, Scale, Tuning}};
+
+
+const JUST_SCL: &str = "! just.scl
+!
+5-limit just intonation
+ 12
+!
+ 16/15
+ 9/8
+ 6/5
+ 5/4
+ 4/3
+ 45/32
+ 3/2
+ 8/5
+ 5/3
+ 9/5
+ 15/8
+ 2/1
+";
+
+// Five keys per octave starting on C4, with C4 at 261.6 Hz
+const PENTATONIC_KBM: &str = "! pentatonic.kbm
+5
+0
+127
+60
+60
+261.6
+12
+! mapping
+0
+2
+4
+7
+x
+";
+
+fn assert_freq(tuning: &Tuning, note: u8, expected: f64) {
+	let freq = tuning.note_to_freq(note).unwrap();
+
+	assert!((freq - expected).abs() < 1e-6, "note {note}: {freq} != {expected}");
+}
+
+#[test]
+fn default_is_equal_temperament() {
+	let tuning = Tuning::default();
+
+	assert_freq(&tuning, 69, 440.0);
+	assert_freq(&tuning, 57, 220.0);
+	assert_freq(&tuning, 60, 440.0 * 2.0f64.powf(-9.0 / 12.0));
+	assert_freq(&tuning, 0, 440.0 * 2.0f64.powf(-69.0 / 12.0));
+	assert_freq(&tuning, 127, 440.0 * 2.0f64.powf(58.0 / 12.0));
+}
+
+#[test]
+fn scala_scale() {
+	let scale = Scale::parse(JUST_SCL).unwrap();
+
+	assert_eq!(scale.description, "5-limit just intonation");
+	assert_eq!(scale.degrees.len(), 12);
+	assert!((scale.degrees[6] - 701.955).abs() < 1e-3);
+	assert_eq!(scale.period(), 1200.0);
+
+	// Cents and integer ratios, with trailing comments
+	let scale = Scale::parse("\n3\n100.5 cents\n3 a ratio\n1200.0\n").unwrap();
+
+	assert_eq!(scale.description, "");
+	assert_eq!(scale.degrees[0], 100.5);
+	assert!((scale.degrees[1] - 1901.955).abs() < 1e-3);
+
+	assert_eq!(Scale::parse("broken\n3\n100.0\n"), None);
+	assert_eq!(Scale::parse("broken\n1\n-3/2\n"), None);
+}
+
+#[test]
+fn scale_without_mapping() {
+	let tuning = Tuning::from_scala(JUST_SCL, None).unwrap();
+
+	// The root sits on C4, tuned so A4 stays at 440 Hz
+	let c4 = 440.0 * 3.0 / 5.0;
+
+	assert_freq(&tuning, 69, 440.0);
+	assert_freq(&tuning, 60, c4);
+	assert_freq(&tuning, 67, c4 * 3.0 / 2.0);
+	assert_freq(&tuning, 72, c4 * 2.0);
+	assert_freq(&tuning, 55, c4 * 3.0 / 4.0);
+}
+
+#[test]
+fn keyboard_mapping() {
+	let mapping = KeyboardMapping::parse(PENTATONIC_KBM).unwrap();
+
+	assert_eq!(mapping.keys, [Some(0), Some(2), Some(4), Some(7), None]);
+	assert_eq!(mapping.reference_freq, 261.6);
+
+	let tuning = Tuning::new(Scale::equal(12), mapping);
+	let semitones = |n: f64| 261.6 * 2.0f64.powf(n / 12.0);
+
+	assert_freq(&tuning, 60, 261.6);
+	assert_freq(&tuning, 61, semitones(2.0));
+	assert_freq(&tuning, 63, semitones(7.0));
+	assert_eq!(tuning.note_to_freq(64), None);
+
+	// The mapping repeats every five keys, an octave up
+	assert_freq(&tuning, 65, semitones(12.0));
+	assert_freq(&tuning, 58, semitones(-5.0));
+	assert_eq!(tuning.note_to_freq(59), None);
+}
+
+#[test]
+fn note_range() {
+	let kbm = "0\n60\n72\n60\n69\n440.0\n0\n";
+	let tuning = Tuning::from_scala(JUST_SCL, Some(kbm)).unwrap();
+
+	assert_eq!(tuning.note_to_freq(59), None);
+	assert_eq!(tuning.note_to_freq(73), None);
+	assert_freq(&tuning, 69, 440.0);
+}
+
+#[test]
+fn save_and_load() {
+	let tuning = Tuning::from_scala(JUST_SCL, Some(PENTATONIC_KBM)).unwrap();
+
+	let mut loaded = Tuning::default();
+	loaded.load(&tuning.save());
+
+	assert_eq!(loaded, tuning);
+}
+
+#[test]
+fn node_tuning_overrides_default() {
+	let mut engine = Engine::new(48000);
+	let just = Tuning::from_scala(JUST_SCL, None).unwrap();
+
+	assert_eq!(engine.config.note_to_freq(69, None), Some(440.0));
+
+	engine.config.tuning = Tuning::equal_temperament(432.0);
+
+	assert_eq!(engine.config.note_to_freq(69, None), Some(432.0));
+	assert_eq!(engine.config.note_to_freq(69, Some(&just)), Some(440.0));
+}